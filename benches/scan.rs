@@ -0,0 +1,50 @@
+// Full scans following leaf sibling links versus climbing back through the branches.
+// Run with `cargo bench --bench scan`.
+use rbolt::db::{Db, ReadTxn};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const KEYS: u32 = 200_000;
+const ROUNDS: u32 = 5;
+
+fn scan(rtxn: &ReadTxn<'_>, use_links: bool) -> (u64, Duration) {
+    let started = Instant::now();
+    let mut cursor = rtxn.cursor();
+    cursor.use_sibling_links(use_links);
+    let mut count = 0;
+    let mut entry = cursor.first().unwrap();
+    while entry.is_some() {
+        count += 1;
+        entry = cursor.move_next().unwrap();
+    }
+    (count, started.elapsed())
+}
+
+fn main() {
+    let db_path = Path::new("bench_scan.rdb");
+    if db_path.exists() {
+        std::fs::remove_file(db_path).unwrap();
+    }
+    let db = Db::open(db_path).unwrap();
+    let entries = (0..KEYS).map(|i| (format!("key_{:08}", i).into_bytes(), format!("value_{}", i).into_bytes()));
+    db.bulk_load(entries, 1.0).unwrap();
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    println!("Scanning {} keys, {}", KEYS, rtxn.stats().unwrap());
+    for (label, use_links) in [("sibling links", true), ("branch re-descent", false)] {
+        // warm the page cache before timing
+        scan(&rtxn, use_links);
+        let mut best = Duration::MAX;
+        for _ in 0..ROUNDS {
+            let (count, elapsed) = scan(&rtxn, use_links);
+            assert_eq!(count, KEYS as u64);
+            best = best.min(elapsed);
+        }
+        let per_second = KEYS as f64 / best.as_secs_f64();
+        println!("   {:<18} {:>10.2?}  {:>12.0} keys/s", label, best, per_second);
+    }
+
+    drop(rtxn);
+    drop(db);
+    std::fs::remove_file(db_path).unwrap();
+}
@@ -0,0 +1,117 @@
+// Coalesces writes from many threads into one transaction, like bbolt's Batch.
+// The first caller to find the queue empty becomes the leader: it waits until the
+// queue is full or the delay runs out, then runs every queued call in a single
+// WriteTxn and commits once. Everyone else blocks on a channel for their outcome.
+use crate::btree::{BTreeError, WriteTxn};
+use crate::db::Db;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 1000;
+pub const DEFAULT_MAX_BATCH_DELAY: Duration = Duration::from_millis(10);
+
+type Result<T> = std::result::Result<T, BTreeError>;
+
+pub(crate) type BatchFn = Arc<dyn Fn(&mut WriteTxn<'_>) -> Result<()> + Send + Sync>;
+
+enum Outcome {
+    Committed,
+    // the call failed or the batch couldn't commit, so it is run again on its own
+    TrySolo,
+}
+
+struct Call {
+    func: BatchFn,
+    done: Sender<Outcome>,
+}
+
+pub(crate) struct Batcher {
+    pending: Mutex<Vec<Call>>,
+    full: Condvar,
+    max_size: usize,
+    max_delay: Duration,
+}
+
+impl Batcher {
+    pub(crate) fn new(max_size: usize, max_delay: Duration) -> Self {
+        Batcher {
+            pending: Mutex::new(Vec::new()),
+            full: Condvar::new(),
+            max_size: max_size.max(1),
+            max_delay,
+        }
+    }
+
+    pub(crate) fn submit(&self, db: &Db, func: BatchFn) -> Result<()> {
+        let (done, outcome) = mpsc::channel();
+        let leader = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.push(Call { func: func.clone(), done });
+            if pending.len() >= self.max_size {
+                self.full.notify_one();
+            }
+            pending.len() == 1
+        };
+
+        if leader {
+            let calls = self.wait_for_batch();
+            run_batch(db, calls);
+        }
+
+        match outcome.recv() {
+            Ok(Outcome::Committed) => Ok(()),
+            // a closed channel means the leader panicked while running the batch
+            Ok(Outcome::TrySolo) | Err(_) => run_solo(db, &func),
+        }
+    }
+
+    fn wait_for_batch(&self) -> Vec<Call> {
+        let deadline = Instant::now() + self.max_delay;
+        let mut pending = self.pending.lock().unwrap();
+        while pending.len() < self.max_size {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            pending = self.full.wait_timeout(pending, deadline - now).unwrap().0;
+        }
+        // the next caller to queue starts a new batch
+        std::mem::take(&mut *pending)
+    }
+}
+
+fn run_batch(db: &Db, mut calls: Vec<Call>) {
+    while !calls.is_empty() {
+        let Ok(mut wtxn) = db.begin_write_transaction() else {
+            break;
+        };
+
+        if let Some(index) = calls.iter().position(|call| (call.func)(&mut wtxn).is_err()) {
+            // dropping the transaction discards the writes of the whole attempt
+            drop(wtxn);
+            let failed = calls.remove(index);
+            let _ = failed.done.send(Outcome::TrySolo);
+            continue;
+        }
+
+        if wtxn.commit().is_err() {
+            break;
+        }
+        for call in calls.drain(..) {
+            let _ = call.done.send(Outcome::Committed);
+        }
+    }
+
+    // whatever is left couldn't be committed together
+    for call in calls {
+        let _ = call.done.send(Outcome::TrySolo);
+    }
+}
+
+fn run_solo(db: &Db, func: &BatchFn) -> Result<()> {
+    let mut wtxn = db.begin_write_transaction()?;
+    func(&mut wtxn)?;
+    wtxn.commit()?;
+    Ok(())
+}
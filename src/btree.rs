@@ -1,10 +1,18 @@
-use crate::db::{DbError, PAGE_SIZE};
-use crate::page::{BRANCH_ELEMENT_SIZE, BranchElement, LEAF_ELEMENT_SIZE, LeafElement, PAGE_BODY_SIZE, PAGE_HEADER_SIZE, Page, PageType};
+use crate::comparator::Comparator;
+use crate::compression::{self, Compression};
+use crate::db::{Db, DbError, Header};
+use crate::freelist;
+use crate::merge::{MergeError, MergeOperator};
+use crate::page::{self, BRANCH_ELEMENT_SIZE, BranchElement, DecodedPages, LEAF_ELEMENT_SIZE, LeafElement, PAGE_HEADER_SIZE, Page, PageType, VerifiedPages};
 use crate::search;
-use std::collections::HashMap;
+use crate::storage::Storage;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
 use std::sync::{RwLockReadGuard, MutexGuard};
 use std::fmt;
-use memmap2::MmapMut;
+use std::ops::{Bound, RangeBounds, RangeFull};
 use zerocopy::{FromBytes, IntoBytes};
 
 #[derive(Debug)]
@@ -15,6 +23,11 @@ pub enum BTreeError {
     KeyTooLarge { key_size: usize, max_size: usize },
     ValueTooLarge { value_size: usize, max_size: usize },
     PageFull { page_id: u64 },
+    OutOfOrder { index: u64 },
+    CountOverflow { page_id: u64, count: u64 },
+    ForeignSavepoint,
+    NoMergeOperator,
+    Merge(MergeError),
     Db(DbError),
 }
 
@@ -39,6 +52,19 @@ impl fmt::Display for BTreeError {
             BTreeError::PageFull { page_id } => {
                 write!(f, "Page {} is full", page_id)
             }
+            BTreeError::OutOfOrder { index } => {
+                write!(f, "Entry {} does not sort after the previous key", index)
+            }
+            BTreeError::CountOverflow { page_id, count } => {
+                write!(f, "Child of branch page {} holds {} keys, more than its count can hold", page_id, count)
+            }
+            BTreeError::ForeignSavepoint => {
+                write!(f, "Savepoint belongs to a different write transaction")
+            }
+            BTreeError::NoMergeOperator => {
+                write!(f, "No merge operator was registered in DbOptions")
+            }
+            BTreeError::Merge(err) => write!(f, "{}", err),
             BTreeError::Db(err) => write!(f, "{}", err),
         }
     }
@@ -46,6 +72,12 @@ impl fmt::Display for BTreeError {
 
 impl std::error::Error for BTreeError {}
 
+impl From<MergeError> for BTreeError {
+    fn from(err: MergeError) -> Self {
+        BTreeError::Merge(err)
+    }
+}
+
 impl From<DbError> for BTreeError {
     fn from(err: DbError) -> Self {
         BTreeError::Db(err)
@@ -54,60 +86,443 @@ impl From<DbError> for BTreeError {
 
 type Result<T> = std::result::Result<T, BTreeError>;
 
+pub const MIN_FILL_PERCENT: f64 = 0.1;
+pub const MAX_FILL_PERCENT: f64 = 1.0;
+pub const DEFAULT_FILL_PERCENT: f64 = 0.5;
+
+/// Returned by [`WriteTxn::compare_and_swap`] when the current value isn't the expected one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompareAndSwapError {
+    pub current: Option<Vec<u8>>,
+    pub proposed: Option<Vec<u8>>,
+}
+
+impl fmt::Display for CompareAndSwapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.current {
+            Some(current) => write!(f, "Compare and swap failed, current value has {} bytes", current.len()),
+            None => write!(f, "Compare and swap failed, key is absent"),
+        }
+    }
+}
+
+impl std::error::Error for CompareAndSwapError {}
+
+// What an update descent does with the key once it reaches the leaf
+enum Update<'v> {
+    Keep,
+    Put(Cow<'v, [u8]>),
+    Delete,
+}
+
+// What an update descent did to the subtree it went into
+enum Updated {
+    // the keys under it are the ones that were there before, so no count changes
+    KeysUnchanged,
+    KeysChanged,
+    // the page split, and the new right page goes into the parent under this separator
+    Split(Vec<u8>, u64),
+}
+
+// Tells the savepoints of different transactions apart
+static NEXT_TXN_ID: AtomicU64 = AtomicU64::new(1);
+
+/// State of a [`WriteTxn`] captured by [`WriteTxn::savepoint`]. Rolling back to it
+/// discards everything the transaction did afterwards, and can be done more than once.
+pub struct Savepoint {
+    txn_id: u64,
+    root_page_id: u64,
+    dirty_pages: HashMap<u64, Vec<u8>>,
+    free_list: Vec<u64>,
+    highest_page_id: u64,
+}
+
 pub struct WriteTxn<'a> {
     // So the write guard is when we're actually writing (_write_guard)
-    // Most of the time we only need the read lock (mmap_guard), so don't want to block others.
+    // Most of the time we only need the read lock (storage), so don't want to block others.
     _write_guard: MutexGuard<'a, ()>,
-    mmap_guard: RwLockReadGuard<'a, MmapMut>,
+    storage: RwLockReadGuard<'a, Box<dyn Storage>>,
+    db: &'a Db,
     root_page_id: u64,
     dirty_pages: HashMap<u64, Vec<u8>>,
     free_list: Vec<u64>,
     highest_page_id: u64,
+    comparator: &'a dyn Comparator,
+    merge_operator: Option<&'a dyn MergeOperator>,
+    fill_percent: f64,
+    txn_id: u64,
+    // branch elements carry their child's key count
+    counted: bool,
+    page_size: usize,
+    // the page size less the room encryption needs, which pages are built to
+    usable_size: usize,
+    // leaves are built this long and packed into runs of pages on commit when they
+    // are longer than a page or compress, see compression.rs
+    leaf_size: usize,
+    compression: Compression,
+    // pages as of the last commit, whose runs are freed when they are rewritten or freed
+    committed_highest_page_id: u64,
+    committed_free: HashSet<u64>,
+    verified: &'a VerifiedPages,
+    decoded: DecodedPages<'a>,
 }
 
 impl<'a> WriteTxn<'a> {
-    pub fn new(
+    // Starts from the tree `header` names and reads the free list as of the last
+    // commit, checking and decoding its pages, decrypted if the database is encrypted,
+    // through `db`'s caches.
+    pub(crate) fn new(
+        db: &'a Db,
         write_guard: MutexGuard<'a, ()>,
-        mmap_guard: RwLockReadGuard<'a, MmapMut>,
-        root_page_id: u64,
-        free_list: Vec<u64>,
-        highest_page_id: u64,
-    ) -> Self {
-        WriteTxn {
+        storage: RwLockReadGuard<'a, Box<dyn Storage>>,
+        header: &Header,
+    ) -> std::result::Result<Self, DbError> {
+        let verified = db.verified();
+        let decoded = db.decoded();
+        let free_list = freelist::read(storage.bytes(), header.page_size(), header.highest_page_id(), verified, decoded.cipher())?;
+        Ok(WriteTxn {
             _write_guard: write_guard,
-            mmap_guard,
-            root_page_id,
+            storage,
+            db,
+            root_page_id: header.root_page_id(),
             dirty_pages: HashMap::new(),
+            highest_page_id: header.highest_page_id(),
+            comparator: db.comparator(),
+            merge_operator: db.merge_operator(),
+            fill_percent: DEFAULT_FILL_PERCENT,
+            txn_id: NEXT_TXN_ID.fetch_add(1, AtomicOrdering::Relaxed),
+            // set from the database header, the tree either keeps counts everywhere or nowhere
+            counted: header.order_statistics(),
+            page_size: header.page_size(),
+            usable_size: header.usable_size(),
+            leaf_size: header.leaf_size(),
+            compression: header.compression(),
+            committed_highest_page_id: header.highest_page_id(),
+            committed_free: free_list.iter().copied().collect(),
             free_list,
-            highest_page_id,
-        }
+            verified,
+            decoded,
+        })
     }
 }
 
 impl WriteTxn<'_> {
+    /// How full the left page is packed when a page splits, as a fraction of the page body.
+    pub fn fill_percent(&self) -> f64 {
+        self.fill_percent
+    }
+
+    /// Overrides the fill percent for the rest of this transaction, clamped to
+    /// [`MIN_FILL_PERCENT`]..=[`MAX_FILL_PERCENT`].
+    pub fn set_fill_percent(&mut self, fill_percent: f64) {
+        self.fill_percent = fill_percent.clamp(MIN_FILL_PERCENT, MAX_FILL_PERCENT);
+    }
+
+    /// The largest key this database takes. Keys are copied into branch pages as
+    /// separators, so this is the most that lets a branch page hold its first child
+    /// and two separators, which is what a branch split needs.
+    pub fn max_key_size(&self) -> usize {
+        ((self.body_size() - BRANCH_ELEMENT_SIZE) / 2 - BRANCH_ELEMENT_SIZE).min(self.leaf_body_size() - LEAF_ELEMENT_SIZE)
+    }
+
     pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
-        match self.insert_recursive(self.root_page_id, key, value)? {
-            Some((separator_key, new_page_id)) => self.split_root(separator_key, new_page_id),
-            None => Ok(()),
+        self.update(key, |_| Ok(Update::Put(Cow::Borrowed(value))))
+    }
+
+    /// Writes `new` (or deletes the key when it is None) only if the current value is
+    /// `expected`, where None means absent. On a mismatch nothing changes and the
+    /// current value is handed back.
+    pub fn compare_and_swap(
+        &mut self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<std::result::Result<(), CompareAndSwapError>> {
+        let mut mismatch = None;
+        self.update(key, |current| {
+            if current != expected {
+                mismatch = Some(CompareAndSwapError {
+                    current: current.map(<[u8]>::to_vec),
+                    proposed: new.map(<[u8]>::to_vec),
+                });
+                return Ok(Update::Keep);
+            }
+            Ok(match new {
+                Some(value) => Update::Put(Cow::Borrowed(value)),
+                None => Update::Delete,
+            })
+        })?;
+        Ok(mismatch.map_or(Ok(()), Err))
+    }
+
+    /// Combines the current value of `key` with `operand` using the tree's merge operator.
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) -> Result<()> {
+        let operator = self.merge_operator.ok_or(BTreeError::NoMergeOperator)?;
+        self.update(key, |current| {
+            Ok(match operator.merge(key, current, operand)? {
+                Some(value) => Update::Put(Cow::Owned(value)),
+                None => Update::Delete,
+            })
+        })
+    }
+
+    /// Inserts `value` unless `key` is already present, returning whether it was inserted.
+    pub fn insert_if_absent(&mut self, key: &[u8], value: &[u8]) -> Result<bool> {
+        let mut inserted = false;
+        self.update(key, |current| {
+            if current.is_some() {
+                return Ok(Update::Keep);
+            }
+            inserted = true;
+            Ok(Update::Put(Cow::Borrowed(value)))
+        })?;
+        Ok(inserted)
+    }
+
+    /// Inserts `value`, returning the value it replaced.
+    pub fn get_and_replace(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
+        let mut previous = None;
+        self.update(key, |current| {
+            previous = current.map(<[u8]>::to_vec);
+            Ok(Update::Put(Cow::Borrowed(value)))
+        })?;
+        Ok(previous)
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let leaf_id = self.find_leaf(key)?;
+        let (page_header, page_body) = self.get_page_immut(leaf_id)?;
+        let (index, found) = search::search_leaf_elements(page_body, page_header.count.get() as usize, key, self.comparator)
+            .map_err(|_| BTreeError::CorruptPageType { page_id: leaf_id, raw_type: page_header.page_type })?;
+        if !found {
+            return Ok(None);
         }
+        let (_, value) = page::leaf_entry(page_body, index)
+            .ok_or(BTreeError::CorruptPageType { page_id: leaf_id, raw_type: page_header.page_type })?;
+        Ok(Some(value.to_vec()))
     }
 
+    /// Removes `key`, returning whether it was present.
+    /// The key and value bytes stay in the page until the leaf is next rewritten by a split.
+    pub fn delete(&mut self, key: &[u8]) -> Result<bool> {
+        let mut found = false;
+        self.update(key, |current| {
+            found = current.is_some();
+            Ok(Update::Delete)
+        })?;
+        Ok(found)
+    }
+
+    /// Removes every key inside `range`, returning how many were removed.
+    /// Subtrees that lie entirely inside the range are freed page by page without
+    /// looking at their elements; only the leaves at the edges of the range are rewritten.
+    pub fn delete_range<'k, R: RangeBounds<&'k [u8]>>(&mut self, range: R) -> Result<u64> {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        // the leaves the range starts and ends in; every leaf between them is freed
+        let first_leaf = match range.0 {
+            Bound::Included(key) | Bound::Excluded(key) => self.find_leaf(key)?,
+            Bound::Unbounded => self.edge_leaf(false)?,
+        };
+        let last_leaf = match range.1 {
+            Bound::Included(key) | Bound::Excluded(key) => self.find_leaf(key)?,
+            Bound::Unbounded => self.edge_leaf(true)?,
+        };
+        let (before_range, _) = self.leaf_links(first_leaf)?;
+        let (_, after_range) = self.leaf_links(last_leaf)?;
+        let freed_from = self.free_list.len();
+
+        let root_page_id = self.root_page_id;
+        let (removed, empty) = self.delete_range_recursive(root_page_id, &range)?;
+        if empty && self.get_page_type(root_page_id)? == PageType::Branch {
+            self.write_leaf_page(root_page_id, &[], 0, 0)?;
+        } else if !empty {
+            let freed = &self.free_list[freed_from..];
+            let left = if freed.contains(&first_leaf) { before_range } else { first_leaf };
+            let right = if freed.contains(&last_leaf) { after_range } else { last_leaf };
+            if left != right {
+                self.link_leaves(left, right)?;
+            }
+        }
+        self.collapse_root()?;
+        Ok(removed)
+    }
+
+    /// Removes every key, returning how many there were.
+    pub fn clear(&mut self) -> Result<u64> {
+        self.delete_range::<RangeFull>(..)
+    }
+
+    /// Loads entries that are strictly increasing under the tree's comparator.
+    /// An empty tree is built bottom-up, packing every leaf and branch page to
+    /// `fill_percent` of its body. If the tree already has keys the entries must
+    /// sort after its last key, and are inserted one by one.
+    /// Returns the number of entries loaded. On error the transaction holds a
+    /// partial load and should be dropped rather than committed.
+    pub fn append_sorted<I, K, V>(&mut self, entries: I, fill_percent: f64) -> Result<u64>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let fill_percent = fill_percent.clamp(MIN_FILL_PERCENT, MAX_FILL_PERCENT);
+        let body_size = self.leaf_body_size();
+        let limit = (body_size as f64 * fill_percent) as usize;
+        let comparator = self.comparator;
+        let bottom_up = self.is_empty_leaf(self.root_page_id)?;
+        let mut previous = if bottom_up { None } else { self.last_key(self.root_page_id)? };
+
+        let mut loaded = 0u64;
+        let mut leaves: Vec<(Vec<u8>, u64)> = Vec::new();
+        let mut batch: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+        let mut batch_size = 0;
+
+        for (key, value) in entries {
+            let (key, value) = (key.as_ref(), value.as_ref());
+            if previous.as_deref().is_some_and(|previous| comparator.compare(previous, key) != Ordering::Less) {
+                return Err(BTreeError::OutOfOrder { index: loaded });
+            }
+
+            if !bottom_up {
+                self.insert(key, value)?;
+            } else {
+                if key.len() > self.max_key_size() {
+                    return Err(BTreeError::KeyTooLarge { key_size: key.len(), max_size: self.max_key_size() });
+                }
+                let size = LEAF_ELEMENT_SIZE + key.len() + value.len();
+                if size > body_size {
+                    return Err(BTreeError::ValueTooLarge {
+                        value_size: value.len(),
+                        max_size: body_size.saturating_sub(LEAF_ELEMENT_SIZE + key.len()),
+                    });
+                }
+                if !batch.is_empty() && batch_size + size > limit {
+                    self.flush_sorted_leaf(&mut batch, &mut leaves)?;
+                    batch_size = 0;
+                }
+                batch.push((key.to_vec(), value.to_vec()));
+                batch_size += size;
+            }
+            previous = Some(key.to_vec());
+            loaded += 1;
+        }
+
+        if bottom_up && !batch.is_empty() {
+            self.flush_sorted_leaf(&mut batch, &mut leaves)?;
+            let branch_limit = (self.body_size() as f64 * fill_percent) as usize;
+            self.root_page_id = self.build_branch_levels(leaves, branch_limit)?;
+        }
+        Ok(loaded)
+    }
+
+    /// Captures the transaction so far. Copies the pages it has dirtied.
+    pub fn savepoint(&self) -> Savepoint {
+        Savepoint {
+            txn_id: self.txn_id,
+            root_page_id: self.root_page_id,
+            dirty_pages: self.dirty_pages.clone(),
+            free_list: self.free_list.clone(),
+            highest_page_id: self.highest_page_id,
+        }
+    }
+
+    /// Undoes every change made since `savepoint` was taken, keeping the ones before it.
+    pub fn rollback_to(&mut self, savepoint: &Savepoint) -> Result<()> {
+        if savepoint.txn_id != self.txn_id {
+            return Err(BTreeError::ForeignSavepoint);
+        }
+        self.restore(savepoint);
+        Ok(())
+    }
+
+    /// Runs `func` as a nested transaction: if it fails, its changes are rolled
+    /// back and the error is returned, leaving the rest of the transaction intact.
+    pub fn nested<T, E, F>(&mut self, func: F) -> std::result::Result<T, E>
+    where
+        F: FnOnce(&mut Self) -> std::result::Result<T, E>,
+    {
+        let savepoint = self.savepoint();
+        let result = func(self);
+        if result.is_err() {
+            self.restore(&savepoint);
+        }
+        result
+    }
+
+    fn restore(&mut self, savepoint: &Savepoint) {
+        self.root_page_id = savepoint.root_page_id;
+        self.dirty_pages = savepoint.dirty_pages.clone();
+        self.free_list = savepoint.free_list.clone();
+        self.highest_page_id = savepoint.highest_page_id;
+    }
+
+    /// Commits the transaction. The write lock is held until the commit has landed, so the
+    /// next write transaction starts from this one's tree and free list.
+    pub fn commit(mut self) -> std::result::Result<(), DbError> {
+        let (dirty_pages, highest_page_id, root_page_id) = self.take_pages();
+        let WriteTxn { _write_guard, storage, db, .. } = self;
+        // the commit needs the storage lock for writing
+        drop(storage);
+        db.commit(dirty_pages, highest_page_id, root_page_id)
+    }
+
+    /// Ends the transaction and hands back its pages for [`Db::commit`]. The write lock is
+    /// released here, before they are written, so prefer [`commit`](Self::commit).
     pub fn prepare_commit(mut self) -> (HashMap<u64, Vec<u8>>, u64, u64) {
+        self.take_pages()
+    }
+
+    fn take_pages(&mut self) -> (HashMap<u64, Vec<u8>>, u64, u64) {
+        if self.leaf_size > self.usable_size {
+            self.pack_leaves();
+        }
+        for (page_id, page_bytes) in freelist::write(&mut self.free_list, self.usable_size) {
+            self.dirty_pages.insert(page_id, page_bytes);
+        }
         let dirty_pages = std::mem::take(&mut self.dirty_pages);
         let highest_page_id = self.highest_page_id;
         let root_page_id = self.root_page_id;
         (dirty_pages, highest_page_id, root_page_id)
     }
 
-    fn insert_recursive(&mut self, page_id: u64, key: &[u8], value: &[u8]) -> Result<Option<(Vec<u8>, u64)>> {
+    // One root-to-leaf descent: `decide` sees the current value of `key` and picks
+    // what to store, splitting pages on the way back up if the leaf overflows.
+    fn update<'v, F>(&mut self, key: &[u8], decide: F) -> Result<()>
+    where
+        F: FnOnce(Option<&[u8]>) -> Result<Update<'v>>,
+    {
+        match self.update_recursive(self.root_page_id, key, decide)? {
+            Updated::Split(separator_key, new_page_id) => self.split_root(separator_key, new_page_id),
+            Updated::KeysUnchanged | Updated::KeysChanged => Ok(()),
+        }
+    }
+
+    fn update_recursive<'v, F>(&mut self, page_id: u64, key: &[u8], decide: F) -> Result<Updated>
+    where
+        F: FnOnce(Option<&[u8]>) -> Result<Update<'v>>,
+    {
         let page_type = self.get_page_type(page_id)?;
         match page_type {
-            PageType::Leaf => self.insert_into_leaf(page_id, key, value),
+            PageType::Leaf => self.update_leaf(page_id, key, decide),
             PageType::Branch => {
                 let child_page_id = self.find_child_page(page_id, key)?;
-                match self.insert_recursive(child_page_id, key, value)? {
-                    Some((sep_key, new_child_id)) => self.insert_into_branch(page_id, sep_key, new_child_id),
-                    None => Ok(None),
+                match self.update_recursive(child_page_id, key, decide)? {
+                    Updated::Split(sep_key, new_child_id) => {
+                        let split = self.insert_into_branch(page_id, sep_key, new_child_id)?;
+                        self.refresh_counts(page_id)?;
+                        match split {
+                            Some((separator, new_page_id)) => {
+                                self.refresh_counts(new_page_id)?;
+                                Ok(Updated::Split(separator, new_page_id))
+                            }
+                            None => Ok(Updated::KeysChanged),
+                        }
+                    }
+                    Updated::KeysChanged => {
+                        self.refresh_child_count(page_id, child_page_id)?;
+                        Ok(Updated::KeysChanged)
+                    }
+                    // leaves the branch as it is, not even copied into the dirty pages
+                    Updated::KeysUnchanged => Ok(Updated::KeysUnchanged),
                 }
             }
             _ => Err(BTreeError::InvalidPageType {
@@ -117,15 +532,196 @@ impl WriteTxn<'_> {
         }
     }
 
-    fn find_child_page(&mut self, page_id: u64, for_key: &[u8]) -> Result<u64> {
-        let page_bytes = self.read_page(page_id)?;
+    fn update_leaf<'v, F>(&mut self, page_id: u64, key: &[u8], decide: F) -> Result<Updated>
+    where
+        F: FnOnce(Option<&[u8]>) -> Result<Update<'v>>,
+    {
+        let (page_header, page_body) = self.get_page_immut(page_id)?;
+        let (index, found) = search::search_leaf_elements(page_body, page_header.count.get() as usize, key, self.comparator)
+            .map_err(|_| BTreeError::CorruptPageType { page_id, raw_type: page_header.page_type })?;
+        let current = match found {
+            true => Some(page::leaf_entry(page_body, index)
+                .ok_or(BTreeError::CorruptPageType { page_id, raw_type: page_header.page_type })?.1),
+            false => None,
+        };
+
+        let current_len = current.map(<[u8]>::len);
+        match decide(current)? {
+            Update::Keep => Ok(Updated::KeysUnchanged),
+            // same size values, like counters, are overwritten where they are
+            Update::Put(value) if current_len == Some(value.len()) => {
+                self.overwrite_value(page_id, index, &value)?;
+                Ok(Updated::KeysUnchanged)
+            }
+            Update::Put(value) => match self.insert_into_leaf(page_id, key, &value)? {
+                Some((separator, new_page_id)) => Ok(Updated::Split(separator, new_page_id)),
+                None => Ok(Updated::KeysChanged),
+            },
+            Update::Delete if found => {
+                self.remove_from_leaf(page_id, index)?;
+                Ok(Updated::KeysChanged)
+            }
+            Update::Delete => Ok(Updated::KeysUnchanged),
+        }
+    }
+
+    // Returns the keys removed under `page_id` and whether the page is left with no keys.
+    fn delete_range_recursive(&mut self, page_id: u64, range: &KeyRange<'_>) -> Result<(u64, bool)> {
+        let comparator = self.comparator;
+        match self.get_page_type(page_id)? {
+            PageType::Leaf => {
+                let (page_header, page_body) = self.get_page_immut(page_id)?;
+                let count = page_header.count.get() as usize;
+                let mut kept = Vec::with_capacity(count);
+                for index in 0..count {
+                    let (key, value) = page::leaf_entry(page_body, index)
+                        .ok_or(BTreeError::CorruptPageType { page_id, raw_type: page_header.page_type })?;
+                    if !range_contains(comparator, range, key) {
+                        kept.push((key.to_vec(), value.to_vec()));
+                    }
+                }
+                let removed = (count - kept.len()) as u64;
+                if removed > 0 {
+                    let (prev, next) = (page_header.prev.get(), page_header.next.get());
+                    self.write_leaf_page(page_id, &kept, prev, next)?;
+                }
+                Ok((removed, kept.is_empty()))
+            }
+            PageType::Branch => {
+                let entries = self.read_branch_entries(page_id)?;
+                let child_count = entries.len();
+                let first = match range.0 {
+                    Bound::Included(key) | Bound::Excluded(key) => child_index(comparator, &entries, key),
+                    Bound::Unbounded => 0,
+                };
+                let last = match range.1 {
+                    Bound::Included(key) | Bound::Excluded(key) => child_index(comparator, &entries, key),
+                    Bound::Unbounded => child_count - 1,
+                };
+
+                let mut removed = 0;
+                let mut kept = Vec::with_capacity(child_count);
+                for (index, (key, child_id)) in entries.into_iter().enumerate() {
+                    if index < first || index > last {
+                        kept.push((key, child_id));
+                        continue;
+                    }
+                    // children strictly between the edges of the range hold nothing but keys inside it
+                    let covered = (index > first || range.0 == Bound::Unbounded)
+                        && (index < last || range.1 == Bound::Unbounded);
+                    if covered {
+                        removed += self.free_subtree(child_id)?;
+                        continue;
+                    }
+                    let (child_removed, child_empty) = self.delete_range_recursive(child_id, range)?;
+                    removed += child_removed;
+                    match child_empty {
+                        true => self.free_page(child_id),
+                        false => kept.push((key, child_id)),
+                    }
+                }
 
-        let (page_header, page_body) = Page::ref_from_prefix(&page_bytes)
-            .map_err(|_| BTreeError::CorruptPageType { page_id, raw_type: page_bytes[8] })?;
-        let element_count = page_header.count as usize;
+                if kept.is_empty() {
+                    return Ok((removed, true));
+                }
+                if kept.len() < child_count {
+                    // keys below the new first child's separator were all removed with its left siblings
+                    kept[0].0.clear();
+                    self.write_branch_page(page_id, &kept)?;
+                }
+                if removed > 0 {
+                    self.refresh_counts(page_id)?;
+                }
+                Ok((removed, false))
+            }
+            page_type => Err(BTreeError::InvalidPageType { page_id, page_type }),
+        }
+    }
 
-        let (result_index, found) = search::search_branch_elements(page_body, element_count, for_key)
-            .map_err(|_| BTreeError::CorruptPageType { page_id, raw_type: page_bytes[8] })?;
+    // Frees every page under `page_id`, returning the number of keys they held.
+    fn free_subtree(&mut self, page_id: u64) -> Result<u64> {
+        let removed = match self.get_page_type(page_id)? {
+            PageType::Leaf => self.get_page_immut(page_id)?.0.count.get() as u64,
+            PageType::Branch => {
+                let mut removed = 0;
+                for (_, child_id) in self.read_branch_entries(page_id)? {
+                    removed += self.free_subtree(child_id)?;
+                }
+                removed
+            }
+            page_type => return Err(BTreeError::InvalidPageType { page_id, page_type }),
+        };
+        self.free_page(page_id);
+        Ok(removed)
+    }
+
+    fn free_page(&mut self, page_id: u64) {
+        self.dirty_pages.remove(&page_id);
+        self.free_list.push(page_id);
+    }
+
+    // A root branch left with a single child is replaced by that child.
+    fn collapse_root(&mut self) -> Result<()> {
+        loop {
+            let root_page_id = self.root_page_id;
+            let (page_header, page_body) = self.get_page_immut(root_page_id)?;
+            if page_header.page_type != PageType::Branch as u8 || page_header.count.get() > 0 {
+                return Ok(());
+            }
+            let (_, child_id) = page::branch_entry(page_body, 0)
+                .ok_or(BTreeError::CorruptPageType { page_id: root_page_id, raw_type: page_header.page_type })?;
+            self.free_page(root_page_id);
+            self.root_page_id = child_id;
+        }
+    }
+
+    fn read_branch_entries(&self, page_id: u64) -> Result<Vec<(Vec<u8>, u64)>> {
+        let (page_header, page_body) = self.get_page_immut(page_id)?;
+        (0..=page_header.count.get() as usize)
+            .map(|index| {
+                page::branch_entry(page_body, index)
+                    .map(|(key, child_id)| (key.to_vec(), child_id))
+                    .ok_or(BTreeError::CorruptPageType { page_id, raw_type: page_header.page_type })
+            })
+            .collect()
+    }
+
+    fn overwrite_value(&mut self, page_id: u64, index: usize, value: &[u8]) -> Result<()> {
+        let (page_header, page_body) = self.get_page_mut(page_id)?;
+        let raw_type = page_header.page_type;
+        let elem = LeafElement::read_from_bytes(&page_body[index*LEAF_ELEMENT_SIZE..(index+1)*LEAF_ELEMENT_SIZE])
+            .map_err(|_| BTreeError::CorruptPageType { page_id, raw_type })?;
+        page_body[elem.vptr.get() as usize..elem.vptr.get() as usize + value.len()].copy_from_slice(value);
+        Ok(())
+    }
+
+    fn remove_from_leaf(&mut self, page_id: u64, index: usize) -> Result<()> {
+        let (page_header, page_body) = self.get_page_mut(page_id)?;
+        let count = page_header.count.get() as usize;
+        page_body.copy_within((index + 1) * LEAF_ELEMENT_SIZE..count * LEAF_ELEMENT_SIZE, index * LEAF_ELEMENT_SIZE);
+        page_body[(count - 1) * LEAF_ELEMENT_SIZE..count * LEAF_ELEMENT_SIZE].fill(0);
+        page_header.count.set((count - 1) as u16);
+        Ok(())
+    }
+
+    fn find_leaf(&self, key: &[u8]) -> Result<u64> {
+        let mut page_id = self.root_page_id;
+        for _ in 0..page::MAX_DEPTH {
+            match self.get_page_type(page_id)? {
+                PageType::Leaf => return Ok(page_id),
+                PageType::Branch => page_id = self.find_child_page(page_id, key)?,
+                page_type => return Err(BTreeError::InvalidPageType { page_id, page_type }),
+            }
+        }
+        Err(BTreeError::Db(DbError::PageFormat { page_id, index: None }))
+    }
+
+    fn find_child_page(&self, page_id: u64, for_key: &[u8]) -> Result<u64> {
+        let (page_header, page_body) = self.get_page_immut(page_id)?;
+        let element_count = page_header.count.get() as usize;
+
+        let (result_index, found) = search::search_branch_elements(page_body, element_count, for_key, self.comparator)
+            .map_err(|err| DbError::PageFormat { page_id, index: Some(err.index) })?;
 
         let child_index = if found {
             result_index
@@ -133,25 +729,56 @@ impl WriteTxn<'_> {
             result_index.saturating_sub(1)
         };
 
-        let elem_bytes = &page_body[(child_index*BRANCH_ELEMENT_SIZE)..(child_index+1)*BRANCH_ELEMENT_SIZE];
-        let elem = BranchElement::ref_from_bytes(elem_bytes)
-            .map_err(|_| BTreeError::CorruptPageType { page_id, raw_type: page_bytes[8] })?;
+        let (_, child_id) = page::branch_entry(page_body, child_index)
+            .ok_or(DbError::PageFormat { page_id, index: Some(child_index) })?;
 
-        Ok(elem.page_id)
+        Ok(child_id)
     }
 
     fn read_page(&self, page_id: u64) -> Result<&[u8]> {
         if let Some(page_bytes) = self.dirty_pages.get(&page_id) {
             return Ok(page_bytes);
         }
-        let offset = (page_id as usize) * PAGE_SIZE;
-        if offset + PAGE_SIZE > self.mmap_guard.len() {
-            return Err(BTreeError::Db(DbError::PageOutOfBounds {
-                page_id,
-                file_size: self.mmap_guard.len(),
-            }));
+        Ok(page::read_page(&**self.storage, page_id, self.page_size, self.verified, &self.decoded)?)
+    }
+
+    // Frees the runs of committed leaves this transaction rewrote or freed, then
+    // replaces every dirty leaf with the pages that store it.
+    fn pack_leaves(&mut self) {
+        let touched: BTreeSet<u64> = self.dirty_pages.keys()
+            .chain(self.free_list.iter())
+            .copied()
+            .filter(|page_id| *page_id <= self.committed_highest_page_id && !self.committed_free.contains(page_id))
+            .collect();
+        for page_id in touched {
+            let storage = &**self.storage;
+            let (page_size, verified) = (self.page_size, self.verified);
+            // runs are followed by their page headers, which are never encrypted
+            let stored_page = |page_id| page::stored_page(storage, page_id, page_size, verified).map(Cow::Borrowed);
+            match stored_page(page_id) {
+                Ok(head) if head[8] == PageType::Packed as u8 => {
+                    // if the run can't be read, leaving it off the free list costs space,
+                    // handing its pages out twice would cost data
+                    if let Ok(overflow_pages) = compression::overflow_pages(page_id, &head, &stored_page) {
+                        self.free_list.extend(overflow_pages);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut leaves: Vec<u64> = self.dirty_pages.iter()
+            .filter(|(_, page_bytes)| page_bytes[8] == PageType::Leaf as u8)
+            .map(|(&page_id, _)| page_id)
+            .collect();
+        leaves.sort_unstable();
+        for page_id in leaves {
+            let leaf = self.dirty_pages.remove(&page_id).unwrap();
+            let (compression, usable_size) = (self.compression, self.usable_size);
+            for (run_page_id, page_bytes) in compression::pack(page_id, &leaf, compression, usable_size, &mut || self.next_page_id()) {
+                self.dirty_pages.insert(run_page_id, page_bytes);
+            }
         }
-        Ok(&self.mmap_guard[offset..offset + PAGE_SIZE])
     }
 
     fn get_page_for_write(&mut self, page_id: u64) -> Result<&mut [u8]> {
@@ -165,60 +792,66 @@ impl WriteTxn<'_> {
     fn get_page_mut(&mut self, page_id: u64) -> Result<(&mut Page, &mut [u8])> {
         let page_bytes = self.get_page_for_write(page_id)?;
         let raw_type = page_bytes[8];
-        Page::mut_from_prefix(&mut *page_bytes)
-            .map_err(|_| BTreeError::CorruptPageType { page_id, raw_type })
+        match Page::mut_from_prefix(&mut *page_bytes) {
+            Ok((page, body)) if page::elements_fit(page, body.len()) => Ok((page, body)),
+            _ => Err(BTreeError::CorruptPageType { page_id, raw_type }),
+        }
     }
 
-    fn get_page_immut(&mut self, page_id: u64) -> Result<(&Page, &[u8])> {
+    fn get_page_immut(&self, page_id: u64) -> Result<(&Page, &[u8])> {
         let page_bytes = self.read_page(page_id)?;
         let raw_type = page_bytes[8];
-        Page::ref_from_prefix(page_bytes)
-            .map_err(|_| BTreeError::CorruptPageType { page_id, raw_type })
+        match Page::ref_from_prefix(page_bytes) {
+            Ok((page, body)) if page::elements_fit(page, body.len()) => Ok((page, body)),
+            _ => Err(BTreeError::CorruptPageType { page_id, raw_type }),
+        }
     }
 
     fn insert_into_leaf(&mut self, page_id: u64, key: &[u8], value: &[u8]) -> Result<Option<(Vec<u8>, u64)>> {
-        if key.len() > u16::MAX as usize {
-            return Err(BTreeError::KeyTooLarge { key_size: key.len(), max_size: u16::MAX as usize });
+        // an entry has to fit in an empty page, so the limits grow with the page size
+        let max_size = self.leaf_body_size() - LEAF_ELEMENT_SIZE;
+        if key.len() > self.max_key_size() {
+            return Err(BTreeError::KeyTooLarge { key_size: key.len(), max_size: self.max_key_size() });
         }
-        if value.len() > u16::MAX as usize {
-            return Err(BTreeError::ValueTooLarge { value_size: value.len(), max_size: u16::MAX as usize });
+        if key.len() + value.len() > max_size {
+            return Err(BTreeError::ValueTooLarge { value_size: value.len(), max_size: max_size - key.len() });
         }
 
+        let comparator = self.comparator;
         let (page_header, page_body) = self.get_page_mut(page_id)?;
-        let current_count = page_header.count as usize;
+        let current_count = page_header.count.get() as usize;
 
         // element ptrs are added forwards but the data block is at the end of the page backwards
         let min_kptr = if current_count == 0 {
-            PAGE_BODY_SIZE as usize
+            page_body.len()
         } else {
-            let mut min_kptr = PAGE_BODY_SIZE;
+            let mut min_kptr = page_body.len();
             for i in 0..current_count {
                 let elem = LeafElement::ref_from_bytes(&page_body[i*LEAF_ELEMENT_SIZE..(i+1)*LEAF_ELEMENT_SIZE])
                     .map_err(|_| BTreeError::CorruptPageType { page_id, raw_type: page_header.page_type })?;
-                min_kptr = min_kptr.min(elem.kptr as usize);
+                min_kptr = min_kptr.min(elem.kptr.get() as usize);
             }
             min_kptr
         };
 
         let new_elements_end = (current_count + 1) * LEAF_ELEMENT_SIZE;
-        let key_offset = min_kptr - (key.len() + value.len());
+        let key_offset = match min_kptr.checked_sub(key.len() + value.len()) {
+            Some(key_offset) if key_offset >= new_elements_end => key_offset,
+            _ => return self.split_leaf(page_id, key, value),
+        };
         let value_offset = key_offset + key.len();
 
-        if new_elements_end > key_offset {
-            return self.split_leaf(page_id, key, value);
-        }
-
-        let (insert_pos, found) = search::search_leaf_elements(page_body, current_count, key)
+        let (insert_pos, found) = search::search_leaf_elements(page_body, current_count, key, comparator)
             .map_err(|_| BTreeError::CorruptPageType { page_id, raw_type: page_header.page_type })?;
 
         page_body[key_offset..value_offset].copy_from_slice(key);
         page_body[value_offset..value_offset + value.len()].copy_from_slice(value);
 
         let leaf_element = LeafElement {
-            ksize: key.len() as u16,
-            vsize: value.len() as u16,
-            kptr: key_offset as u16,
-            vptr: value_offset as u16,
+            ksize: (key.len() as u16).into(),
+            vsize: (value.len() as u16).into(),
+            kptr: (key_offset as u16).into(),
+            vptr: (value_offset as u16).into(),
         };
 
         let elem_offset = insert_pos * LEAF_ELEMENT_SIZE;
@@ -241,7 +874,7 @@ impl WriteTxn<'_> {
         page_body[elem_offset..elem_offset + LEAF_ELEMENT_SIZE]
             .copy_from_slice(leaf_element.as_bytes());
 
-        page_header.count = (current_count + 1) as u16;
+        page_header.count.set((current_count + 1) as u16);
 
         println!("   [OK] Inserted key (len={}) value (len={}) into page {} at position {}, count now {}",
                  key.len(), value.len(), page_id, insert_pos, current_count + 1);
@@ -251,22 +884,31 @@ impl WriteTxn<'_> {
     fn split_leaf(&mut self, page_id: u64, new_key: &[u8], new_value: &[u8]) -> Result<Option<(Vec<u8>, u64)>> {
         println!("   [SPLIT] Splitting leaf page {}", page_id);
 
+        let comparator = self.comparator;
         let (page_header, page_body) = self.get_page_immut(page_id)?;
-        let count = page_header.count as usize;
+        let count = page_header.count.get() as usize;
 
         let mut kvs = Vec::with_capacity(count + 1);
         let mut inserted = false;
 
         for i in 0..count {
-            let elem = LeafElement::ref_from_bytes(&page_body[i*LEAF_ELEMENT_SIZE..(i+1)*LEAF_ELEMENT_SIZE])
-                .map_err(|_| BTreeError::CorruptPageType { page_id, raw_type: page_header.page_type })?;
-
-            let key = &page_body[elem.kptr as usize..(elem.kptr + elem.ksize) as usize];
-            let value = &page_body[elem.vptr as usize..(elem.vptr + elem.vsize) as usize];
-
-            if !inserted && new_key < key {
-                kvs.push((new_key.to_vec(), new_value.to_vec()));
-                inserted = true;
+            let (key, value) = page::leaf_entry(page_body, i)
+                .ok_or(DbError::PageFormat { page_id, index: Some(i) })?;
+
+            if !inserted {
+                match comparator.compare(new_key, key) {
+                    Ordering::Less => {
+                        kvs.push((new_key.to_vec(), new_value.to_vec()));
+                        inserted = true;
+                    }
+                    Ordering::Equal => {
+                        // overwrite of a key the comparator considers equal
+                        kvs.push((new_key.to_vec(), new_value.to_vec()));
+                        inserted = true;
+                        continue;
+                    }
+                    Ordering::Greater => {}
+                }
             }
             kvs.push((key.to_vec(), value.to_vec()));
         }
@@ -275,10 +917,17 @@ impl WriteTxn<'_> {
             kvs.push((new_key.to_vec(), new_value.to_vec()));
         }
 
-        let split_idx = (kvs.len() + 1) / 2;
+        // a key past the end of the last leaf is an append: keep the old page full and start a new one
+        let (prev, next) = self.leaf_links(page_id)?;
+        let sizes: Vec<usize> = kvs.iter().map(|(k, v)| LEAF_ELEMENT_SIZE + k.len() + v.len()).collect();
+        let split_idx = match !inserted && next == 0 {
+            true => split_index(&sizes, self.leaf_body_size(), self.leaf_body_size(), 1),
+            false => split_index(&sizes, self.fill_threshold(self.leaf_body_size()), self.leaf_body_size(), 1),
+        }.ok_or(BTreeError::PageFull { page_id })?;
         let new_page_id = self.allocate_page()?;
-        self.write_leaf_page(page_id, &kvs[..split_idx])?;
-        self.write_leaf_page(new_page_id, &kvs[split_idx..])?;
+        self.write_leaf_page(page_id, &kvs[..split_idx], prev, new_page_id)?;
+        self.write_leaf_page(new_page_id, &kvs[split_idx..], page_id, next)?;
+        self.link_leaves(new_page_id, next)?;
         let separator = kvs[split_idx].0.clone();
 
         println!("   [SPLIT] Split into pages {} and {}, separator key len={}",
@@ -288,32 +937,36 @@ impl WriteTxn<'_> {
 
     // (key, child_page_id). The first entry is child only, empty key
     fn write_branch_page(&mut self, page_id: u64, entries: &[(Vec<u8>, u64)]) -> Result<()> {
-        let mut page_bytes = vec![0u8; PAGE_SIZE];
+        let mut page_bytes = vec![0u8; self.usable_size];
 
         let page = Page {
-            id: page_id,
+            id: page_id.into(),
             page_type: PageType::Branch as u8,
-            _padding: 0,
-            count: (entries.len() - 1) as u16,
-            overflow: 0,
+            codec: 0,
+            count: ((entries.len() - 1) as u16).into(),
+            checksum: 0.into(),
+            next: 0.into(),
+            prev: 0.into(),
         };
         page_bytes[..PAGE_HEADER_SIZE].copy_from_slice(page.as_bytes());
 
-        let mut data_offset = PAGE_BODY_SIZE;
+        let mut data_offset = self.body_size();
         for (i, (key, child_id)) in entries.iter().enumerate() {
             let kptr = match key.is_empty() {
                 true => 0,
                 false => {
+                    // kptr is relative to the body, which starts after the page header
                     data_offset -= key.len();
-                    page_bytes[data_offset..data_offset + key.len()].copy_from_slice(key);
+                    let start = PAGE_HEADER_SIZE + data_offset;
+                    page_bytes[start..start + key.len()].copy_from_slice(key);
                     data_offset
                 }
             };
             let elem = BranchElement {
-                page_id: *child_id,
-                ksize: key.len() as u16,
-                kptr: kptr as u16,
-                _padding: [0; 4],
+                page_id: (*child_id).into(),
+                ksize: (key.len() as u16).into(),
+                kptr: (kptr as u16).into(),
+                count: 0.into(),
             };
             page_bytes[(PAGE_HEADER_SIZE + i*BRANCH_ELEMENT_SIZE)..(PAGE_HEADER_SIZE + (i+1)*BRANCH_ELEMENT_SIZE)]
                 .copy_from_slice(elem.as_bytes());
@@ -322,17 +975,19 @@ impl WriteTxn<'_> {
         Ok(())
     }
 
-    fn write_leaf_page(&mut self, page_id: u64, kvs: &[(Vec<u8>, Vec<u8>)]) -> Result<()> {
-        let mut page_bytes = vec![0u8; PAGE_SIZE];
+    fn write_leaf_page(&mut self, page_id: u64, kvs: &[(Vec<u8>, Vec<u8>)], prev: u64, next: u64) -> Result<()> {
+        let mut page_bytes = vec![0u8; self.leaf_size];
         let page = Page {
-            id: page_id,
+            id: page_id.into(),
             page_type: PageType::Leaf as u8,
-            _padding: 0,
-            count: kvs.len() as u16,
-            overflow: 0,
+            codec: 0,
+            count: (kvs.len() as u16).into(),
+            checksum: 0.into(),
+            next: next.into(),
+            prev: prev.into(),
         };
         page_bytes[..PAGE_HEADER_SIZE].copy_from_slice(page.as_bytes());
-        let mut data_offset = PAGE_SIZE;
+        let mut data_offset = self.leaf_size;
 
         for (i, (key, value)) in kvs.iter().enumerate() {
             data_offset -= value.len();
@@ -344,10 +999,10 @@ impl WriteTxn<'_> {
             let kptr_body = data_offset - PAGE_HEADER_SIZE;
 
             let elem = LeafElement {
-                ksize: key.len() as u16,
-                vsize: value.len() as u16,
-                kptr: kptr_body as u16,
-                vptr: vptr_body as u16,
+                ksize: (key.len() as u16).into(),
+                vsize: (value.len() as u16).into(),
+                kptr: (kptr_body as u16).into(),
+                vptr: (vptr_body as u16).into(),
             };
             let offset = PAGE_HEADER_SIZE + i * LEAF_ELEMENT_SIZE;
             page_bytes[offset..offset + LEAF_ELEMENT_SIZE].copy_from_slice(elem.as_bytes());
@@ -357,75 +1012,128 @@ impl WriteTxn<'_> {
         Ok(())
     }
 
+    fn leaf_links(&self, page_id: u64) -> Result<(u64, u64)> {
+        let (page_header, _) = self.get_page_immut(page_id)?;
+        Ok((page_header.prev.get(), page_header.next.get()))
+    }
+
+    // Makes `right` follow `left` in the leaf chain. Either may be 0 for the ends of the chain.
+    fn link_leaves(&mut self, left: u64, right: u64) -> Result<()> {
+        if left != 0 {
+            self.get_page_mut(left)?.0.next.set(right);
+        }
+        if right != 0 {
+            self.get_page_mut(right)?.0.prev.set(left);
+        }
+        Ok(())
+    }
+
+    // Whether the last leaf under `page_id` is the last leaf of the tree, that is, the
+    // page is on the rightmost path from the root.
+    fn on_right_edge(&self, mut page_id: u64) -> Result<bool> {
+        loop {
+            let (page_header, page_body) = self.get_page_immut(page_id)?;
+            match self.get_page_type(page_id)? {
+                PageType::Leaf => return Ok(page_header.next.get() == 0),
+                PageType::Branch => {
+                    page_id = page::branch_entry(page_body, page_header.count.get() as usize)
+                        .ok_or(BTreeError::CorruptPageType { page_id, raw_type: page_header.page_type })?.1;
+                }
+                page_type => return Err(BTreeError::InvalidPageType { page_id, page_type }),
+            }
+        }
+    }
+
+    // The leftmost or rightmost leaf of the tree.
+    fn edge_leaf(&self, rightmost: bool) -> Result<u64> {
+        let mut page_id = self.root_page_id;
+        loop {
+            let (page_header, page_body) = self.get_page_immut(page_id)?;
+            match self.get_page_type(page_id)? {
+                PageType::Leaf => return Ok(page_id),
+                PageType::Branch => {
+                    let index = if rightmost { page_header.count.get() as usize } else { 0 };
+                    page_id = page::branch_entry(page_body, index)
+                        .ok_or(BTreeError::CorruptPageType { page_id, raw_type: page_header.page_type })?.1;
+                }
+                page_type => return Err(BTreeError::InvalidPageType { page_id, page_type }),
+            }
+        }
+    }
+
     fn split_root(&mut self, separator_key: Vec<u8>, new_page_id: u64) -> Result<()> {
+        // the new root holds two elements and the separator
+        let key_offset = self.body_size().checked_sub(separator_key.len())
+            .filter(|&key_offset| key_offset >= 2 * BRANCH_ELEMENT_SIZE)
+            .ok_or(BTreeError::KeyTooLarge { key_size: separator_key.len(), max_size: self.max_key_size() })?;
         let old_root_id = self.root_page_id;
         let new_root_id = self.allocate_page()?;
 
         println!("   [SPLIT] Splitting root {} into new root {} with children {} and {}",
                  old_root_id, new_root_id, old_root_id, new_page_id);
-        let mut page_bytes = vec![0u8; PAGE_SIZE];
-
-        let key_offset = PAGE_BODY_SIZE - separator_key.len();
+        let mut page_bytes = vec![0u8; self.usable_size];
         page_bytes[PAGE_HEADER_SIZE + key_offset..].copy_from_slice(&separator_key);
 
         let page = Page {
-            id: new_root_id,
+            id: new_root_id.into(),
             page_type: PageType::Branch as u8,
-            _padding: 0,
-            count: 1,  // One separator key
-            overflow: 0,
+            codec: 0,
+            count: 1.into(),  // One separator key
+            checksum: 0.into(),
+            next: 0.into(),
+            prev: 0.into(),
         };
         page_bytes[..PAGE_HEADER_SIZE].copy_from_slice(page.as_bytes());
 
         let elem = BranchElement {
-            page_id: old_root_id,
-            ksize: 0,
-            kptr: 0,
-            _padding: [0; 4],
+            page_id: old_root_id.into(),
+            ksize: 0.into(),
+            kptr: 0.into(),
+            count: 0.into(),
         };
         page_bytes[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + BRANCH_ELEMENT_SIZE].copy_from_slice(elem.as_bytes());
         let elem2 = BranchElement {
-            page_id: new_page_id,
-            ksize: separator_key.len() as u16,
-            kptr: key_offset as u16,
-            _padding: [0; 4],
+            page_id: new_page_id.into(),
+            ksize: (separator_key.len() as u16).into(),
+            kptr: (key_offset as u16).into(),
+            count: 0.into(),
         };
         page_bytes[PAGE_HEADER_SIZE + BRANCH_ELEMENT_SIZE..PAGE_HEADER_SIZE + 2 * BRANCH_ELEMENT_SIZE]
             .copy_from_slice(elem2.as_bytes());
 
         self.dirty_pages.insert(new_root_id, page_bytes);
         self.root_page_id = new_root_id;
-        Ok(())
+        self.refresh_counts(new_root_id)
     }
 
     fn insert_into_branch(&mut self, page_id: u64, key: Vec<u8>, child_page_id: u64) -> Result<Option<(Vec<u8>, u64)>> {
+        let comparator = self.comparator;
         let (page_header, page_body) = self.get_page_mut(page_id)?;
-        let current_count = page_header.count as usize;
+        let current_count = page_header.count.get() as usize;
         let total_elements = current_count + 1;
 
         let min_kptr = if current_count == 0 {
-            PAGE_BODY_SIZE
+            page_body.len()
         } else {
-            let mut min_kptr = PAGE_BODY_SIZE;
+            let mut min_kptr = page_body.len();
             for i in 0..total_elements {
                 let elem_bytes = &page_body[i*BRANCH_ELEMENT_SIZE..(i+1)*BRANCH_ELEMENT_SIZE];
                 let elem = BranchElement::ref_from_bytes(elem_bytes)
                     .map_err(|_| BTreeError::CorruptPageType { page_id, raw_type: page_header.page_type })?;
-                if elem.ksize > 0 {
-                    min_kptr = min_kptr.min(elem.kptr as usize);
+                if elem.ksize.get() > 0 {
+                    min_kptr = min_kptr.min(elem.kptr.get() as usize);
                 }
             }
             min_kptr
         };
 
         let new_elements_end = (total_elements + 1) * BRANCH_ELEMENT_SIZE;
-        let key_offset = min_kptr - key.len();
-
-        if new_elements_end > key_offset {
-            return self.split_branch(page_id, key, child_page_id);
-        }
+        let key_offset = match min_kptr.checked_sub(key.len()) {
+            Some(key_offset) if key_offset >= new_elements_end => key_offset,
+            _ => return self.split_branch(page_id, key, child_page_id),
+        };
 
-        let (insert_pos, _) = search::search_branch_elements(page_body, total_elements, &key)
+        let (insert_pos, _) = search::search_branch_elements(page_body, total_elements, &key, comparator)
             .map_err(|_| BTreeError::CorruptPageType { page_id, raw_type: page_header.page_type })?;
 
         page_body[key_offset..key_offset + key.len()].copy_from_slice(&key);
@@ -436,17 +1144,17 @@ impl WriteTxn<'_> {
         }
 
         let new_element = BranchElement {
-            page_id: child_page_id,
-            ksize: key.len() as u16,
-            kptr: key_offset as u16,
-            _padding: [0; 4],
+            page_id: child_page_id.into(),
+            ksize: (key.len() as u16).into(),
+            kptr: (key_offset as u16).into(),
+            count: 0.into(),
         };
 
         page_body[insert_pos*BRANCH_ELEMENT_SIZE..(insert_pos+1)*BRANCH_ELEMENT_SIZE].copy_from_slice(new_element.as_bytes());
-        page_header.count = (current_count + 1) as u16;
+        page_header.count.set((current_count + 1) as u16);
 
         println!("   [OK] Inserted separator key (len={}) into branch page {}, count now {}",
-                 key.len(), page_id, page_header.count);
+                 key.len(), page_id, page_header.count.get());
 
         Ok(None)
     }
@@ -454,35 +1162,42 @@ impl WriteTxn<'_> {
     fn split_branch(&mut self, page_id: u64, new_key: Vec<u8>, new_child_id: u64) -> Result<Option<(Vec<u8>, u64)>> {
         println!("   [SPLIT] Splitting branch page {}", page_id);
 
+        let comparator = self.comparator;
         let (page_header, page_body) = self.get_page_immut(page_id)?;
-        let count = page_header.count as usize;
+        let count = page_header.count.get() as usize;
 
         // branch has count+1 children (first has no key)
         let mut entries = Vec::with_capacity(count + 2);
         let mut inserted = false;
 
-        let elem = BranchElement::ref_from_bytes(&page_body[0..BRANCH_ELEMENT_SIZE])
-            .map_err(|_| BTreeError::CorruptPageType { page_id, raw_type: page_header.page_type })?;
-        entries.push((Vec::new(), elem.page_id));
+        let (_, first_child_id) = page::branch_entry(page_body, 0)
+            .ok_or(DbError::PageFormat { page_id, index: Some(0) })?;
+        entries.push((Vec::new(), first_child_id));
 
         for i in 1..=count {
-            let elem = BranchElement::ref_from_bytes(&page_body[i*BRANCH_ELEMENT_SIZE..(i+1)*BRANCH_ELEMENT_SIZE])
-                .map_err(|_| BTreeError::CorruptPageType { page_id, raw_type: page_header.page_type })?;
-
-            let key = page_body[elem.kptr as usize..(elem.kptr + elem.ksize) as usize].to_vec();
+            let (key, child_id) = page::branch_entry(page_body, i)
+                .ok_or(DbError::PageFormat { page_id, index: Some(i) })?;
+            let key = key.to_vec();
 
-            if !inserted && new_key.as_slice() < key.as_slice() {
+            if !inserted && comparator.compare(&new_key, &key) == Ordering::Less {
                 entries.push((new_key.clone(), new_child_id));
                 inserted = true;
             }
-            entries.push((key, elem.page_id));
+            entries.push((key, child_id));
         }
 
         if !inserted {
             entries.push((new_key.clone(), new_child_id));
         }
 
-        let split_idx = entries.len() / 2;
+        // entry split_idx moves up as the separator and becomes the first child on the right;
+        // appends are only kept full on the rightmost path, as they are for leaves
+        let sizes: Vec<usize> = entries.iter().map(|(k, _)| BRANCH_ELEMENT_SIZE + k.len()).collect();
+        let append = !inserted && self.on_right_edge(new_child_id)?;
+        let split_idx = match append {
+            true => split_index(&sizes, self.body_size(), self.body_size(), 2),
+            false => split_index(&sizes, self.fill_threshold(self.body_size()), self.body_size(), 2),
+        }.ok_or(BTreeError::PageFull { page_id })?;
         let separator = entries[split_idx].0.clone();
         let new_page_id = self.allocate_page()?;
         self.write_branch_page(page_id, &entries[0..split_idx])?;
@@ -495,15 +1210,167 @@ impl WriteTxn<'_> {
         Ok(Some((separator, new_page_id)))
     }
 
+    // Keys under `page_id`: a leaf's element count, or the sum of a branch's child counts.
+    fn subtree_count(&self, page_id: u64) -> Result<u64> {
+        let (page_header, page_body) = self.get_page_immut(page_id)?;
+        match self.get_page_type(page_id)? {
+            PageType::Leaf => Ok(page_header.count.get() as u64),
+            PageType::Branch => (0..=page_header.count.get() as usize)
+                .map(|index| page::branch_child_count(page_body, index)
+                    .ok_or(BTreeError::CorruptPageType { page_id, raw_type: page_header.page_type }))
+                .sum(),
+            page_type => Err(BTreeError::InvalidPageType { page_id, page_type }),
+        }
+    }
+
+    // Recomputes the count of every child of branch `page_id`, whose children are up to date.
+    fn refresh_counts(&mut self, page_id: u64) -> Result<()> {
+        if !self.counted {
+            return Ok(());
+        }
+        let counts = self.read_branch_entries(page_id)?
+            .into_iter()
+            .map(|(_, child_id)| self.subtree_count(child_id))
+            .collect::<Result<Vec<_>>>()?;
+        for (index, count) in counts.into_iter().enumerate() {
+            self.set_child_count(page_id, index, count)?;
+        }
+        Ok(())
+    }
+
+    // Recomputes the count of the one child of branch `page_id` that a write went through.
+    fn refresh_child_count(&mut self, page_id: u64, child_id: u64) -> Result<()> {
+        if !self.counted {
+            return Ok(());
+        }
+        let (page_header, page_body) = self.get_page_immut(page_id)?;
+        let index = (0..=page_header.count.get() as usize)
+            .position(|index| page::branch_entry(page_body, index).is_some_and(|(_, id)| id == child_id))
+            .ok_or(BTreeError::CorruptPageType { page_id, raw_type: page_header.page_type })?;
+        let count = self.subtree_count(child_id)?;
+        self.set_child_count(page_id, index, count)
+    }
+
+    fn set_child_count(&mut self, page_id: u64, index: usize, count: u64) -> Result<()> {
+        // a u32 per child caps a subtree at 4 billion keys
+        let count = u32::try_from(count).map_err(|_| BTreeError::CountOverflow { page_id, count })?;
+        let (page_header, page_body) = self.get_page_mut(page_id)?;
+        let raw_type = page_header.page_type;
+        let elem_bytes = page_body.get_mut(index*BRANCH_ELEMENT_SIZE..(index+1)*BRANCH_ELEMENT_SIZE)
+            .ok_or(BTreeError::CorruptPageType { page_id, raw_type })?;
+        let elem = BranchElement::mut_from_bytes(elem_bytes)
+            .map_err(|_| BTreeError::CorruptPageType { page_id, raw_type })?;
+        elem.count.set(count);
+        Ok(())
+    }
+
+    fn fill_threshold(&self, body_size: usize) -> usize {
+        (body_size as f64 * self.fill_percent) as usize
+    }
+
+    fn body_size(&self) -> usize {
+        self.usable_size - PAGE_HEADER_SIZE
+    }
+
+    fn leaf_body_size(&self) -> usize {
+        self.leaf_size - PAGE_HEADER_SIZE
+    }
+
+    // The first leaf reuses the empty root page, every later one gets a new page.
+    fn flush_sorted_leaf(&mut self, batch: &mut Vec<(Vec<u8>, Vec<u8>)>, leaves: &mut Vec<(Vec<u8>, u64)>) -> Result<()> {
+        let page_id = match leaves.is_empty() {
+            true => self.root_page_id,
+            false => self.allocate_page()?,
+        };
+        let prev = leaves.last().map_or(0, |&(_, prev)| prev);
+        self.write_leaf_page(page_id, batch, prev, 0)?;
+        self.link_leaves(prev, page_id)?;
+        leaves.push((batch[0].0.clone(), page_id));
+        batch.clear();
+        Ok(())
+    }
+
+    // Groups each level of (first key, page) pairs into branch pages until one root is left.
+    fn build_branch_levels(&mut self, mut level: Vec<(Vec<u8>, u64)>, limit: usize) -> Result<u64> {
+        while level.len() > 1 {
+            let mut parents = Vec::new();
+            let mut group: Vec<(Vec<u8>, u64)> = Vec::new();
+            let mut group_size = 0;
+
+            for (key, page_id) in level {
+                // counts the first key too, although it's dropped when the page is written
+                let size = BRANCH_ELEMENT_SIZE + key.len();
+                if group.len() >= 2 && group_size + size > limit {
+                    parents.push(self.flush_sorted_branch(&mut group)?);
+                    group_size = 0;
+                }
+                if group_size + size > self.body_size() {
+                    return Err(BTreeError::PageFull { page_id });
+                }
+                group.push((key, page_id));
+                group_size += size;
+            }
+            parents.push(self.flush_sorted_branch(&mut group)?);
+            level = parents;
+        }
+        Ok(level[0].1)
+    }
+
+    fn flush_sorted_branch(&mut self, group: &mut Vec<(Vec<u8>, u64)>) -> Result<(Vec<u8>, u64)> {
+        let page_id = self.allocate_page()?;
+        let first_key = std::mem::take(&mut group[0].0);
+        self.write_branch_page(page_id, group)?;
+        // the level below is complete, so its counts are final
+        self.refresh_counts(page_id)?;
+        group.clear();
+        Ok((first_key, page_id))
+    }
+
+    fn is_empty_leaf(&self, page_id: u64) -> Result<bool> {
+        let (page_header, _) = self.get_page_immut(page_id)?;
+        Ok(page_header.page_type == PageType::Leaf as u8 && page_header.count.get() == 0)
+    }
+
+    // Largest key under `page_id`, following the rightmost child of every branch.
+    fn last_key(&self, page_id: u64) -> Result<Option<Vec<u8>>> {
+        let (page_header, page_body) = self.get_page_immut(page_id)?;
+        match self.get_page_type(page_id)? {
+            PageType::Leaf => match page_header.count.get() {
+                0 => Ok(None),
+                count => {
+                    let (key, _) = page::leaf_entry(page_body, count as usize - 1)
+                        .ok_or(BTreeError::CorruptPageType { page_id, raw_type: page_header.page_type })?;
+                    Ok(Some(key.to_vec()))
+                }
+            },
+            PageType::Branch => {
+                // empty leaves can be left behind by deletes, so fall back leftwards
+                for index in (0..=page_header.count.get() as usize).rev() {
+                    let (_, child_id) = page::branch_entry(page_body, index)
+                        .ok_or(BTreeError::CorruptPageType { page_id, raw_type: page_header.page_type })?;
+                    if let Some(key) = self.last_key(child_id)? {
+                        return Ok(Some(key));
+                    }
+                }
+                Ok(None)
+            }
+            page_type => Err(BTreeError::InvalidPageType { page_id, page_type }),
+        }
+    }
+
     fn allocate_page(&mut self) -> Result<u64> {
+        Ok(self.next_page_id())
+    }
+
+    fn next_page_id(&mut self) -> u64 {
         if let Some(page_id) = self.free_list.pop() {
-            return Ok(page_id);
+            return page_id;
         }
         self.highest_page_id += 1;
-        Ok(self.highest_page_id)
+        self.highest_page_id
     }
 
-    fn get_page_type(&mut self, page_id: u64) -> Result<PageType> {
+    fn get_page_type(&self, page_id: u64) -> Result<PageType> {
         let page_bytes = self.read_page(page_id)?;
         let page_header = Page::ref_from_prefix(page_bytes)
             .map_err(|_| BTreeError::CorruptPageType { page_id, raw_type: 0 })?
@@ -522,4 +1389,43 @@ impl WriteTxn<'_> {
             }
         })
     }
-}
\ No newline at end of file
+}
+
+// Index of the first element of the right page: the left page takes elements while
+// they fit in `threshold` bytes, then more if the right page would overflow its
+// `body_size` bytes. Each side keeps at least `min_per_side` elements, or there is no
+// split if there are too few of them.
+fn split_index(sizes: &[usize], threshold: usize, body_size: usize, min_per_side: usize) -> Option<usize> {
+    if sizes.len() < 2 * min_per_side {
+        return None;
+    }
+    let total: usize = sizes.iter().sum();
+    let mut used = 0;
+    let mut index = 0;
+    while index < sizes.len() && (used + sizes[index] <= threshold || total - used > body_size) {
+        used += sizes[index];
+        index += 1;
+    }
+    Some(index.clamp(min_per_side, sizes.len() - min_per_side))
+}
+
+type KeyRange<'k> = (Bound<&'k [u8]>, Bound<&'k [u8]>);
+
+fn range_contains(comparator: &dyn Comparator, range: &KeyRange<'_>, key: &[u8]) -> bool {
+    let after_start = match range.0 {
+        Bound::Included(start) => comparator.compare(key, start) != Ordering::Less,
+        Bound::Excluded(start) => comparator.compare(key, start) == Ordering::Greater,
+        Bound::Unbounded => true,
+    };
+    let before_end = match range.1 {
+        Bound::Included(end) => comparator.compare(key, end) != Ordering::Greater,
+        Bound::Excluded(end) => comparator.compare(key, end) == Ordering::Less,
+        Bound::Unbounded => true,
+    };
+    after_start && before_end
+}
+
+// Index of the child of a branch whose key range holds `key`, skipping the empty first separator.
+fn child_index(comparator: &dyn Comparator, entries: &[(Vec<u8>, u64)], key: &[u8]) -> usize {
+    entries[1..].partition_point(|(separator, _)| comparator.compare(separator, key) != Ordering::Greater)
+}
@@ -0,0 +1,36 @@
+// 64 bit FNV-1a, the hash bbolt uses for its meta pages. Cheap and good enough to
+// tell a torn or half-written region from a complete one; not a defence against tampering.
+const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const PRIME: u64 = 0x100000001b3;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Checksum(u64);
+
+impl Checksum {
+    pub fn new() -> Self {
+        Checksum(OFFSET_BASIS)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(PRIME);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for Checksum {
+    fn default() -> Self {
+        Checksum::new()
+    }
+}
+
+pub fn checksum(data: &[u8]) -> u64 {
+    let mut checksum = Checksum::new();
+    checksum.update(data);
+    checksum.finish()
+}
@@ -0,0 +1,23 @@
+use std::cmp::Ordering;
+
+pub const MAX_COMPARATOR_NAME: usize = 32;
+
+/// Orders the keys of a tree. The name is persisted in the header, so a file
+/// can only be reopened with the comparator it was built with.
+pub trait Comparator: Send + Sync {
+    fn name(&self) -> &str;
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+}
+
+/// Plain lexicographic byte order, the default for every tree.
+pub struct Bytewise;
+
+impl Comparator for Bytewise {
+    fn name(&self) -> &str {
+        "rbolt.bytewise"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+}
@@ -0,0 +1,234 @@
+// Leaves of a database created with a codec hold LEAF_SPAN pages worth of entries and
+// are compressed as they are committed. The result is stored in a run of pages: a
+// Packed page at the leaf's own id, chained through `next` to as many Overflow pages as
+// the rest needs, each taken from the free list like any other page and sealed with its
+// own checksum. Branch and free list pages are always stored as they are.
+//
+// The body of a Packed page starts with the leaf's length before and after encoding,
+// and its `codec` byte says how the run was encoded. A leaf that doesn't shrink is
+// stored uncompressed, so a file mixes plain pages and runs of either kind.
+use crate::db::{DbError, MAX_PAGE_SIZE};
+use crate::page::{Page, PageType, PAGE_HEADER_SIZE};
+use std::borrow::Cow;
+use zerocopy::little_endian::U32;
+use zerocopy::{FromBytes, IntoBytes, Immutable, KnownLayout, Unaligned};
+
+/// Pages of entries a leaf holds before compression.
+pub const LEAF_SPAN: usize = 4;
+const RUN_HEADER_SIZE: usize = std::mem::size_of::<RunHeader>();
+
+/// Codec leaf pages are compressed with, see [`DbOptions::compression`](crate::db::DbOptions::compression).
+/// Each needs its cargo feature: `lz4` or `zstd`, or `compression` for both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum Compression {
+    #[default]
+    None = 0,
+    Lz4 = 1,
+    Zstd = 2,
+}
+
+impl Compression {
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Lz4),
+            2 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Whether this build was compiled with the codec's feature.
+    pub fn is_available(self) -> bool {
+        match self {
+            Compression::None => true,
+            Compression::Lz4 => cfg!(feature = "lz4"),
+            Compression::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    // None if the codec isn't compiled in or fails
+    fn compress(self, bytes: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Compression::None => None,
+            Compression::Lz4 => lz4::compress(bytes),
+            Compression::Zstd => zstd::compress(bytes),
+        }
+    }
+
+    fn decompress(self, stored: &[u8], len: usize) -> Option<Vec<u8>> {
+        match self {
+            Compression::None => Some(stored.to_vec()),
+            Compression::Lz4 => lz4::decompress(stored, len),
+            Compression::Zstd => zstd::decompress(stored, len),
+        }
+    }
+}
+
+#[cfg(feature = "lz4")]
+mod lz4 {
+    pub(super) fn compress(bytes: &[u8]) -> Option<Vec<u8>> {
+        Some(lz4_flex::block::compress(bytes))
+    }
+
+    pub(super) fn decompress(stored: &[u8], len: usize) -> Option<Vec<u8>> {
+        lz4_flex::block::decompress(stored, len).ok()
+    }
+}
+
+#[cfg(not(feature = "lz4"))]
+mod lz4 {
+    pub(super) fn compress(_: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+
+    pub(super) fn decompress(_: &[u8], _: usize) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+#[cfg(feature = "zstd")]
+mod zstd {
+    pub(super) fn compress(bytes: &[u8]) -> Option<Vec<u8>> {
+        zstd::bulk::compress(bytes, zstd::DEFAULT_COMPRESSION_LEVEL).ok()
+    }
+
+    pub(super) fn decompress(stored: &[u8], len: usize) -> Option<Vec<u8>> {
+        zstd::bulk::decompress(stored, len).ok()
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+mod zstd {
+    pub(super) fn compress(_: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+
+    pub(super) fn decompress(_: &[u8], _: usize) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+// Starts the body of a Packed page, followed by the first of the encoded bytes.
+#[repr(C)]
+#[derive(Clone, Copy, FromBytes, IntoBytes, KnownLayout, Immutable, Unaligned)]
+struct RunHeader {
+    leaf_len: U32, // bytes of the leaf page once decoded
+    stored_len: U32, // encoded bytes, spread over the run
+}
+
+/// Bytes per leaf page before compression: LEAF_SPAN pages when leaves are compressed,
+/// up to the largest page element offsets can address. `usable_size` is the page size
+/// less any room encryption needs.
+pub(crate) fn leaf_size(usable_size: usize, codec: Compression) -> usize {
+    match codec {
+        Compression::None => usable_size,
+        _ => (usable_size * LEAF_SPAN).min(MAX_PAGE_SIZE),
+    }
+}
+
+/// The pages that store `leaf` at `page_id`: the page itself if it fits and doesn't
+/// compress, otherwise a run whose overflow pages come from `allocate`. Pages hold
+/// `usable_size` bytes, the page size less any room encryption needs.
+pub(crate) fn pack(page_id: u64, leaf: &[u8], codec: Compression, usable_size: usize, allocate: &mut dyn FnMut() -> u64) -> Vec<(u64, Vec<u8>)> {
+    let (codec, stored) = match codec.compress(leaf) {
+        Some(compressed) if compressed.len() < leaf.len() => (codec, compressed),
+        _ if leaf.len() <= usable_size => return vec![(page_id, leaf.to_vec())],
+        _ => (Compression::None, leaf.to_vec()),
+    };
+
+    let head = RunHeader { leaf_len: (leaf.len() as u32).into(), stored_len: (stored.len() as u32).into() };
+    let mut chunks = vec![&stored[..stored.len().min(usable_size - PAGE_HEADER_SIZE - RUN_HEADER_SIZE)]];
+    chunks.extend(stored[chunks[0].len()..].chunks(usable_size - PAGE_HEADER_SIZE));
+    let mut run: Vec<u64> = vec![page_id];
+    run.extend((1..chunks.len()).map(|_| allocate()));
+
+    let mut pages = Vec::with_capacity(run.len());
+    for (index, chunk) in chunks.iter().enumerate() {
+        let page = Page {
+            id: run[index].into(),
+            page_type: if index == 0 { PageType::Packed } else { PageType::Overflow } as u8,
+            codec: if index == 0 { codec as u8 } else { 0 },
+            count: if index == 0 { (run.len() as u16 - 1).into() } else { 0.into() },
+            checksum: 0.into(),
+            next: run.get(index + 1).copied().unwrap_or(0).into(),
+            prev: if index == 0 { 0.into() } else { page_id.into() },
+        };
+        let mut page_bytes = vec![0u8; usable_size];
+        page_bytes[..PAGE_HEADER_SIZE].copy_from_slice(page.as_bytes());
+        let mut offset = PAGE_HEADER_SIZE;
+        if index == 0 {
+            page_bytes[offset..offset + RUN_HEADER_SIZE].copy_from_slice(head.as_bytes());
+            offset += RUN_HEADER_SIZE;
+        }
+        page_bytes[offset..offset + chunk.len()].copy_from_slice(chunk);
+        pages.push((run[index], page_bytes));
+    }
+    pages
+}
+
+/// Decodes the leaf stored in the run headed by `head`, the Packed page `page_id`.
+/// `stored_page` returns a page of the file once it has matched its checksum, decrypted.
+pub(crate) fn unpack<'s>(
+    page_id: u64,
+    head: &[u8],
+    stored_page: &dyn Fn(u64) -> Result<Cow<'s, [u8]>, DbError>,
+) -> Result<Vec<u8>, DbError> {
+    let malformed = || DbError::PageFormat { page_id, index: None };
+    let (page, body) = Page::ref_from_prefix(head).map_err(|_| malformed())?;
+    let (run_header, first) = RunHeader::ref_from_prefix(body).map_err(|_| malformed())?;
+    let codec = Compression::from_id(page.codec)
+        .filter(|codec| codec.is_available())
+        .ok_or(DbError::UnsupportedCodec { codec: page.codec })?;
+    let (leaf_len, stored_len) = (run_header.leaf_len.get() as usize, run_header.stored_len.get() as usize);
+    if page.page_type != PageType::Packed as u8 || !(PAGE_HEADER_SIZE..=MAX_PAGE_SIZE).contains(&leaf_len) {
+        return Err(malformed());
+    }
+
+    let mut stored = first[..stored_len.min(first.len())].to_vec();
+    for (_, page_bytes) in overflow(page_id, page, stored_page)? {
+        let body = &page_bytes[PAGE_HEADER_SIZE..];
+        stored.extend_from_slice(&body[..(stored_len - stored.len()).min(body.len())]);
+    }
+    match codec.decompress(&stored, leaf_len) {
+        Some(leaf) if stored.len() == stored_len && leaf.len() == leaf_len => Ok(leaf),
+        _ => Err(malformed()),
+    }
+}
+
+/// Ids of the Overflow pages in the run headed by the Packed page `page_id`, so the
+/// run can be freed along with it.
+pub(crate) fn overflow_pages<'s>(
+    page_id: u64,
+    head: &[u8],
+    stored_page: &dyn Fn(u64) -> Result<Cow<'s, [u8]>, DbError>,
+) -> Result<Vec<u64>, DbError> {
+    let (page, _) = Page::ref_from_prefix(head).map_err(|_| DbError::PageFormat { page_id, index: None })?;
+    Ok(overflow(page_id, page, stored_page)?.into_iter().map(|(overflow_id, _)| overflow_id).collect())
+}
+
+// The Overflow pages of a run, by id.
+type OverflowPages<'s> = Vec<(u64, Cow<'s, [u8]>)>;
+
+// Follows the chain from a Packed page through the `count` Overflow pages it names,
+// each of which has to point back at it.
+fn overflow<'s>(
+    page_id: u64,
+    page: &Page,
+    stored_page: &dyn Fn(u64) -> Result<Cow<'s, [u8]>, DbError>,
+) -> Result<OverflowPages<'s>, DbError> {
+    let mut pages = Vec::with_capacity(page.count.get() as usize);
+    let mut next = page.next.get();
+    for index in 0..page.count.get() as usize {
+        let page_bytes = stored_page(next)?;
+        match Page::ref_from_prefix(&page_bytes) {
+            Ok((overflow, _)) if overflow.page_type == PageType::Overflow as u8 && overflow.prev.get() == page_id => {
+                let overflow_next = overflow.next.get();
+                pages.push((next, page_bytes));
+                next = overflow_next;
+            }
+            _ => return Err(DbError::PageFormat { page_id, index: Some(index) }),
+        }
+    }
+    Ok(pages)
+}
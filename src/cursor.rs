@@ -0,0 +1,346 @@
+use crate::db::{DbError, ReadTxn, Result};
+use crate::page::{self, Page, PageType};
+use crate::search;
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+type Entry<'t> = (&'t [u8], &'t [u8]);
+
+/// Walks the leaves of a read transaction in key order.
+/// The stack holds (page_id, element index) from the root down to the current leaf.
+/// Once the cursor has followed a sibling link it only holds the leaf.
+pub struct Cursor<'t> {
+    txn: &'t ReadTxn<'t>,
+    stack: Vec<(u64, usize)>,
+    follow_links: bool,
+}
+
+impl<'t> Cursor<'t> {
+    pub(crate) fn new(txn: &'t ReadTxn<'t>) -> Self {
+        Cursor {
+            txn,
+            stack: Vec::new(),
+            follow_links: true,
+        }
+    }
+
+    /// Whether to move between leaves through their sibling links (the default)
+    /// or by climbing back through the branch pages above them.
+    pub fn use_sibling_links(&mut self, enabled: bool) {
+        self.follow_links = enabled;
+    }
+
+    /// Moves to the smallest key.
+    pub fn first(&mut self) -> Result<Option<Entry<'t>>> {
+        self.stack.clear();
+        self.descend_first(self.txn.root_page_id())?;
+        self.settle()
+    }
+
+    /// Moves to the largest key.
+    pub fn last(&mut self) -> Result<Option<Entry<'t>>> {
+        self.stack.clear();
+        self.descend_last(self.txn.root_page_id())?;
+        self.settle_back()
+    }
+
+    /// Moves to the first key greater than or equal to `key`.
+    pub fn seek(&mut self, key: &[u8]) -> Result<Option<Entry<'t>>> {
+        self.stack.clear();
+        let comparator = self.txn.comparator();
+        let mut page_id = self.txn.root_page_id();
+        loop {
+            let (page, body) = self.page(page_id)?;
+            match page.page_type {
+                t if t == PageType::Branch as u8 => {
+                    let (index, found) = search::search_branch_elements(body, page.count.get() as usize, key, comparator)
+                        .map_err(|err| DbError::PageFormat { page_id, index: Some(err.index) })?;
+                    let child_index = if found { index } else { index.saturating_sub(1) };
+                    let (_, child_id) = page::branch_entry(body, child_index)
+                        .ok_or(DbError::PageFormat { page_id, index: Some(child_index) })?;
+                    self.push_branch(page_id, child_index)?;
+                    page_id = child_id;
+                }
+                t if t == PageType::Leaf as u8 => {
+                    let (index, _) = search::search_leaf_elements(body, page.count.get() as usize, key, comparator)
+                        .map_err(|err| DbError::PageFormat { page_id, index: Some(err.index) })?;
+                    self.stack.push((page_id, index));
+                    break;
+                }
+                _ => return Ok(None),
+            }
+        }
+        self.settle()
+    }
+
+    /// Moves to the largest key less than or equal to `key`.
+    pub fn seek_for_prev(&mut self, key: &[u8]) -> Result<Option<Entry<'t>>> {
+        let comparator = self.txn.comparator();
+        match self.seek(key)? {
+            Some(entry) if comparator.compare(entry.0, key) == Ordering::Equal => Ok(Some(entry)),
+            Some(_) => self.move_prev(),
+            // every key sorts before `key`
+            None => self.last(),
+        }
+    }
+
+    /// Moves to the next key.
+    pub fn move_next(&mut self) -> Result<Option<Entry<'t>>> {
+        match self.stack.last_mut() {
+            Some((_, index)) => *index += 1,
+            None => return Ok(None),
+        }
+        self.settle()
+    }
+
+    /// Moves to the previous key.
+    pub fn move_prev(&mut self) -> Result<Option<Entry<'t>>> {
+        match self.stack.last_mut() {
+            Some((_, index)) if *index > 0 => *index -= 1,
+            Some(_) => {
+                if !self.step_leaf(false)? {
+                    return Ok(None);
+                }
+            }
+            None => return Ok(None),
+        }
+        self.settle_back()
+    }
+
+    /// The entry under the cursor, or None once it has run off the end.
+    pub fn current(&self) -> Result<Option<Entry<'t>>> {
+        let Some(&(page_id, index)) = self.stack.last() else {
+            return Ok(None);
+        };
+        let (page, body) = self.page(page_id)?;
+        if index >= page.count.get() as usize {
+            return Ok(None);
+        }
+        Ok(Some(page::leaf_entry(body, index).ok_or(DbError::PageFormat { page_id, index: Some(index) })?))
+    }
+
+    fn page(&self, page_id: u64) -> Result<(&'t Page, &'t [u8])> {
+        page::parse(page_id, self.txn.page_bytes(page_id)?)
+    }
+
+    // Records a step down through a branch, refusing paths too long to be a tree.
+    fn push_branch(&mut self, page_id: u64, index: usize) -> Result<()> {
+        if self.stack.len() >= page::MAX_DEPTH {
+            return Err(DbError::PageFormat { page_id, index: Some(index) });
+        }
+        self.stack.push((page_id, index));
+        Ok(())
+    }
+
+    fn descend_first(&mut self, mut page_id: u64) -> Result<()> {
+        loop {
+            let (page, body) = self.page(page_id)?;
+            if page.page_type != PageType::Branch as u8 {
+                if page.page_type == PageType::Leaf as u8 {
+                    self.stack.push((page_id, 0));
+                }
+                return Ok(());
+            }
+            self.push_branch(page_id, 0)?;
+            page_id = page::branch_entry(body, 0).ok_or(DbError::PageFormat { page_id, index: Some(0) })?.1;
+        }
+    }
+
+    // Leaves the cursor on the last element of the rightmost leaf under `page_id`,
+    // or past the end of it when that leaf is empty.
+    fn descend_last(&mut self, mut page_id: u64) -> Result<()> {
+        loop {
+            let (page, body) = self.page(page_id)?;
+            if page.page_type != PageType::Branch as u8 {
+                if page.page_type == PageType::Leaf as u8 {
+                    self.stack.push((page_id, (page.count.get() as usize).saturating_sub(1)));
+                }
+                return Ok(());
+            }
+            let last = page.count.get() as usize;
+            self.push_branch(page_id, last)?;
+            page_id = page::branch_entry(body, last).ok_or(DbError::PageFormat { page_id, index: Some(last) })?.1;
+        }
+    }
+
+    // Moves to the first element of the next leaf, or the last element of the previous
+    // one when going backwards. False, with the stack emptied, at either end of the tree.
+    fn step_leaf(&mut self, forward: bool) -> Result<bool> {
+        let Some((leaf_id, _)) = self.stack.pop() else {
+            return Ok(false);
+        };
+        if !self.follow_links {
+            return if forward { self.climb_right() } else { self.climb_left() };
+        }
+
+        // the branch path above no longer leads to the sibling
+        self.stack.clear();
+        let (leaf, _) = self.page(leaf_id)?;
+        let sibling_id = if forward { leaf.next.get() } else { leaf.prev.get() };
+        if sibling_id == 0 {
+            return Ok(false);
+        }
+        let (sibling, _) = self.page(sibling_id)?;
+        if sibling.page_type != PageType::Leaf as u8 {
+            return Err(DbError::PageFormat { page_id: sibling_id, index: None });
+        }
+        let index = if forward { 0 } else { (sibling.count.get() as usize).saturating_sub(1) };
+        self.stack.push((sibling_id, index));
+        Ok(true)
+    }
+
+    // Moves to the leftmost leaf of the nearest right sibling subtree, false at the end of the tree.
+    fn climb_right(&mut self) -> Result<bool> {
+        loop {
+            let Some(&(parent_id, child_index)) = self.stack.last() else {
+                return Ok(false);
+            };
+            let (parent, body) = self.page(parent_id)?;
+            if child_index < parent.count.get() as usize {
+                let (_, child_id) = page::branch_entry(body, child_index + 1)
+                    .ok_or(DbError::PageFormat { page_id: parent_id, index: Some(child_index + 1) })?;
+                self.stack.last_mut().unwrap().1 += 1;
+                self.descend_first(child_id)?;
+                return Ok(true);
+            }
+            self.stack.pop();
+        }
+    }
+
+    // Moves to the rightmost leaf of the nearest left sibling subtree, false at the start of the tree.
+    fn climb_left(&mut self) -> Result<bool> {
+        loop {
+            let Some(&(parent_id, child_index)) = self.stack.last() else {
+                return Ok(false);
+            };
+            if child_index > 0 {
+                let (_, body) = self.page(parent_id)?;
+                let (_, child_id) = page::branch_entry(body, child_index - 1)
+                    .ok_or(DbError::PageFormat { page_id: parent_id, index: Some(child_index - 1) })?;
+                self.stack.last_mut().unwrap().1 -= 1;
+                self.descend_last(child_id)?;
+                return Ok(true);
+            }
+            self.stack.pop();
+        }
+    }
+
+    // Steps over exhausted (or empty) leaves until the cursor rests on an element.
+    fn settle(&mut self) -> Result<Option<Entry<'t>>> {
+        self.settle_towards(true)
+    }
+
+    // Like settle, but steps backwards over empty leaves.
+    fn settle_back(&mut self) -> Result<Option<Entry<'t>>> {
+        self.settle_towards(false)
+    }
+
+    fn settle_towards(&mut self, forward: bool) -> Result<Option<Entry<'t>>> {
+        loop {
+            let Some(&(page_id, index)) = self.stack.last() else {
+                return Ok(None);
+            };
+            let (page, _) = self.page(page_id)?;
+            if index < page.count.get() as usize {
+                return self.current();
+            }
+            if !self.step_leaf(forward)? {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Iterator over a key range, created by [`ReadTxn::range`], or by
+/// [`ReadTxn::range_rev`] which walks it from the largest key down.
+pub struct Iter<'t> {
+    cursor: Cursor<'t>,
+    // the bound the iteration runs towards: the end of the range, or its start in reverse
+    stop: Bound<Vec<u8>>,
+    reverse: bool,
+    prefix: Option<Vec<u8>>,
+    pending: Option<Result<Entry<'t>>>,
+}
+
+impl<'t> Iter<'t> {
+    pub(crate) fn new(mut cursor: Cursor<'t>, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Result<Self> {
+        let comparator = cursor.txn.comparator();
+        let first = match start {
+            Bound::Unbounded => cursor.first()?,
+            Bound::Included(key) => cursor.seek(key)?,
+            Bound::Excluded(key) => match cursor.seek(key)? {
+                Some((k, _)) if comparator.compare(k, key) == Ordering::Equal => cursor.move_next()?,
+                other => other,
+            },
+        };
+        Ok(Iter {
+            cursor,
+            stop: end.map(|k| k.to_vec()),
+            reverse: false,
+            prefix: None,
+            pending: first.map(Ok),
+        })
+    }
+
+    pub(crate) fn new_rev(mut cursor: Cursor<'t>, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Result<Self> {
+        let comparator = cursor.txn.comparator();
+        let first = match end {
+            Bound::Unbounded => cursor.last()?,
+            Bound::Included(key) => cursor.seek_for_prev(key)?,
+            Bound::Excluded(key) => match cursor.seek_for_prev(key)? {
+                Some((k, _)) if comparator.compare(k, key) == Ordering::Equal => cursor.move_prev()?,
+                other => other,
+            },
+        };
+        Ok(Iter {
+            cursor,
+            stop: start.map(|k| k.to_vec()),
+            reverse: true,
+            prefix: None,
+            pending: first.map(Ok),
+        })
+    }
+
+    /// Iterates over the keys starting with `prefix`, stopping at the first one that doesn't.
+    pub(crate) fn with_prefix(mut cursor: Cursor<'t>, prefix: &[u8]) -> Result<Self> {
+        let first = cursor.seek(prefix)?;
+        Ok(Iter {
+            cursor,
+            stop: Bound::Unbounded,
+            reverse: false,
+            prefix: Some(prefix.to_vec()),
+            pending: first.map(Ok),
+        })
+    }
+
+    fn in_range(&self, key: &[u8]) -> bool {
+        if self.prefix.as_ref().is_some_and(|prefix| !key.starts_with(prefix)) {
+            return false;
+        }
+        let comparator = self.cursor.txn.comparator();
+        let beyond = if self.reverse { Ordering::Less } else { Ordering::Greater };
+        match &self.stop {
+            Bound::Unbounded => true,
+            Bound::Included(stop) => comparator.compare(key, stop) != beyond,
+            Bound::Excluded(stop) => comparator.compare(key, stop) == beyond.reverse(),
+        }
+    }
+}
+
+impl<'t> Iterator for Iter<'t> {
+    type Item = Result<Entry<'t>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = match self.pending.take()? {
+            Ok(entry) => entry,
+            Err(err) => return Some(Err(err)),
+        };
+        if !self.in_range(entry.0) {
+            return None;
+        }
+        // an error moving past this entry is reported on the following call
+        let step = if self.reverse { self.cursor.move_prev() } else { self.cursor.move_next() };
+        self.pending = step.transpose();
+        Some(Ok(entry))
+    }
+}
@@ -1,18 +1,57 @@
-use crate::page::{BRANCH_ELEMENT_SIZE, BranchElement, LEAF_ELEMENT_SIZE, LeafElement, PAGE_HEADER_SIZE, Page, PageError, PageReader, PageType};
+use crate::page::{self, DecodedPages, PAGE_HEADER_SIZE, Page, PageCache, PageError, PageReader, PageType, VerifiedPages};
+use crate::checksum::checksum;
+use crate::batch::{Batcher, DEFAULT_MAX_BATCH_DELAY, DEFAULT_MAX_BATCH_SIZE};
+use crate::btree::{BTreeError, WriteTxn, DEFAULT_FILL_PERCENT};
+use crate::comparator::{Bytewise, Comparator, MAX_COMPARATOR_NAME};
+use crate::compression::{self, Compression};
+use crate::cursor::{Cursor, Iter};
+use crate::encryption::{self, Cipher, KEY_CHECK_SIZE, KEY_SIZE};
+use crate::freelist;
+use crate::journal;
+use crate::merge::MergeOperator;
+use crate::order;
 use crate::search;
-use std::fs::File;
-use std::io::{self, Seek, Write};
+use crate::stats::{self, TreeStats};
+use crate::storage::{FileStorage, MemoryStorage, Storage};
+use std::io;
 use std::path::Path;
-use std::sync::{RwLock, RwLockReadGuard, Mutex};
-use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::{Arc, RwLock, RwLockReadGuard, Mutex};
 use std::fmt;
-use memmap2::{MmapMut, MmapOptions};
-use zerocopy::{FromBytes, IntoBytes, Immutable, KnownLayout};
-
-pub const PAGE_SIZE: usize = 4096;
+use std::ops::{Bound, RangeBounds, RangeFull};
+use std::time::Duration;
+use zerocopy::little_endian::{U32, U64};
+use zerocopy::{FromBytes, IntoBytes, Immutable, KnownLayout, Unaligned};
+
+/// Page size of new databases unless [`DbOptions::page_size`] says otherwise.
+pub const DEFAULT_PAGE_SIZE: usize = 4096;
+pub const MIN_PAGE_SIZE: usize = 4096;
+/// Element offsets within a page body are u16, which caps pages at 64 KiB.
+pub const MAX_PAGE_SIZE: usize = 65536;
 pub const HEADER_SIZE: usize = std::mem::size_of::<Header>();
 const MAGIC: u32 = 0x73796E63;
-const VERSION: u32 = 1;
+// 2: against version 1, the layout of the original crate,
+//    leaf pages carry next/prev sibling links in a 32 byte page header
+//    two checksummed header slots naming a redo journal, see journal.rs
+//    every page carries a CRC32C of its contents in the page header
+//    every multi-byte field little-endian, marked by FLAG_LITTLE_ENDIAN
+//    branch elements carry subtree key counts when FLAG_ORDER_STATISTICS is set
+//    leaves of a database with a codec stored compressed in runs of pages, see compression.rs
+//    a key check value in the header, and page bodies encrypted when it is set, see encryption.rs
+pub(crate) const VERSION: u32 = 2;
+// page 0 holds two header slots, written alternately by successive commits. They sit
+// at the same offsets whatever the page size, so the header is found before it is known
+const HEADER_SLOT_SIZE: usize = MIN_PAGE_SIZE / 2;
+// branch elements hold the key count of their child's subtree
+const FLAG_ORDER_STATISTICS: u32 = 1;
+// headers, pages and elements are little-endian whatever the host; set on every file
+const FLAG_LITTLE_ENDIAN: u32 = 2;
+// page bodies are encrypted under the key the header's key check value was made with
+const FLAG_ENCRYPTED: u32 = 4;
+// flags a file must carry for this build to read it
+const REQUIRED_FLAGS: u32 = FLAG_LITTLE_ENDIAN;
+// bits 8 to 15 of the flags hold the Compression leaves are written with
+const CODEC_SHIFT: u32 = 8;
 
 #[derive(Debug)]
 pub enum DbError {
@@ -21,7 +60,21 @@ pub enum DbError {
     InvalidMagic { found: u32, expected: u32 },
     FileTooSmall { size: usize, required: usize },
     PageOutOfBounds { page_id: u64, file_size: usize },
-    PageFormat,
+    PageFormat { page_id: u64, index: Option<usize> },
+    ComparatorMismatch { stored: String, requested: String },
+    InvalidComparatorName { name: String, max_len: usize },
+    UnsupportedVersion { found: u32, supported: u32 },
+    MissingFormatFlags { flags: u32, required: u32 },
+    UnsupportedPageSize { found: u32, min: u32, max: u32 },
+    UnsupportedCodec { codec: u8 },
+    EncryptionUnsupported,
+    MissingKey,
+    WrongKey,
+    NotEncrypted,
+    Unauthenticated { page_id: u64 },
+    HeaderChecksum { expected: u64, found: u64 },
+    Corrupted { page_id: u64, expected: u32, found: u32 },
+    Poisoned,
 }
 
 impl fmt::Display for DbError {
@@ -38,8 +91,51 @@ impl fmt::Display for DbError {
             DbError::PageOutOfBounds { page_id, file_size } => {
                 write!(f, "Page {} out of bounds (file size: {})", page_id, file_size)
             }
-            DbError::PageFormat => {
-                write!(f, "Failed to parse page structure")
+            DbError::PageFormat { page_id, index: Some(index) } => {
+                write!(f, "Malformed element {} on page {}", index, page_id)
+            }
+            DbError::PageFormat { page_id, index: None } => {
+                write!(f, "Malformed page {}", page_id)
+            }
+            DbError::ComparatorMismatch { stored, requested } => {
+                write!(f, "Database was created with comparator {:?}, opened with {:?}", stored, requested)
+            }
+            DbError::InvalidComparatorName { name, max_len } => {
+                write!(f, "Comparator name {:?} must be 1 to {} bytes with no NUL", name, max_len)
+            }
+            DbError::UnsupportedVersion { found, supported } if found < supported => {
+                write!(f, "File format version {} predates version {}, convert it with `rbolt upgrade`", found, supported)
+            }
+            DbError::UnsupportedVersion { found, supported } => {
+                write!(f, "Unsupported file format version {}, this build reads version {}", found, supported)
+            }
+            DbError::MissingFormatFlags { flags, required } => {
+                write!(f, "Unsupported file format flags 0x{:x}, this build requires 0x{:x}", flags, required)
+            }
+            DbError::UnsupportedPageSize { found, min, max } => {
+                write!(f, "Unsupported page size {}, must be a power of two from {} to {}", found, min, max)
+            }
+            DbError::UnsupportedCodec { codec } => match Compression::from_id(*codec) {
+                Some(compression) => write!(f, "Pages are compressed with {:?}, which this build was compiled without", compression),
+                None => write!(f, "Unknown compression codec {}", codec),
+            },
+            DbError::EncryptionUnsupported => {
+                write!(f, "Encryption needs the `encryption` feature, which this build was compiled without")
+            }
+            DbError::MissingKey => write!(f, "Database is encrypted, open it with its key"),
+            DbError::WrongKey => write!(f, "Wrong key, it doesn't match the key check value of the database"),
+            DbError::NotEncrypted => write!(f, "Database is not encrypted, open it without a key"),
+            DbError::Unauthenticated { page_id } => {
+                write!(f, "Page {} failed authentication, it was altered or written under another key", page_id)
+            }
+            DbError::HeaderChecksum { expected, found } => {
+                write!(f, "No intact header: checksum 0x{:016x}, expected 0x{:016x}", found, expected)
+            }
+            DbError::Corrupted { page_id, expected, found } => {
+                write!(f, "Page {} is corrupted: checksum 0x{:08x}, expected 0x{:08x}", page_id, found, expected)
+            }
+            DbError::Poisoned => {
+                write!(f, "A commit failed after its header was written, reopen the database to recover it")
             }
         }
     }
@@ -59,58 +155,409 @@ impl From<PageError> for DbError {
     }
 }
 
-type Result<T> = std::result::Result<T, DbError>;
+pub(crate) type Result<T> = std::result::Result<T, DbError>;
 
 #[repr(C)]
-#[derive(Clone, Copy, FromBytes, IntoBytes, KnownLayout, Immutable)]
-struct Header {
-    magic: u32,
-    version: u32,
-    page_size: u32,
-    _padding: u32,  // Explicit padding to align to 8 bytes
-
-    root_page_id: u64, // Location of Root Page. always 0 but u64 for consistent sizing
-    free_list_page_id: u64, //Location of the Free List Page. always 1 but u64 for consistent sizing
-
-    highest_page_id: u64, //highest allocated page ID
-    tx_id: u64, //transaction id
+#[derive(Clone, Copy, FromBytes, IntoBytes, KnownLayout, Immutable, Unaligned)]
+pub(crate) struct Header {
+    magic: U32,
+    version: U32,
+    page_size: U32,
+    flags: U32, // FLAG_* bits fixed when the file is created
+
+    root_page_id: U64, // Location of Root Page. always 0 but u64 for consistent sizing
+    free_list_page_id: U64, //Location of the Free List Page. always 1 but u64 for consistent sizing
+
+    highest_page_id: U64, //highest allocated page ID
+    tx_id: U64, //transaction id
+
+    comparator: [u8; MAX_COMPARATOR_NAME], // name of the key comparator, zero padded. all zero = bytewise
+
+    journal_page_id: U64, // first page of the redo journal of the commit that wrote this header
+    journal_count: U64, // page images in the journal, 0 for none
+    journal_checksum: U64,
+    key_check: [u8; KEY_CHECK_SIZE], // of the key pages are encrypted with, zero if they aren't
+    checksum: U64, // of every byte before it
 }
 
 
 impl Header {
-    fn new(page_size: u32) -> Self {
+    fn new(page_size: u32, comparator: &dyn Comparator, flags: u32, key_check: [u8; KEY_CHECK_SIZE]) -> Self {
+        let mut name = [0u8; MAX_COMPARATOR_NAME];
+        name[..comparator.name().len()].copy_from_slice(comparator.name().as_bytes());
         Header {
-            magic: MAGIC,
-            version: VERSION,
-            page_size,
-            _padding: 0,
-            root_page_id: 0,
-            free_list_page_id: 1, // Free list on page 1
-            highest_page_id: 2,   // Highest allocated page ID - start at 2
-            tx_id: 0,
+            magic: MAGIC.into(),
+            version: VERSION.into(),
+            page_size: page_size.into(),
+            flags: flags.into(),
+            root_page_id: 2.into(),
+            free_list_page_id: 1.into(), // Free list on page 1
+            highest_page_id: 2.into(),   // Highest allocated page ID - start at 2
+            tx_id: 0.into(),
+            comparator: name,
+            journal_page_id: 0.into(),
+            journal_count: 0.into(),
+            journal_checksum: 0.into(),
+            key_check,
+            checksum: 0.into(),
+        }
+    }
+
+    // The header in the slot at `offset`. Version 1 headers end at the comparator name,
+    // and the zeros after it read as no journal and no key check value.
+    fn read_slot(bytes: &[u8], offset: usize) -> Self {
+        Header::read_from_bytes(&bytes[offset..offset + HEADER_SIZE]).unwrap()
+    }
+
+    fn compute_checksum(&self) -> u64 {
+        checksum(&self.as_bytes()[..HEADER_SIZE - 8])
+    }
+
+    fn seal(&mut self) {
+        self.checksum.set(self.compute_checksum());
+    }
+
+    // Commits alternate between the slots, so a torn header write leaves the previous one intact.
+    fn slot_offset(&self) -> usize {
+        (self.tx_id.get() % 2) as usize * HEADER_SLOT_SIZE
+    }
+
+    pub(crate) fn root_page_id(&self) -> u64 {
+        self.root_page_id.get()
+    }
+
+    pub(crate) fn highest_page_id(&self) -> u64 {
+        self.highest_page_id.get()
+    }
+
+    pub(crate) fn page_size(&self) -> usize {
+        self.page_size.get() as usize
+    }
+
+    pub(crate) fn order_statistics(&self) -> bool {
+        self.flags.get() & FLAG_ORDER_STATISTICS != 0
+    }
+
+    fn codec(&self) -> u8 {
+        (self.flags.get() >> CODEC_SHIFT) as u8
+    }
+
+    // checked by read_header, so files this build opens always name a known codec
+    pub(crate) fn compression(&self) -> Compression {
+        Compression::from_id(self.codec()).unwrap_or_default()
+    }
+
+    pub(crate) fn encrypted(&self) -> bool {
+        self.flags.get() & FLAG_ENCRYPTED != 0
+    }
+
+    // bytes of a page left for its header and body
+    pub(crate) fn usable_size(&self) -> usize {
+        encryption::usable_size(self.page_size(), self.encrypted())
+    }
+
+    pub(crate) fn leaf_size(&self) -> usize {
+        compression::leaf_size(self.usable_size(), self.compression())
+    }
+
+    // The key check value of an encrypted file.
+    pub(crate) fn key_check(&self) -> Option<[u8; KEY_CHECK_SIZE]> {
+        self.encrypted().then_some(self.key_check)
+    }
+
+    fn comparator_name(&self) -> String {
+        let len = self.comparator.iter().position(|&b| b == 0).unwrap_or(MAX_COMPARATOR_NAME);
+        if len == 0 {
+            // files from before comparators were persisted are always bytewise
+            return Bytewise.name().to_string();
+        }
+        String::from_utf8_lossy(&self.comparator[..len]).into_owned()
+    }
+}
+
+/// A file's pages as of its newest intact header, see [`Db::snapshot`].
+pub(crate) struct Snapshot {
+    pub(crate) image: Vec<u8>,
+    pub(crate) version: u32,
+    pub(crate) page_size: usize,
+    pub(crate) root_page_id: u64,
+    pub(crate) highest_page_id: u64,
+    pub(crate) tx_id: u64,
+    pub(crate) comparator: String,
+    pub(crate) order_statistics: bool,
+    pub(crate) compression: Compression,
+    pub(crate) key_check: Option<[u8; KEY_CHECK_SIZE]>,
+}
+
+/// Settings chosen when opening a database.
+#[derive(Clone)]
+pub struct DbOptions {
+    pub comparator: Arc<dyn Comparator>,
+    /// Used by [`WriteTxn::merge`], which fails when none is set.
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// Fraction of a page kept on the left when it splits, see [`WriteTxn::set_fill_percent`](crate::btree::WriteTxn::set_fill_percent).
+    /// Appends past the last key of a page always leave it full.
+    pub fill_percent: f64,
+    /// Calls to [`Db::batch`] run together once this many are queued...
+    pub max_batch_size: usize,
+    /// ...or once the first of them has waited this long.
+    pub max_batch_delay: Duration,
+    /// Keep the key count of every subtree in its parent branch, so [`ReadTxn::rank`],
+    /// [`ReadTxn::nth`] and [`ReadTxn::count_range`] walk a single path instead of scanning.
+    /// Writes then dirty every branch on their path. Only applies when the file is
+    /// created; an existing file keeps the mode it was created with.
+    pub order_statistics: bool,
+    /// Bytes per page, a power of two from [`MIN_PAGE_SIZE`] to [`MAX_PAGE_SIZE`]. Larger
+    /// pages make for shallower trees and room for larger values. Only applies when the
+    /// file is created; an existing file keeps the page size it was created with.
+    pub page_size: usize,
+    /// Compress leaf pages as they are committed, see [`Compression`]. Leaves then hold
+    /// [`LEAF_SPAN`](crate::compression::LEAF_SPAN) pages of entries, so values several
+    /// pages long fit too. Only applies when the file is created; an existing file keeps
+    /// the codec it was created with.
+    pub compression: Compression,
+    /// Encrypt page bodies with this key, see [`encryption`](crate::encryption). A file
+    /// created with a key can only be opened with the same one, and a file created without
+    /// can't be opened with one. Needs the `encryption` feature.
+    pub key: Option<[u8; KEY_SIZE]>,
+}
+
+impl Default for DbOptions {
+    fn default() -> Self {
+        DbOptions {
+            comparator: Arc::new(Bytewise),
+            merge_operator: None,
+            fill_percent: DEFAULT_FILL_PERCENT,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            max_batch_delay: DEFAULT_MAX_BATCH_DELAY,
+            order_statistics: false,
+            page_size: DEFAULT_PAGE_SIZE,
+            compression: Compression::None,
+            key: None,
         }
     }
 }
 
 
 pub struct ReadTxn<'a> {
-    mmap_guard: RwLockReadGuard<'a, MmapMut>,
+    storage: RwLockReadGuard<'a, Box<dyn Storage>>,
     header: Header,
+    comparator: &'a dyn Comparator,
+    verified: &'a VerifiedPages,
+    decoded: DecodedPages<'a>,
 }
 
 impl<'a> ReadTxn<'a> {
-    pub fn get_page(&self, page_id: u64) -> Result<&'a Page> {
-        Ok(self.mmap_guard.get_page(page_id, self.header.highest_page_id)?)
+    pub fn get_page(&self, page_id: u64) -> Result<&Page> {
+        self.storage.get_page(page_id, self.header.highest_page_id(), self.page_size())?;
+        Ok(page::parse(page_id, self.page_bytes(page_id)?)?.0)
     }
     pub fn root_page_id(&self) -> u64 {
-        self.header.root_page_id
+        self.header.root_page_id.get()
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.header.page_size()
+    }
+
+    /// Bytes per leaf page before compression: the page size, less the room encryption
+    /// needs if the database is encrypted, unless leaves are compressed.
+    pub fn leaf_size(&self) -> usize {
+        self.header.leaf_size()
+    }
+
+    pub(crate) fn page_bytes(&self, page_id: u64) -> Result<&[u8]> {
+        page::read_page(&**self.storage, page_id, self.page_size(), self.verified, &self.decoded)
+    }
+
+    pub(crate) fn comparator(&self) -> &'a dyn Comparator {
+        self.comparator
     }
 
     pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        self.get_recursive(self.header.root_page_id, key)
+        self.get_recursive(self.header.root_page_id.get(), key, 1)
+    }
+
+    /// Looks up every key in one walk of the tree, returning the values in the order
+    /// of `keys`. The keys are visited sorted, and each climbs back up only as far as
+    /// the lowest branch page whose key range still holds it.
+    pub fn get_many(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        let comparator = self.comparator;
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|&a, &b| comparator.compare(keys[a], keys[b]));
+
+        let mut values = vec![None; keys.len()];
+        // pages from the root to the current leaf, each with the exclusive upper bound of its keys
+        let mut path: Vec<(u64, Option<&[u8]>)> = Vec::new();
+        for index in order {
+            let key = keys[index];
+            while let Some(&(_, Some(upper))) = path.last() {
+                if comparator.compare(key, upper) == std::cmp::Ordering::Less {
+                    break;
+                }
+                path.pop();
+            }
+            if path.is_empty() {
+                path.push((self.header.root_page_id.get(), None));
+            }
+
+            loop {
+                let (page_id, upper) = *path.last().unwrap();
+                if path.len() > page::MAX_DEPTH {
+                    return Err(DbError::PageFormat { page_id, index: None });
+                }
+                let (page, page_body) = page::parse(page_id, self.page_bytes(page_id)?)?;
+                let count = page.count.get() as usize;
+                match page.page_type {
+                    t if t == PageType::Branch as u8 => {
+                        let (result_index, found) = search::search_branch_elements(page_body, count, key, comparator)
+                            .map_err(|err| DbError::PageFormat { page_id, index: Some(err.index) })?;
+                        let child_index = if found { result_index } else { result_index.saturating_sub(1) };
+                        let (_, child_id) = page::branch_entry(page_body, child_index)
+                            .ok_or(DbError::PageFormat { page_id, index: Some(child_index) })?;
+                        let child_upper = match child_index < count {
+                            true => Some(page::branch_entry(page_body, child_index + 1)
+                                .ok_or(DbError::PageFormat { page_id, index: Some(child_index + 1) })?.0),
+                            false => upper,
+                        };
+                        path.push((child_id, child_upper));
+                    }
+                    t if t == PageType::Leaf as u8 => {
+                        let (found_index, found) = search::search_leaf_elements(page_body, count, key, comparator)
+                            .map_err(|err| DbError::PageFormat { page_id, index: Some(err.index) })?;
+                        if found {
+                            let (_, value) = page::leaf_entry(page_body, found_index)
+                                .ok_or(DbError::PageFormat { page_id, index: Some(found_index) })?;
+                            values[index] = Some(value.to_vec());
+                        }
+                        break;
+                    }
+                    // nothing has been written yet
+                    _ => break,
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    pub fn cursor(&self) -> Cursor<'_> {
+        Cursor::new(self)
+    }
+
+    /// Iterates over the keys inside `range` in comparator order.
+    pub fn range<'k, R: RangeBounds<&'k [u8]>>(&self, range: R) -> Result<Iter<'_>> {
+        Iter::new(self.cursor(), range.start_bound().cloned(), range.end_bound().cloned())
+    }
+
+    pub fn iter(&self) -> Result<Iter<'_>> {
+        self.range::<RangeFull>(..)
+    }
+
+    /// Iterates over the keys inside `range` from the largest down.
+    pub fn range_rev<'k, R: RangeBounds<&'k [u8]>>(&self, range: R) -> Result<Iter<'_>> {
+        Iter::new_rev(self.cursor(), range.start_bound().cloned(), range.end_bound().cloned())
+    }
+
+    pub fn iter_rev(&self) -> Result<Iter<'_>> {
+        self.range_rev::<RangeFull>(..)
+    }
+
+    /// The entry with the smallest key.
+    pub fn first(&self) -> Result<Option<(&[u8], &[u8])>> {
+        self.cursor().first()
     }
 
-    fn get_recursive(&self, page_id: u64, key: &[u8]) -> Result<Option<Vec<u8>>> {
+    /// The entry with the largest key.
+    pub fn last(&self) -> Result<Option<(&[u8], &[u8])>> {
+        self.cursor().last()
+    }
+
+    /// The entry with the largest key less than or equal to `key`.
+    pub fn seek_for_prev(&self, key: &[u8]) -> Result<Option<(&[u8], &[u8])>> {
+        self.cursor().seek_for_prev(key)
+    }
+
+    /// Iterates over the keys starting with `prefix`. Keys sharing a prefix must sort
+    /// next to each other, which holds for the bytewise comparator.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<Iter<'_>> {
+        Iter::with_prefix(self.cursor(), prefix)
+    }
+
+    pub fn count_prefix(&self, prefix: &[u8]) -> Result<u64> {
+        let mut count = 0;
+        for entry in self.scan_prefix(prefix)? {
+            entry?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    pub fn first_with_prefix(&self, prefix: &[u8]) -> Result<Option<(&[u8], &[u8])>> {
+        self.scan_prefix(prefix)?.next().transpose()
+    }
+
+    /// The largest key starting with `prefix`, found by seeking to the first key past the prefix.
+    pub fn last_with_prefix(&self, prefix: &[u8]) -> Result<Option<(&[u8], &[u8])>> {
+        let mut cursor = self.cursor();
+        let candidate = match prefix_successor(prefix) {
+            Some(successor) => match cursor.seek(&successor)? {
+                Some(_) => cursor.move_prev()?,
+                None => cursor.last()?,
+            },
+            // every key from the prefix on starts with it
+            None => cursor.last()?,
+        };
+        Ok(candidate.filter(|(key, _)| key.starts_with(prefix)))
+    }
+
+    /// Whether the tree keeps subtree key counts, see [`DbOptions::order_statistics`].
+    pub fn has_order_statistics(&self) -> bool {
+        self.header.order_statistics()
+    }
+
+    /// Number of keys inside `range`.
+    pub fn count_range<'k, R: RangeBounds<&'k [u8]>>(&self, range: R) -> Result<u64> {
+        if !self.has_order_statistics() {
+            return order::count_by_scan(self.range(range)?);
+        }
+        let start = match range.start_bound() {
+            Bound::Included(key) => order::keys_before(self, key, false)?,
+            Bound::Excluded(key) => order::keys_before(self, key, true)?,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(key) => order::keys_before(self, key, true)?,
+            Bound::Excluded(key) => order::keys_before(self, key, false)?,
+            Bound::Unbounded => order::total(self)?,
+        };
+        Ok(end.saturating_sub(start))
+    }
+
+    /// Number of keys that sort before `key`, whether or not `key` itself is stored.
+    pub fn rank(&self, key: &[u8]) -> Result<u64> {
+        match self.has_order_statistics() {
+            true => order::keys_before(self, key, false),
+            false => order::count_by_scan(self.range(..key)?),
+        }
+    }
+
+    /// The entry at position `n` in key order, counting from 0.
+    pub fn nth(&self, n: u64) -> Result<Option<(&[u8], &[u8])>> {
+        match self.has_order_statistics() {
+            true => order::nth(self, n),
+            false => self.iter()?.nth(n as usize).transpose(),
+        }
+    }
+
+    /// Walks the whole tree and reports page counts and how full the pages are.
+    pub fn stats(&self) -> Result<TreeStats> {
+        stats::collect(self)
+    }
+
+    fn get_recursive(&self, page_id: u64, key: &[u8], depth: usize) -> Result<Option<Vec<u8>>> {
+        if depth > page::MAX_DEPTH {
+            return Err(DbError::PageFormat { page_id, index: None });
+        }
         let page = self.get_page(page_id)?;
 
         match page.page_type {
@@ -119,27 +566,22 @@ impl<'a> ReadTxn<'a> {
             }
             t if t == PageType::Branch as u8 => {
                 let child_id = self.find_child_in_branch(page_id, key)?;
-                self.get_recursive(child_id, key)
+                self.get_recursive(child_id, key, depth + 1)
             }
             _ => Ok(None),
         }
     }
 
     fn search_leaf(&self, page_id: u64, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        let page_offset = page_id as usize * PAGE_SIZE;
-        let page_bytes = &self.mmap_guard[page_offset..page_offset + PAGE_SIZE];
-        let (page, page_body) = Page::ref_from_prefix(page_bytes)
-            .map_err(|_| DbError::PageFormat)?;
+        let (page, page_body) = page::parse(page_id, self.page_bytes(page_id)?)?;
 
-        let element_count = page.count as usize;
-        let (index, found) = search::search_leaf_elements(page_body, element_count, key)
-            .map_err(|_| DbError::PageFormat)?;
+        let element_count = page.count.get() as usize;
+        let (index, found) = search::search_leaf_elements(page_body, element_count, key, self.comparator)
+            .map_err(|err| DbError::PageFormat { page_id, index: Some(err.index) })?;
 
         if found {
-            let elem_bytes = &page_body[index*LEAF_ELEMENT_SIZE..(index+1)*LEAF_ELEMENT_SIZE];
-            let elem = LeafElement::ref_from_bytes(elem_bytes)
-                .map_err(|_| DbError::PageFormat)?;
-            let value = &page_body[elem.vptr as usize..(elem.vptr + elem.vsize) as usize];
+            let (_, value) = page::leaf_entry(page_body, index)
+                .ok_or(DbError::PageFormat { page_id, index: Some(index) })?;
             Ok(Some(value.to_vec()))
         } else {
             Ok(None)
@@ -147,14 +589,11 @@ impl<'a> ReadTxn<'a> {
     }
 
     fn find_child_in_branch(&self, page_id: u64, for_key: &[u8]) -> Result<u64> {
-        let page_offset = page_id as usize * PAGE_SIZE;
-        let page_bytes = &self.mmap_guard[page_offset..page_offset + PAGE_SIZE];
-        let (page, page_body) = Page::ref_from_prefix(page_bytes)
-            .map_err(|_| DbError::PageFormat)?;
+        let (page, page_body) = page::parse(page_id, self.page_bytes(page_id)?)?;
 
-        let element_count = page.count as usize;
-        let (result_index, found) = search::search_branch_elements(page_body, element_count, for_key)
-            .map_err(|_| DbError::PageFormat)?;
+        let element_count = page.count.get() as usize;
+        let (result_index, found) = search::search_branch_elements(page_body, element_count, for_key, self.comparator)
+            .map_err(|err| DbError::PageFormat { page_id, index: Some(err.index) })?;
 
         let child_index = if found {
             result_index
@@ -162,227 +601,460 @@ impl<'a> ReadTxn<'a> {
             result_index.saturating_sub(1)
         };
 
-        let elem_bytes = &page_body[child_index*BRANCH_ELEMENT_SIZE..(child_index+1)*BRANCH_ELEMENT_SIZE];
-        let elem = BranchElement::ref_from_bytes(elem_bytes)
-            .map_err(|_| DbError::PageFormat)?;
+        let (_, child_id) = page::branch_entry(page_body, child_index)
+            .ok_or(DbError::PageFormat { page_id, index: Some(child_index) })?;
+
+        Ok(child_id)
+    }
+}
 
-        Ok(elem.page_id)
+// Powers of two keep pages lined up with the pages of the OS and the disk.
+fn check_page_size(page_size: usize) -> Result<()> {
+    if !page_size.is_power_of_two() || !(MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size) {
+        return Err(DbError::UnsupportedPageSize {
+            found: page_size.try_into().unwrap_or(u32::MAX),
+            min: MIN_PAGE_SIZE as u32,
+            max: MAX_PAGE_SIZE as u32,
+        });
     }
+    Ok(())
+}
+
+// The smallest key greater than every key starting with `prefix`: the prefix with its
+// trailing 0xFF bytes dropped and the last remaining byte incremented. None if it is all 0xFF.
+fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let end = prefix.iter().rposition(|&b| b != 0xFF)?;
+    let mut successor = prefix[..=end].to_vec();
+    successor[end] += 1;
+    Some(successor)
 }
 
 pub struct Db {
-    mmap: RwLock<MmapMut>,
+    storage: RwLock<Box<dyn Storage>>,
     write_lock: Mutex<()>,
     header: RwLock<Header>,
-    file: UnsafeCell<File>,
+    comparator: Arc<dyn Comparator>,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    fill_percent: f64,
+    batcher: Batcher,
+    verified: VerifiedPages,
+    cipher: Option<Cipher>,
+    cache: PageCache,
+    // set when a commit fails once its header may be on disk: the pages in storage no
+    // longer match `header`, and only reopening, which replays the journal, sorts them out
+    poisoned: AtomicBool,
 }
 
-// Db can be safely sent between threads
-// - mmap: RwLock
-// - write_lock: Mutex
-// - header: RwLock
-// - file: written while holding mmap write lock
-unsafe impl Send for Db {}
-unsafe impl Sync for Db {}
-
 impl Db {
     pub fn open(path: &Path) -> Result<Self> {
-        let mut file = std::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(path)?;
+        Self::open_with_options(path, DbOptions::default())
+    }
 
-        let file_len = file.metadata()?.len() as usize;
+    pub fn open_with_options(path: &Path, options: DbOptions) -> Result<Self> {
+        Self::open_with_storage(Box::new(FileStorage::open(path)?), options)
+    }
 
-        if file_len < PAGE_SIZE * 2 {
-            file.set_len(PAGE_SIZE as u64 * 2)?;
+    /// Opens the encrypted database at `path`, or creates one encrypted with `key`.
+    /// Fails with [`DbError::WrongKey`] if the file was encrypted with another key.
+    pub fn open_with_key(path: &Path, key: [u8; KEY_SIZE]) -> Result<Self> {
+        Self::open_with_options(path, DbOptions { key: Some(key), ..DbOptions::default() })
+    }
 
-            let default_header = Header::new(PAGE_SIZE as u32);
-            Self::write_header(&mut file, &default_header)?;
+    /// A database that lives only in memory, for tests and ephemeral caches.
+    pub fn open_in_memory() -> Result<Self> {
+        Self::open_in_memory_with_options(DbOptions::default())
+    }
+
+    pub fn open_in_memory_with_options(options: DbOptions) -> Result<Self> {
+        Self::open_with_storage(Box::new(MemoryStorage::new()), options)
+    }
+
+    /// Opens the database held by `storage`, writing a fresh header if it is empty.
+    pub fn open_with_storage(mut storage: Box<dyn Storage>, options: DbOptions) -> Result<Self> {
+        let comparator = options.comparator;
+        let name = comparator.name();
+        if name.is_empty() || name.len() > MAX_COMPARATOR_NAME || name.contains('\0') {
+            return Err(DbError::InvalidComparatorName {
+                name: comparator.name().to_string(),
+                max_len: MAX_COMPARATOR_NAME,
+            });
         }
 
-        let initial_mmap = unsafe {
-            MmapOptions::new()
-                .map_mut(&file)
-                .expect("Failed to create mutable memory map")
-        };
+        if storage.is_empty() {
+            check_page_size(options.page_size)?;
+            if !options.compression.is_available() {
+                return Err(DbError::UnsupportedCodec { codec: options.compression as u8 });
+            }
+            let cipher = options.key.as_ref().map(Cipher::new).transpose()?;
+            let key_check = match &cipher {
+                Some(cipher) => cipher.key_check()?,
+                None => [0; KEY_CHECK_SIZE],
+            };
+            let flags = FLAG_LITTLE_ENDIAN
+                | if options.order_statistics { FLAG_ORDER_STATISTICS } else { 0 }
+                | if cipher.is_some() { FLAG_ENCRYPTED } else { 0 }
+                | (options.compression as u32) << CODEC_SHIFT;
+            let header = Header::new(options.page_size as u32, comparator.as_ref(), flags, key_check);
+            Self::create(storage.as_mut(), header, cipher.as_ref())?;
+        }
 
-        let header = Self::read_header(&initial_mmap)?;
+        let header = Self::read_header(storage.bytes())?;
+        Self::recover(storage.as_mut(), &header)?;
 
-        if header.magic != MAGIC {
-            return Err(DbError::InvalidMagic {
-                found: header.magic,
-                expected: MAGIC,
+        let stored = header.comparator_name();
+        if stored != comparator.name() {
+            return Err(DbError::ComparatorMismatch {
+                stored,
+                requested: comparator.name().to_string(),
             });
         }
+        let cipher = match (header.key_check(), &options.key) {
+            (Some(key_check), key) => Some(Cipher::unlock(&key_check, key.as_ref())?),
+            (None, Some(_)) => return Err(DbError::NotEncrypted),
+            (None, None) => None,
+        };
 
         Ok(Db {
-            mmap: RwLock::new(initial_mmap),
+            storage: RwLock::new(storage),
             write_lock: Mutex::new(()),
             header: RwLock::new(header),
-            file: UnsafeCell::new(file),
+            comparator,
+            merge_operator: options.merge_operator,
+            fill_percent: options.fill_percent,
+            batcher: Batcher::new(options.max_batch_size, options.max_batch_delay),
+            verified: VerifiedPages::default(),
+            cipher,
+            cache: PageCache::default(),
+            poisoned: AtomicBool::new(false),
         })
-
     }
 
-    fn write_header(file: &mut File, header: &Header) -> Result<()> {
-        file.seek(io::SeekFrom::Start(0))?;
-        let mut writer = io::BufWriter::new(file);
-        writer.write_all(header.as_bytes())?;
-        writer.flush()?;
+    // Lays out the header, an empty free list page and an empty root leaf, encrypted
+    // with `cipher` if the database is.
+    fn create(storage: &mut dyn Storage, mut header: Header, cipher: Option<&Cipher>) -> Result<()> {
+        let page_size = header.page_size();
+        let page = Page {
+            id: 2.into(),
+            page_type: PageType::Leaf as u8,
+            codec: 0,
+            count: 0.into(),
+            checksum: 0.into(),
+            next: 0.into(),
+            prev: 0.into(),
+        };
+        // the root leaf is as large as any other, so a compressed database stores it as a run
+        let mut leaf = vec![0u8; header.leaf_size()];
+        leaf[..PAGE_HEADER_SIZE].copy_from_slice(page.as_bytes());
+        let mut highest_page_id = header.highest_page_id();
+        let mut pages = freelist::write(&mut Vec::new(), header.usable_size());
+        pages.extend(compression::pack(2, &leaf, header.compression(), header.usable_size(), &mut || {
+            highest_page_id += 1;
+            highest_page_id
+        }));
+        header.highest_page_id.set(highest_page_id);
+
+        storage.grow((highest_page_id as usize + 1) * page_size)?; // 0, 1, 2
+        if let Some(cipher) = cipher {
+            cipher.encrypt(header.tx_id.get(), page_size, pages.iter_mut().map(|(page_id, page_bytes)| (*page_id, page_bytes)))?;
+        }
+        for (_, page_bytes) in &mut pages {
+            page::seal_page(page_bytes);
+        }
+        let pages: Vec<(u64, &[u8])> = pages.iter().map(|(page_id, page_bytes)| (*page_id, page_bytes.as_slice())).collect();
+        storage.write_pages(page_size, &pages)?;
+        storage.sync()?;
+
+        header.seal();
+        storage.write_at(header.slot_offset(), header.as_bytes())?;
+        storage.sync()?;
+        println!("   [OK] Created database of {} byte pages with an empty root leaf on page 2", page_size);
         Ok(())
     }
 
-    fn read_header(mmap: &MmapMut) -> Result<Header> {
-        if mmap.len() < HEADER_SIZE {
+    // The header of a file this build opens, at the current version and with the current flags.
+    fn read_header(bytes: &[u8]) -> Result<Header> {
+        let header = Self::read_any_header(bytes)?;
+        if header.version.get() != VERSION {
+            return Err(DbError::UnsupportedVersion {
+                found: header.version.get(),
+                supported: VERSION,
+            });
+        }
+        if header.flags.get() & REQUIRED_FLAGS != REQUIRED_FLAGS {
+            return Err(DbError::MissingFormatFlags {
+                flags: header.flags.get(),
+                required: REQUIRED_FLAGS,
+            });
+        }
+        if !Compression::from_id(header.codec()).is_some_and(Compression::is_available) {
+            return Err(DbError::UnsupportedCodec { codec: header.codec() });
+        }
+        Ok(header)
+    }
+
+    // The header of a file at any version up to the current one: the intact slot with the
+    // highest tx_id, or for version 1, which kept one header without a checksum, slot 0.
+    // Version 1 files are read as little-endian, the byte order of every host that wrote them.
+    fn read_any_header(bytes: &[u8]) -> Result<Header> {
+        if bytes.len() < MIN_PAGE_SIZE {
             return Err(DbError::FileTooSmall {
-                size: mmap.len(),
-                required: HEADER_SIZE,
+                size: bytes.len(),
+                required: MIN_PAGE_SIZE,
+            });
+        }
+
+        let slots: Vec<Header> = [0, HEADER_SLOT_SIZE].iter()
+            .map(|&offset| Header::read_slot(bytes, offset))
+            .collect();
+
+        let newest = slots.iter()
+            .filter(|slot| slot.magic.get() == MAGIC && slot.checksum.get() == slot.compute_checksum())
+            .max_by_key(|slot| slot.tx_id.get());
+        // with no intact slot, the first one (written when the file was created) tells what the file is
+        let header = newest.unwrap_or(&slots[0]);
+        if header.magic.get() != MAGIC {
+            return Err(DbError::InvalidMagic {
+                found: header.magic.get(),
+                expected: MAGIC,
             });
         }
+        let header = match header.version.get() {
+            1 => *header,
+            VERSION => match newest {
+                Some(&header) => header,
+                None => return Err(DbError::HeaderChecksum {
+                    expected: header.compute_checksum(),
+                    found: header.checksum.get(),
+                }),
+            },
+            found => return Err(DbError::UnsupportedVersion { found, supported: VERSION }),
+        };
+        check_page_size(header.page_size())?;
+        Ok(header)
+    }
 
-        let header_bytes = &mmap[..HEADER_SIZE];
-        let header = Header::ref_from_bytes(header_bytes)
-            .map_err(|_| DbError::FileTooSmall {
-                size: mmap.len(),
-                required: HEADER_SIZE,
-            })?;
-        Ok(*header)
+    // Finishes a commit cut short after its header was written, then drops its journal.
+    fn recover(storage: &mut dyn Storage, header: &Header) -> Result<()> {
+        if header.journal_count.get() > 0 {
+            let journal = journal::read(storage.bytes(), header.page_size(), header.journal_page_id.get(), header.journal_count.get() as usize, header.journal_checksum.get());
+            if let Some(pages) = journal {
+                let pages: Vec<(u64, &[u8])> = pages.iter().map(|(page_id, page_bytes)| (*page_id, page_bytes.as_slice())).collect();
+                storage.write_pages(header.page_size(), &pages)?;
+                storage.sync()?;
+            }
+        }
+        storage.truncate((header.highest_page_id.get() as usize + 1) * header.page_size())?;
+        Ok(())
+    }
+
+    // The pages of `bytes` as the newest intact header left them, with the journal it
+    // names replayed over a copy so the file itself is never written.
+    pub(crate) fn snapshot(bytes: &[u8]) -> Result<Snapshot> {
+        Ok(Self::snapshot_of(bytes, Self::read_header(bytes)?))
+    }
+
+    // Like `snapshot`, for a file at any version up to the current one.
+    pub(crate) fn snapshot_any_version(bytes: &[u8]) -> Result<Snapshot> {
+        Ok(Self::snapshot_of(bytes, Self::read_any_header(bytes)?))
+    }
+
+    fn snapshot_of(bytes: &[u8], header: Header) -> Snapshot {
+        let page_size = header.page_size();
+        let mut image = bytes.to_vec();
+        // journals arrived with version 2; before that the fields were never written
+        if header.version.get() >= 2 && header.journal_count.get() > 0 {
+            let journal = journal::read(bytes, page_size, header.journal_page_id.get(), header.journal_count.get() as usize, header.journal_checksum.get());
+            for (page_id, page_bytes) in journal.unwrap_or_default() {
+                let offset = page_id as usize * page_size;
+                if image.len() < offset + page_size {
+                    image.resize(offset + page_size, 0);
+                }
+                image[offset..offset + page_size].copy_from_slice(&page_bytes);
+            }
+        }
+        Snapshot {
+            image,
+            version: header.version.get(),
+            page_size,
+            root_page_id: header.root_page_id.get(),
+            highest_page_id: header.highest_page_id.get(),
+            tx_id: header.tx_id.get(),
+            comparator: header.comparator_name(),
+            order_statistics: header.order_statistics(),
+            compression: header.compression(),
+            key_check: header.key_check(),
+        }
+    }
+
+    /// Bytes per page, fixed when the file was created.
+    pub fn page_size(&self) -> usize {
+        self.header.read().unwrap().page_size()
+    }
+
+    /// Codec leaf pages are compressed with, fixed when the file was created.
+    pub fn compression(&self) -> Compression {
+        self.header.read().unwrap().compression()
+    }
+
+    /// Whether page bodies are encrypted, fixed when the file was created.
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
     }
 
     pub fn begin_read_transaction(&self) -> Result<ReadTxn<'_>> {
-        let mmap_guard = self.mmap.read().unwrap();
+        self.check_poisoned()?;
+        let storage = self.storage.read().unwrap();
         let header = *self.header.read().unwrap();
-        println!("   [OK] Read transaction started on database of size {} bytes.", mmap_guard.len());
+        println!("   [OK] Read transaction started on database of size {} bytes.", storage.len());
         Ok(ReadTxn {
-            mmap_guard,
+            storage,
             header,
+            comparator: self.comparator.as_ref(),
+            verified: &self.verified,
+            decoded: DecodedPages::new(&self.cache, self.cipher.as_ref()),
         })
     }
 
-    pub fn commit(&self, dirty_pages: std::collections::HashMap<u64, Vec<u8>>, highest_page_id: u64, root_page_id: u64) -> Result<()> {
-        self.commit_dirty_pages(dirty_pages, highest_page_id, root_page_id)?;
-        Ok(())
+    pub(crate) fn comparator(&self) -> &dyn Comparator {
+        self.comparator.as_ref()
     }
 
-    pub fn begin_write_transaction(&self) -> Result<crate::btree::WriteTxn<'_>> {
-        let write_guard = self.write_lock.lock().unwrap();
-        let needs_init = {
-            let mmap = self.mmap.read().unwrap();
-            let root_offset = 2 * PAGE_SIZE;
-            root_offset >= mmap.len() || mmap[root_offset] == 0
-        };
-
-        if needs_init {
-            self.initialize_root_page()?;
-        }
+    pub(crate) fn merge_operator(&self) -> Option<&dyn MergeOperator> {
+        self.merge_operator.as_deref()
+    }
 
-        let (root_page_id, highest_page_id) = {
-            let header = self.header.read().unwrap();
-            (header.root_page_id, header.highest_page_id)
-        };
-        let free_list = Vec::new();
-
-        let mmap_guard = self.mmap.read().unwrap();
-
-        Ok(crate::btree::WriteTxn::new(
-            write_guard,
-            mmap_guard,
-            root_page_id,
-            free_list,
-            highest_page_id,
-        ))
-    }
-
-    fn initialize_root_page(&self) -> Result<()> {
-        let mut mmap = self.mmap.write().unwrap();
-        let required_size = 3 * PAGE_SIZE; // 0, 1, 2
-        if mmap.len() < required_size {
-            unsafe {
-                let file = &mut *self.file.get();
-                file.set_len(required_size as u64)?;
-                let new_mmap = MmapMut::map_mut(&*file)?;
-                *mmap = new_mmap;
-            }
-        }
+    pub(crate) fn verified(&self) -> &VerifiedPages {
+        &self.verified
+    }
 
-        let root_offset = 2 * PAGE_SIZE;
-        let page_bytes = &mut mmap[root_offset..root_offset + PAGE_SIZE];
+    pub(crate) fn decoded(&self) -> DecodedPages<'_> {
+        DecodedPages::new(&self.cache, self.cipher.as_ref())
+    }
 
-        let page = Page {
-            id: 2,
-            page_type: PageType::Leaf as u8,
-            _padding: 0,
-            count: 0,
-            overflow: 0,
-        };
+    /// Writes the pages [`WriteTxn::prepare_commit`] handed back. A write transaction begun
+    /// in between would start from the old tree and free list and hand out the same pages,
+    /// so [`WriteTxn::commit`] does both under the write lock.
+    pub fn commit(&self, dirty_pages: std::collections::HashMap<u64, Vec<u8>>, highest_page_id: u64, root_page_id: u64) -> Result<()> {
+        self.commit_dirty_pages(dirty_pages, highest_page_id, root_page_id)?;
+        Ok(())
+    }
 
-        page_bytes[..PAGE_HEADER_SIZE].copy_from_slice(page.as_bytes());
-        page_bytes[PAGE_HEADER_SIZE..].fill(0);
+    /// Builds the tree from entries sorted by the comparator, in one write transaction.
+    /// See [`WriteTxn::append_sorted`](crate::btree::WriteTxn::append_sorted).
+    pub fn bulk_load<I, K, V>(&self, entries: I, fill_percent: f64) -> std::result::Result<u64, BTreeError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let mut wtxn = self.begin_write_transaction()?;
+        let loaded = wtxn.append_sorted(entries, fill_percent)?;
+        wtxn.commit()?;
+        Ok(loaded)
+    }
 
-        let mut header = self.header.write().unwrap();
-        header.root_page_id = 2;
-        header.highest_page_id = 2;
+    /// Runs `func` in a write transaction shared with other concurrent `batch` calls,
+    /// so many small writes cost one commit. Returns once the batch has committed.
+    /// If `func` fails the batch is rolled back and retried without it, then `func`
+    /// runs again alone and its error is returned, so it must be safe to call twice.
+    pub fn batch<F>(&self, func: F) -> std::result::Result<(), BTreeError>
+    where
+        F: Fn(&mut WriteTxn<'_>) -> std::result::Result<(), BTreeError> + Send + Sync + 'static,
+    {
+        self.batcher.submit(self, Arc::new(func))
+    }
 
-        mmap[..HEADER_SIZE].copy_from_slice(header.as_bytes());
-        mmap.flush()?;
+    pub fn begin_write_transaction(&self) -> Result<crate::btree::WriteTxn<'_>> {
+        let write_guard = self.write_lock.lock().unwrap();
+        self.check_poisoned()?;
+        let header = *self.header.read().unwrap();
 
-        println!("   [OK] Initialized root page (page 2) as empty leaf");
-        Ok(())
+        let storage = self.storage.read().unwrap();
+        let mut wtxn = crate::btree::WriteTxn::new(self, write_guard, storage, &header)?;
+        wtxn.set_fill_percent(self.fill_percent);
+        Ok(wtxn)
     }
 
     pub fn commit_write_transaction(&self, new_data: &[u8]) -> Result<()> {
-        let mut mmap_guard = self.mmap.write().unwrap();
-        unsafe {
-            let file = &mut *self.file.get();
-            file.set_len(new_data.len() as u64)?;
-            let new_mmap = MmapMut::map_mut(&*file)?;
-            *mmap_guard = new_mmap;
-        }
+        let mut storage = self.storage.write().unwrap();
+        storage.grow(new_data.len())?;
+        storage.sync()?;
+        println!("   [OK] Write transaction committed and storage grown and synced.");
+        Ok(())
+    }
 
-        mmap_guard.flush()?;
-        println!("   [OK] Write transaction committed and Mmap updated and flushed.");
+    // Steps 2 and 3 of a commit. Once the header is written the file may hold the commit
+    // whether or not the rest goes through.
+    fn write_header_and_pages(storage: &mut dyn Storage, header: &Header, pages: &[(u64, &[u8])]) -> Result<()> {
+        let page_size = header.page_size();
+        storage.write_at(header.slot_offset(), header.as_bytes())?;
+        storage.sync()?;
+
+        // 3. the pages themselves, then the journal is no longer needed
+        storage.write_pages(page_size, pages)?;
+        storage.sync()?;
+        storage.truncate((header.highest_page_id.get() as usize + 1) * page_size)?;
         Ok(())
     }
 
+    fn check_poisoned(&self) -> Result<()> {
+        match self.poisoned.load(AtomicOrdering::SeqCst) {
+            true => Err(DbError::Poisoned),
+            false => Ok(()),
+        }
+    }
+
+    /// Writes the pages of a transaction atomically: a crash at any point leaves either
+    /// the previous commit or this one when the database is next opened. A failure once
+    /// the header is written fails every later call with [`DbError::Poisoned`] until then.
     pub fn commit_dirty_pages(
         &self,
-        dirty_pages: std::collections::HashMap<u64, Vec<u8>>,
+        mut dirty_pages: std::collections::HashMap<u64, Vec<u8>>,
         new_highest_page_id: u64,
         new_root_page_id: u64,
     ) -> Result<()> {
-        let mut mmap = self.mmap.write().unwrap();
-
-        let required_size = (new_highest_page_id as usize + 1) * PAGE_SIZE;
-        if required_size > mmap.len() {
-            unsafe {
-                let file = &mut *self.file.get();
-                file.set_len(required_size as u64)?;
-                let new_mmap = MmapMut::map_mut(&*file)?;
-                *mmap = new_mmap;
-            }
+        let mut storage = self.storage.write().unwrap();
+        self.check_poisoned()?;
+        let page_size = self.page_size();
+        let tx_id = self.header.read().unwrap().tx_id.get() + 1;
+        if let Some(cipher) = &self.cipher {
+            cipher.encrypt(tx_id, page_size, dirty_pages.iter_mut().map(|(&page_id, page_bytes)| (page_id, page_bytes)))?;
         }
-
-        for (page_id, page_bytes) in dirty_pages.iter() {
-            let offset = *page_id as usize * PAGE_SIZE;
-            if offset + PAGE_SIZE <= mmap.len() {
-                mmap[offset..offset + PAGE_SIZE].copy_from_slice(page_bytes);
-            }
+        for page_bytes in dirty_pages.values_mut() {
+            page::seal_page(page_bytes);
         }
 
-        let mut header = self.header.write().unwrap();
-        header.highest_page_id = new_highest_page_id;
-        header.root_page_id = new_root_page_id;
-        header.tx_id += 1;
-
-        mmap[..std::mem::size_of::<Header>()].copy_from_slice(header.as_bytes());
+        let mut pages: Vec<(u64, &[u8])> = dirty_pages.iter()
+            .filter(|&(&page_id, _)| page_id <= new_highest_page_id)
+            .map(|(&page_id, page_bytes)| (page_id, page_bytes.as_slice()))
+            .collect();
+        pages.sort_by_key(|&(page_id, _)| page_id);
+
+        // 1. the journal, past every page the tree uses
+        let journal_page_id = new_highest_page_id + 1;
+        let journal_checksum = journal::write(storage.as_mut(), page_size, journal_page_id, &pages)?;
+        storage.sync()?;
+
+        // 2. the header naming it, in the slot the previous commit didn't use
+        let mut header = *self.header.read().unwrap();
+        header.highest_page_id.set(new_highest_page_id);
+        header.root_page_id.set(new_root_page_id);
+        header.tx_id.set(tx_id);
+        header.journal_page_id.set(journal_page_id);
+        header.journal_count.set(pages.len() as u64);
+        header.journal_checksum.set(journal_checksum);
+        header.seal();
+        if let Err(err) = Self::write_header_and_pages(storage.as_mut(), &header, &pages) {
+            self.poisoned.store(true, AtomicOrdering::SeqCst);
+            return Err(err);
+        }
+        self.verified.forget(dirty_pages.keys().copied());
+        self.cache.forget(dirty_pages.keys().copied());
 
-        mmap.flush()?;
+        // readers only see the new tree once its pages are in place
+        *self.header.write().unwrap() = header;
 
-        println!("   [OK] Committed {} dirty pages, tx_id={}", dirty_pages.len(), header.tx_id);
+        println!("   [OK] Committed {} dirty pages, tx_id={}", dirty_pages.len(), header.tx_id.get());
         Ok(())
     }
-
-
-}
\ No newline at end of file
+}
@@ -0,0 +1,194 @@
+// Pages of a database opened with a key are encrypted as they are committed. Each page
+// keeps its 32 byte header in the clear, so the free list, runs and checksums are walked
+// as they are otherwise, and its body is encrypted with XChaCha20-Poly1305. The last
+// TRAILER_SIZE bytes of the page hold what its nonce was made from and the tag, so a
+// page of an encrypted database has that much less room for elements:
+//
+//   page header    in the clear, authenticated along with the body
+//   body           encrypted
+//   tx_id, salt    the commit that wrote the page; with the page id, the nonce
+//   tag            of the header and body
+//
+// The salt is drawn once per commit: a commit that fails before its header is written is
+// retried under the same tx_id, and mustn't reuse the nonces of the attempt that failed.
+// The page checksum covers the stored bytes, so damage is still reported as such, and a
+// page that checks out but fails its tag was altered or moved.
+//
+// The header holds a key check value, the tag of an empty message under a random nonce,
+// so a wrong key is refused when the file is opened rather than by its first page.
+use crate::db::DbError;
+use crate::page::{CHECKSUM_OFFSET, PAGE_HEADER_SIZE};
+use zerocopy::little_endian::U64;
+use zerocopy::{FromBytes, IntoBytes, Immutable, KnownLayout, Unaligned};
+
+/// Bytes of a key, see [`DbOptions::key`](crate::db::DbOptions::key).
+pub const KEY_SIZE: usize = 32;
+/// Bytes at the end of every page of an encrypted database, after its body.
+pub const TRAILER_SIZE: usize = std::mem::size_of::<Trailer>();
+pub(crate) const KEY_CHECK_SIZE: usize = std::mem::size_of::<KeyCheck>();
+const NONCE_SIZE: usize = 24;
+const TAG_SIZE: usize = 16;
+const KEY_CHECK_AAD: &[u8] = b"rbolt key check";
+
+/// Whether this build was compiled with the `encryption` feature.
+pub fn is_available() -> bool {
+    cfg!(feature = "encryption")
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, FromBytes, IntoBytes, KnownLayout, Immutable, Unaligned)]
+struct Trailer {
+    tx_id: U64, // commit that wrote the page
+    salt: [u8; 8], // drawn for that commit
+    tag: [u8; TAG_SIZE],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, FromBytes, IntoBytes, KnownLayout, Immutable, Unaligned)]
+struct KeyCheck {
+    nonce: [u8; NONCE_SIZE],
+    tag: [u8; TAG_SIZE], // of an empty message under the key
+}
+
+#[cfg(feature = "encryption")]
+mod xchacha {
+    use super::{KEY_SIZE, NONCE_SIZE, TAG_SIZE};
+    use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+    use chacha20poly1305::{Tag, XChaCha20Poly1305, XNonce};
+    use std::io;
+
+    pub(super) struct Aead(XChaCha20Poly1305);
+
+    impl Aead {
+        pub(super) fn new(key: &[u8; KEY_SIZE]) -> Option<Self> {
+            Some(Aead(XChaCha20Poly1305::new(key.into())))
+        }
+
+        // Encrypts `body` in place, returning the tag of it and `aad`.
+        pub(super) fn seal(&self, nonce: &[u8; NONCE_SIZE], aad: &[u8], body: &mut [u8]) -> [u8; TAG_SIZE] {
+            self.0.encrypt_in_place_detached(XNonce::from_slice(nonce), aad, body)
+                .expect("a page is far below the cipher's message limit")
+                .into()
+        }
+
+        // Decrypts `body` in place, or leaves it be and returns false if the tag doesn't match.
+        pub(super) fn open(&self, nonce: &[u8; NONCE_SIZE], aad: &[u8], body: &mut [u8], tag: &[u8; TAG_SIZE]) -> bool {
+            self.0.decrypt_in_place_detached(XNonce::from_slice(nonce), aad, body, Tag::from_slice(tag)).is_ok()
+        }
+    }
+
+    pub(super) fn fill_random(bytes: &mut [u8]) -> io::Result<()> {
+        getrandom::getrandom(bytes).map_err(|err| io::Error::other(err.to_string()))
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+mod xchacha {
+    use super::{KEY_SIZE, NONCE_SIZE, TAG_SIZE};
+    use std::io;
+
+    // never made, a build without the feature has no cipher
+    pub(super) enum Aead {}
+
+    impl Aead {
+        pub(super) fn new(_: &[u8; KEY_SIZE]) -> Option<Self> {
+            None
+        }
+
+        pub(super) fn seal(&self, _: &[u8; NONCE_SIZE], _: &[u8], _: &mut [u8]) -> [u8; TAG_SIZE] {
+            match *self {}
+        }
+
+        pub(super) fn open(&self, _: &[u8; NONCE_SIZE], _: &[u8], _: &mut [u8], _: &[u8; TAG_SIZE]) -> bool {
+            match *self {}
+        }
+    }
+
+    pub(super) fn fill_random(_: &mut [u8]) -> io::Result<()> {
+        Err(io::Error::other("built without the encryption feature"))
+    }
+}
+
+/// The key of an encrypted database, sealing pages as they are committed and opening
+/// them as they are read.
+pub(crate) struct Cipher {
+    aead: xchacha::Aead,
+}
+
+impl Cipher {
+    pub(crate) fn new(key: &[u8; KEY_SIZE]) -> Result<Self, DbError> {
+        let aead = xchacha::Aead::new(key).ok_or(DbError::EncryptionUnsupported)?;
+        Ok(Cipher { aead })
+    }
+
+    /// The cipher of a file whose header holds `key_check`, once `key` has matched it.
+    pub(crate) fn unlock(key_check: &[u8; KEY_CHECK_SIZE], key: Option<&[u8; KEY_SIZE]>) -> Result<Self, DbError> {
+        let cipher = Cipher::new(key.ok_or(DbError::MissingKey)?)?;
+        let check = KeyCheck::read_from_bytes(key_check).unwrap();
+        if !cipher.aead.open(&check.nonce, KEY_CHECK_AAD, &mut [], &check.tag) {
+            return Err(DbError::WrongKey);
+        }
+        Ok(cipher)
+    }
+
+    /// A key check value for the header of a new file.
+    pub(crate) fn key_check(&self) -> Result<[u8; KEY_CHECK_SIZE], DbError> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        xchacha::fill_random(&mut nonce)?;
+        let check = KeyCheck { nonce, tag: self.aead.seal(&nonce, KEY_CHECK_AAD, &mut []) };
+        Ok(check.as_bytes().try_into().unwrap())
+    }
+
+    /// Encrypts the pages the commit `tx_id` writes in place, each grown to `page_size`
+    /// to make room for its trailer. Their contents have to end TRAILER_SIZE bytes short of that.
+    pub(crate) fn encrypt<'p>(&self, tx_id: u64, page_size: usize, pages: impl IntoIterator<Item = (u64, &'p mut Vec<u8>)>) -> Result<(), DbError> {
+        let mut salt = [0u8; 8];
+        xchacha::fill_random(&mut salt)?;
+        for (page_id, page_bytes) in pages {
+            page_bytes.resize(page_size, 0);
+            let (plain, trailer) = page_bytes.split_at_mut(page_size - TRAILER_SIZE);
+            let (header, body) = plain.split_at_mut(PAGE_HEADER_SIZE);
+            let tag = self.aead.seal(&nonce(page_id, tx_id, &salt), &aad(header), body);
+            trailer.copy_from_slice(Trailer { tx_id: tx_id.into(), salt, tag }.as_bytes());
+        }
+        Ok(())
+    }
+
+    /// The header and decrypted body of the stored page `page_id`, without its trailer.
+    pub(crate) fn decrypt(&self, page_id: u64, page_bytes: &[u8]) -> Result<Vec<u8>, DbError> {
+        let plain_len = page_bytes.len().checked_sub(TRAILER_SIZE)
+            .filter(|&plain_len| plain_len >= PAGE_HEADER_SIZE)
+            .ok_or(DbError::PageFormat { page_id, index: None })?;
+        let trailer = Trailer::read_from_bytes(&page_bytes[plain_len..]).unwrap();
+        let mut plain = page_bytes[..plain_len].to_vec();
+        let (header, body) = plain.split_at_mut(PAGE_HEADER_SIZE);
+        let nonce = nonce(page_id, trailer.tx_id.get(), &trailer.salt);
+        if !self.aead.open(&nonce, &aad(header), body, &trailer.tag) {
+            return Err(DbError::Unauthenticated { page_id });
+        }
+        Ok(plain)
+    }
+}
+
+/// Bytes of a page left for its header and body.
+pub(crate) fn usable_size(page_size: usize, encrypted: bool) -> usize {
+    match encrypted {
+        true => page_size - TRAILER_SIZE,
+        false => page_size,
+    }
+}
+
+fn nonce(page_id: u64, tx_id: u64, salt: &[u8; 8]) -> [u8; NONCE_SIZE] {
+    let mut nonce = [0u8; NONCE_SIZE];
+    nonce[..8].copy_from_slice(&page_id.to_le_bytes());
+    nonce[8..16].copy_from_slice(&tx_id.to_le_bytes());
+    nonce[16..].copy_from_slice(salt);
+    nonce
+}
+
+// The page header as authenticated: its checksum is only set once the page is encrypted.
+fn aad(header: &[u8]) -> [u8; PAGE_HEADER_SIZE] {
+    let mut aad: [u8; PAGE_HEADER_SIZE] = header.try_into().unwrap();
+    aad[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4].fill(0);
+    aad
+}
@@ -0,0 +1,91 @@
+// The free list lives on page 1 and chains on to further pages when it outgrows it.
+// Each page body starts with the id of the next free list page (0 for none),
+// followed by `count` free page ids. Chained pages are taken from the free list
+// itself, and count as free again once the list has been read back.
+use crate::db::DbError;
+use crate::encryption::Cipher;
+use crate::page::{self, Page, PageType, VerifiedPages, PAGE_HEADER_SIZE};
+use zerocopy::{FromBytes, IntoBytes};
+
+pub const FREE_LIST_PAGE_ID: u64 = 1;
+
+// Free page ids one page of `usable_size` bytes holds, after the id of the next page.
+fn ids_per_page(usable_size: usize) -> usize {
+    (usable_size - PAGE_HEADER_SIZE) / 8 - 1
+}
+
+/// Reads the free page ids, checking each page of the list against its checksum and
+/// decrypting it with `cipher` if the database is encrypted.
+pub(crate) fn read(mmap: &[u8], page_size: usize, highest_page_id: u64, verified: &VerifiedPages, cipher: Option<&Cipher>) -> Result<Vec<u64>, DbError> {
+    let mut free = Vec::new();
+    let mut page_id = FREE_LIST_PAGE_ID;
+    let mut visited = 0;
+    while page_id != 0 {
+        let offset = page_id as usize * page_size;
+        let page_bytes = mmap.get(offset..offset + page_size)
+            .ok_or(DbError::PageOutOfBounds { page_id, file_size: mmap.len() })?;
+        verified.check(page_id, page_bytes)?;
+        let page_bytes = page::plain_page(page_id, page_bytes, cipher)?;
+        let (page, body) = Page::ref_from_prefix(&page_bytes).map_err(|_| DbError::PageFormat { page_id, index: None })?;
+        let count = page.count.get() as usize;
+        if page.page_type != PageType::FreeList as u8 || count > ids_per_page(page_bytes.len()) {
+            return Err(DbError::PageFormat { page_id, index: None });
+        }
+
+        let mut ids = body[..(count + 1) * 8].chunks_exact(8).map(|id| u64::from_le_bytes(id.try_into().unwrap()));
+        let next = ids.next().unwrap_or(0);
+        for (index, id) in ids.enumerate() {
+            if id <= FREE_LIST_PAGE_ID || id > highest_page_id {
+                return Err(DbError::PageFormat { page_id, index: Some(index) });
+            }
+            free.push(id);
+        }
+        if page_id != FREE_LIST_PAGE_ID {
+            free.push(page_id);
+        }
+
+        // a cycle or an id past the end means the list is corrupt
+        visited += 1;
+        if visited > highest_page_id || next > highest_page_id {
+            return Err(DbError::PageFormat { page_id, index: None });
+        }
+        page_id = next;
+    }
+    Ok(free)
+}
+
+/// Page images of `usable_size` bytes holding `free`, starting with page 1. Pages
+/// needed past the first are popped off `free`.
+pub(crate) fn write(free: &mut Vec<u64>, usable_size: usize) -> Vec<(u64, Vec<u8>)> {
+    let ids_per_page = ids_per_page(usable_size);
+    let mut chain = vec![FREE_LIST_PAGE_ID];
+    while free.len() > chain.len() * ids_per_page {
+        chain.push(free.pop().unwrap());
+    }
+
+    let mut chunks = free.chunks(ids_per_page);
+    let mut pages = Vec::with_capacity(chain.len());
+    for (index, &page_id) in chain.iter().enumerate() {
+        let ids = chunks.next().unwrap_or(&[]);
+        let next = chain.get(index + 1).copied().unwrap_or(0);
+
+        let mut page_bytes = vec![0u8; usable_size];
+        let page = Page {
+            id: page_id.into(),
+            page_type: PageType::FreeList as u8,
+            codec: 0,
+            count: (ids.len() as u16).into(),
+            checksum: 0.into(),
+            next: 0.into(),
+            prev: 0.into(),
+        };
+        page_bytes[..PAGE_HEADER_SIZE].copy_from_slice(page.as_bytes());
+        page_bytes[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 8].copy_from_slice(&next.to_le_bytes());
+        for (slot, id) in ids.iter().enumerate() {
+            let offset = PAGE_HEADER_SIZE + (slot + 1) * 8;
+            page_bytes[offset..offset + 8].copy_from_slice(&id.to_le_bytes());
+        }
+        pages.push((page_id, page_bytes));
+    }
+    pages
+}
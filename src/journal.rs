@@ -0,0 +1,64 @@
+// Redo journal making commits atomic. Before a commit overwrites any page in place
+// it writes the new page images past the highest page of the tree:
+//
+//   journal_page_id      directory: the target page id of every image, 8 bytes each
+//   ... + dir pages      one page image per target, in directory order
+//
+// and syncs. The meta slot written next records where the journal is, how many pages
+// it holds and their checksum; once that is synced the commit has happened, and the
+// images are copied into place. Opening a database replays the journal of its newest
+// meta if the journal still checks out. It only stops checking out once a later
+// transaction has reused its pages, by which time it had already been applied.
+use crate::checksum::Checksum;
+use crate::storage::Storage;
+use std::io;
+
+fn directory_pages(count: usize, page_size: usize) -> usize {
+    (count * 8).div_ceil(page_size)
+}
+
+/// Writes the journal of `pages` starting at `journal_page_id`, growing the storage
+/// to fit, and returns its checksum. Does not sync.
+pub(crate) fn write(storage: &mut dyn Storage, page_size: usize, journal_page_id: u64, pages: &[(u64, &[u8])]) -> io::Result<u64> {
+    let dir_pages = directory_pages(pages.len(), page_size);
+    let mut directory = vec![0u8; dir_pages * page_size];
+    for (slot, &(page_id, _)) in pages.iter().enumerate() {
+        directory[slot * 8..slot * 8 + 8].copy_from_slice(&page_id.to_le_bytes());
+    }
+
+    let mut checksum = Checksum::new();
+    checksum.update(&directory);
+    let mut journal: Vec<(u64, &[u8])> = directory.chunks(page_size)
+        .enumerate()
+        .map(|(index, page_bytes)| (journal_page_id + index as u64, page_bytes))
+        .collect();
+    for (index, &(_, page_bytes)) in pages.iter().enumerate() {
+        checksum.update(page_bytes);
+        journal.push((journal_page_id + (dir_pages + index) as u64, page_bytes));
+    }
+    storage.write_pages(page_size, &journal)?;
+    Ok(checksum.finish())
+}
+
+/// The (target page id, image) pairs of the journal, or None if it no longer
+/// checks out, or lies past the end of the storage after being truncated away.
+pub(crate) fn read(bytes: &[u8], page_size: usize, journal_page_id: u64, count: usize, expected: u64) -> Option<Vec<(u64, Vec<u8>)>> {
+    let dir_pages = directory_pages(count, page_size);
+    let start = (journal_page_id as usize).checked_mul(page_size)?;
+    let end = start.checked_add((dir_pages + count).checked_mul(page_size)?)?;
+    let journal = bytes.get(start..end)?;
+
+    let (directory, images) = journal.split_at(dir_pages * page_size);
+    let mut checksum = Checksum::new();
+    checksum.update(directory);
+    checksum.update(images);
+    if checksum.finish() != expected {
+        return None;
+    }
+
+    let pages = directory[..count * 8].chunks_exact(8)
+        .map(|id| u64::from_le_bytes(id.try_into().unwrap()))
+        .zip(images.chunks_exact(page_size).map(<[u8]>::to_vec))
+        .collect();
+    Some(pages)
+}
@@ -0,0 +1,486 @@
+// Order preserving tuple encoding, the same layout as the FoundationDB tuple layer.
+// Packed tuples compare bytewise in the same order as the tuples themselves,
+// so they can be used as keys with the default comparator.
+use std::fmt;
+use std::ops::Range;
+
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(feature = "serde")]
+pub use serialize::{from_element, to_element};
+
+const NULL: u8 = 0x00;
+const BYTES: u8 = 0x01;
+const STRING: u8 = 0x02;
+const NESTED: u8 = 0x05;
+const INT_ZERO: u8 = 0x14;
+const DOUBLE: u8 = 0x21;
+const FALSE: u8 = 0x26;
+const TRUE: u8 = 0x27;
+const ESCAPE: u8 = 0xFF;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyError {
+    Truncated { offset: usize },
+    InvalidTypeCode { offset: usize, code: u8 },
+    InvalidUtf8 { offset: usize },
+    IntegerOverflow { offset: usize },
+    TypeMismatch { index: usize, expected: &'static str },
+    LengthMismatch { expected: usize, found: usize },
+    Custom(String),
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::Truncated { offset } => write!(f, "Tuple truncated at byte {}", offset),
+            KeyError::InvalidTypeCode { offset, code } => {
+                write!(f, "Invalid type code 0x{:02x} at byte {}", code, offset)
+            }
+            KeyError::InvalidUtf8 { offset } => write!(f, "Invalid UTF-8 string at byte {}", offset),
+            KeyError::IntegerOverflow { offset } => write!(f, "Integer at byte {} does not fit in 64 bits", offset),
+            KeyError::TypeMismatch { index, expected } => {
+                write!(f, "Tuple element {} is not {}", index, expected)
+            }
+            KeyError::LengthMismatch { expected, found } => {
+                write!(f, "Expected a tuple of {} elements, found {}", expected, found)
+            }
+            KeyError::Custom(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+type Result<T> = std::result::Result<T, KeyError>;
+
+/// A decoded tuple element. Non-negative integers always decode as `Uint`,
+/// negative ones as `Int`, since both share one encoding.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+    Null,
+    Bytes(Vec<u8>),
+    String(String),
+    Tuple(Vec<Element>),
+    Uint(u64),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+/// A value that can be one element of a tuple.
+pub trait TupleElement {
+    fn encode(&self, out: &mut Vec<u8>, nested: bool);
+}
+
+/// A value that can be packed as a whole tuple.
+pub trait Tuple {
+    fn pack_into(&self, out: &mut Vec<u8>);
+}
+
+/// Encodes a tuple into bytes that sort in tuple order.
+pub fn pack<T: Tuple + ?Sized>(tuple: &T) -> Vec<u8> {
+    let mut out = Vec::new();
+    tuple.pack_into(&mut out);
+    out
+}
+
+/// Decodes every element of a packed tuple.
+pub fn unpack(bytes: &[u8]) -> Result<Vec<Element>> {
+    let mut elements = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let (element, next) = decode_element(bytes, pos, false)?;
+        elements.push(element);
+        pos = next;
+    }
+    Ok(elements)
+}
+
+/// Decodes a packed tuple into a typed tuple such as `(u64, String)`.
+pub fn unpack_as<T: FromTuple>(bytes: &[u8]) -> Result<T> {
+    T::from_elements(unpack(bytes)?)
+}
+
+/// Half-open byte range holding `prefix` itself and every tuple that extends it.
+/// The start is the packed prefix and the end sorts after any extension, so the
+/// range can bound a scan over all keys under `prefix`.
+pub fn prefix_range<T: Tuple + ?Sized>(prefix: &T) -> Range<Vec<u8>> {
+    let start = pack(prefix);
+    let mut end = start.clone();
+    end.push(0xFF);
+    start..end
+}
+
+fn encode_escaped(code: u8, data: &[u8], out: &mut Vec<u8>) {
+    out.push(code);
+    for &b in data {
+        out.push(b);
+        if b == 0x00 {
+            out.push(ESCAPE);
+        }
+    }
+    out.push(0x00);
+}
+
+fn encode_uint(value: u64, out: &mut Vec<u8>) {
+    if value == 0 {
+        out.push(INT_ZERO);
+        return;
+    }
+    let len = 8 - value.leading_zeros() as usize / 8;
+    out.push(INT_ZERO + len as u8);
+    out.extend_from_slice(&value.to_be_bytes()[8 - len..]);
+}
+
+fn encode_int(value: i64, out: &mut Vec<u8>) {
+    if value >= 0 {
+        encode_uint(value as u64, out);
+        return;
+    }
+    // negative numbers are the ones' complement of their magnitude, with a
+    // smaller type code for longer magnitudes so they sort first
+    let magnitude = value.unsigned_abs();
+    let len = 8 - magnitude.leading_zeros() as usize / 8;
+    out.push(INT_ZERO - len as u8);
+    out.extend_from_slice(&(!magnitude).to_be_bytes()[8 - len..]);
+}
+
+fn encode_float(value: f64, out: &mut Vec<u8>) {
+    let bits = value.to_bits();
+    let bits = if bits >> 63 == 1 { !bits } else { bits ^ (1 << 63) };
+    out.push(DOUBLE);
+    out.extend_from_slice(&bits.to_be_bytes());
+}
+
+fn decode_element(bytes: &[u8], pos: usize, nested: bool) -> Result<(Element, usize)> {
+    let code = bytes[pos];
+    let body = pos + 1;
+    match code {
+        NULL => {
+            if nested && bytes.get(body) == Some(&ESCAPE) {
+                return Ok((Element::Null, body + 1));
+            }
+            Ok((Element::Null, body))
+        }
+        BYTES => {
+            let (data, next) = decode_escaped(bytes, body)?;
+            Ok((Element::Bytes(data), next))
+        }
+        STRING => {
+            let (data, next) = decode_escaped(bytes, body)?;
+            let s = String::from_utf8(data).map_err(|_| KeyError::InvalidUtf8 { offset: pos })?;
+            Ok((Element::String(s), next))
+        }
+        NESTED => {
+            let mut elements = Vec::new();
+            let mut cursor = body;
+            loop {
+                match bytes.get(cursor) {
+                    None => return Err(KeyError::Truncated { offset: cursor }),
+                    Some(&0x00) if bytes.get(cursor + 1) != Some(&ESCAPE) => {
+                        return Ok((Element::Tuple(elements), cursor + 1));
+                    }
+                    Some(_) => {
+                        let (element, next) = decode_element(bytes, cursor, true)?;
+                        elements.push(element);
+                        cursor = next;
+                    }
+                }
+            }
+        }
+        0x0C..=0x1C => {
+            let len = (code as i32 - INT_ZERO as i32).unsigned_abs() as usize;
+            let end = body + len;
+            if end > bytes.len() {
+                return Err(KeyError::Truncated { offset: bytes.len() });
+            }
+            let mut buf = [0u8; 8];
+            buf[8 - len..].copy_from_slice(&bytes[body..end]);
+            let raw = u64::from_be_bytes(buf);
+            if code >= INT_ZERO {
+                return Ok((Element::Uint(raw), end));
+            }
+            let mask = if len == 8 { u64::MAX } else { (1u64 << (len * 8)) - 1 };
+            let magnitude = !raw & mask;
+            if magnitude > i64::MAX as u64 + 1 {
+                return Err(KeyError::IntegerOverflow { offset: pos });
+            }
+            Ok((Element::Int((magnitude as i64).wrapping_neg()), end))
+        }
+        DOUBLE => {
+            let end = body + 8;
+            if end > bytes.len() {
+                return Err(KeyError::Truncated { offset: bytes.len() });
+            }
+            let bits = u64::from_be_bytes(bytes[body..end].try_into().unwrap());
+            let bits = if bits >> 63 == 1 { bits ^ (1 << 63) } else { !bits };
+            Ok((Element::Float(f64::from_bits(bits)), end))
+        }
+        FALSE => Ok((Element::Bool(false), body)),
+        TRUE => Ok((Element::Bool(true), body)),
+        _ => Err(KeyError::InvalidTypeCode { offset: pos, code }),
+    }
+}
+
+fn decode_escaped(bytes: &[u8], start: usize) -> Result<(Vec<u8>, usize)> {
+    let mut data = Vec::new();
+    let mut pos = start;
+    loop {
+        match bytes.get(pos) {
+            None => return Err(KeyError::Truncated { offset: pos }),
+            Some(&0x00) => {
+                if bytes.get(pos + 1) == Some(&ESCAPE) {
+                    data.push(0x00);
+                    pos += 2;
+                } else {
+                    return Ok((data, pos + 1));
+                }
+            }
+            Some(&b) => {
+                data.push(b);
+                pos += 1;
+            }
+        }
+    }
+}
+
+impl TupleElement for u64 {
+    fn encode(&self, out: &mut Vec<u8>, _nested: bool) {
+        encode_uint(*self, out);
+    }
+}
+
+impl TupleElement for i64 {
+    fn encode(&self, out: &mut Vec<u8>, _nested: bool) {
+        encode_int(*self, out);
+    }
+}
+
+impl TupleElement for f64 {
+    fn encode(&self, out: &mut Vec<u8>, _nested: bool) {
+        encode_float(*self, out);
+    }
+}
+
+impl TupleElement for bool {
+    fn encode(&self, out: &mut Vec<u8>, _nested: bool) {
+        out.push(if *self { TRUE } else { FALSE });
+    }
+}
+
+impl TupleElement for str {
+    fn encode(&self, out: &mut Vec<u8>, _nested: bool) {
+        encode_escaped(STRING, self.as_bytes(), out);
+    }
+}
+
+impl TupleElement for String {
+    fn encode(&self, out: &mut Vec<u8>, nested: bool) {
+        self.as_str().encode(out, nested);
+    }
+}
+
+impl TupleElement for [u8] {
+    fn encode(&self, out: &mut Vec<u8>, _nested: bool) {
+        encode_escaped(BYTES, self, out);
+    }
+}
+
+impl TupleElement for Vec<u8> {
+    fn encode(&self, out: &mut Vec<u8>, nested: bool) {
+        self.as_slice().encode(out, nested);
+    }
+}
+
+impl<T: TupleElement + ?Sized> TupleElement for &T {
+    fn encode(&self, out: &mut Vec<u8>, nested: bool) {
+        (**self).encode(out, nested);
+    }
+}
+
+impl<T: TupleElement> TupleElement for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>, nested: bool) {
+        match self {
+            Some(value) => value.encode(out, nested),
+            None => Element::Null.encode(out, nested),
+        }
+    }
+}
+
+impl TupleElement for Element {
+    fn encode(&self, out: &mut Vec<u8>, nested: bool) {
+        match self {
+            Element::Null => {
+                out.push(NULL);
+                if nested {
+                    out.push(ESCAPE);
+                }
+            }
+            Element::Bytes(b) => b.encode(out, nested),
+            Element::String(s) => s.encode(out, nested),
+            Element::Tuple(elements) => {
+                out.push(NESTED);
+                for element in elements {
+                    element.encode(out, true);
+                }
+                out.push(0x00);
+            }
+            Element::Uint(v) => encode_uint(*v, out),
+            Element::Int(v) => encode_int(*v, out),
+            Element::Float(v) => encode_float(*v, out),
+            Element::Bool(v) => v.encode(out, nested),
+        }
+    }
+}
+
+impl Tuple for [Element] {
+    fn pack_into(&self, out: &mut Vec<u8>) {
+        for element in self {
+            element.encode(out, false);
+        }
+    }
+}
+
+impl Tuple for Vec<Element> {
+    fn pack_into(&self, out: &mut Vec<u8>) {
+        self.as_slice().pack_into(out);
+    }
+}
+
+/// Converts a decoded element back into a Rust value.
+pub trait FromElement: Sized {
+    const NAME: &'static str;
+    fn from_element(element: Element) -> Option<Self>;
+}
+
+/// Converts a decoded tuple back into a typed Rust tuple.
+pub trait FromTuple: Sized {
+    fn from_elements(elements: Vec<Element>) -> Result<Self>;
+}
+
+impl FromElement for Element {
+    const NAME: &'static str = "an element";
+    fn from_element(element: Element) -> Option<Self> {
+        Some(element)
+    }
+}
+
+impl FromElement for u64 {
+    const NAME: &'static str = "an unsigned integer";
+    fn from_element(element: Element) -> Option<Self> {
+        match element {
+            Element::Uint(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl FromElement for i64 {
+    const NAME: &'static str = "a signed integer";
+    fn from_element(element: Element) -> Option<Self> {
+        match element {
+            Element::Int(v) => Some(v),
+            Element::Uint(v) => i64::try_from(v).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl FromElement for f64 {
+    const NAME: &'static str = "a float";
+    fn from_element(element: Element) -> Option<Self> {
+        match element {
+            Element::Float(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl FromElement for bool {
+    const NAME: &'static str = "a bool";
+    fn from_element(element: Element) -> Option<Self> {
+        match element {
+            Element::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl FromElement for String {
+    const NAME: &'static str = "a string";
+    fn from_element(element: Element) -> Option<Self> {
+        match element {
+            Element::String(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl FromElement for Vec<u8> {
+    const NAME: &'static str = "a byte string";
+    fn from_element(element: Element) -> Option<Self> {
+        match element {
+            Element::Bytes(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl<T: FromElement> FromElement for Option<T> {
+    const NAME: &'static str = T::NAME;
+    fn from_element(element: Element) -> Option<Self> {
+        match element {
+            Element::Null => Some(None),
+            other => T::from_element(other).map(Some),
+        }
+    }
+}
+
+macro_rules! impl_tuple {
+    ($len:expr; $($name:ident $idx:tt),+) => {
+        impl<$($name: TupleElement),+> Tuple for ($($name,)+) {
+            fn pack_into(&self, out: &mut Vec<u8>) {
+                $(self.$idx.encode(out, false);)+
+            }
+        }
+
+        impl<$($name: TupleElement),+> TupleElement for ($($name,)+) {
+            fn encode(&self, out: &mut Vec<u8>, _nested: bool) {
+                out.push(NESTED);
+                $(self.$idx.encode(out, true);)+
+                out.push(0x00);
+            }
+        }
+
+        impl<$($name: FromElement),+> FromTuple for ($($name,)+) {
+            fn from_elements(elements: Vec<Element>) -> Result<Self> {
+                if elements.len() != $len {
+                    return Err(KeyError::LengthMismatch { expected: $len, found: elements.len() });
+                }
+                let mut elements = elements.into_iter();
+                Ok(($(
+                    $name::from_element(elements.next().unwrap())
+                        .ok_or(KeyError::TypeMismatch { index: $idx, expected: $name::NAME })?,
+                )+))
+            }
+        }
+
+        impl<$($name: FromElement),+> FromElement for ($($name,)+) {
+            const NAME: &'static str = "a nested tuple";
+            fn from_element(element: Element) -> Option<Self> {
+                match element {
+                    Element::Tuple(elements) => Self::from_elements(elements).ok(),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+impl_tuple!(1; A 0);
+impl_tuple!(2; A 0, B 1);
+impl_tuple!(3; A 0, B 1, C 2);
+impl_tuple!(4; A 0, B 1, C 2, D 3);
+impl_tuple!(5; A 0, B 1, C 2, D 3, E 4);
+impl_tuple!(6; A 0, B 1, C 2, D 3, E 4, F 5);
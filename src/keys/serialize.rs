@@ -0,0 +1,467 @@
+// Maps the serde data model onto tuple elements so any `Serialize` type can be
+// used as an order preserving key. Structs, tuples and sequences become nested
+// tuples, and enum variants become a tuple led by the variant index so they
+// sort the way `#[derive(Ord)]` orders them.
+use super::{Element, KeyError};
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+use std::fmt::Display;
+use std::vec::IntoIter;
+
+type Result<T> = std::result::Result<T, KeyError>;
+
+/// Converts a serializable value into a single tuple element.
+pub fn to_element<T: Serialize + ?Sized>(value: &T) -> Result<Element> {
+    value.serialize(ElementSerializer)
+}
+
+/// Rebuilds a value from an element produced by [`to_element`].
+pub fn from_element<T: DeserializeOwned>(element: Element) -> Result<T> {
+    T::deserialize(ElementDeserializer(element))
+}
+
+impl ser::Error for KeyError {
+    fn custom<T: Display>(msg: T) -> Self {
+        KeyError::Custom(msg.to_string())
+    }
+}
+
+impl de::Error for KeyError {
+    fn custom<T: Display>(msg: T) -> Self {
+        KeyError::Custom(msg.to_string())
+    }
+}
+
+fn int(value: i64) -> Element {
+    if value < 0 { Element::Int(value) } else { Element::Uint(value as u64) }
+}
+
+struct ElementSerializer;
+
+struct TupleBuilder {
+    elements: Vec<Element>,
+}
+
+impl TupleBuilder {
+    fn variant(index: u32) -> Self {
+        TupleBuilder { elements: vec![Element::Uint(index as u64)] }
+    }
+
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.elements.push(to_element(value)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Element> {
+        Ok(Element::Tuple(self.elements))
+    }
+}
+
+struct MapBuilder {
+    pairs: Vec<Element>,
+    key: Option<Element>,
+}
+
+impl ser::Serializer for ElementSerializer {
+    type Ok = Element;
+    type Error = KeyError;
+    type SerializeSeq = TupleBuilder;
+    type SerializeTuple = TupleBuilder;
+    type SerializeTupleStruct = TupleBuilder;
+    type SerializeTupleVariant = TupleBuilder;
+    type SerializeMap = MapBuilder;
+    type SerializeStruct = TupleBuilder;
+    type SerializeStructVariant = TupleBuilder;
+
+    fn serialize_bool(self, v: bool) -> Result<Element> {
+        Ok(Element::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Element> {
+        Ok(int(v as i64))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Element> {
+        Ok(int(v as i64))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Element> {
+        Ok(int(v as i64))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Element> {
+        Ok(int(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Element> {
+        Ok(Element::Uint(v as u64))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Element> {
+        Ok(Element::Uint(v as u64))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Element> {
+        Ok(Element::Uint(v as u64))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Element> {
+        Ok(Element::Uint(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Element> {
+        Ok(Element::Float(v as f64))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Element> {
+        Ok(Element::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Element> {
+        Ok(Element::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Element> {
+        Ok(Element::String(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Element> {
+        Ok(Element::Bytes(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Element> {
+        Ok(Element::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Element> {
+        to_element(value)
+    }
+
+    fn serialize_unit(self) -> Result<Element> {
+        Ok(Element::Tuple(Vec::new()))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Element> {
+        Ok(Element::Tuple(Vec::new()))
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, index: u32, _variant: &'static str) -> Result<Element> {
+        TupleBuilder::variant(index).finish()
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<Element> {
+        to_element(value)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<Element> {
+        let mut builder = TupleBuilder::variant(index);
+        builder.push(value)?;
+        builder.finish()
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<TupleBuilder> {
+        Ok(TupleBuilder { elements: Vec::with_capacity(len.unwrap_or(0)) })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<TupleBuilder> {
+        Ok(TupleBuilder { elements: Vec::with_capacity(len) })
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<TupleBuilder> {
+        Ok(TupleBuilder { elements: Vec::with_capacity(len) })
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<TupleBuilder> {
+        Ok(TupleBuilder::variant(index))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<MapBuilder> {
+        Ok(MapBuilder { pairs: Vec::with_capacity(len.unwrap_or(0)), key: None })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<TupleBuilder> {
+        Ok(TupleBuilder { elements: Vec::with_capacity(len) })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<TupleBuilder> {
+        Ok(TupleBuilder::variant(index))
+    }
+}
+
+impl ser::SerializeSeq for TupleBuilder {
+    type Ok = Element;
+    type Error = KeyError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Element> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for TupleBuilder {
+    type Ok = Element;
+    type Error = KeyError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Element> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for TupleBuilder {
+    type Ok = Element;
+    type Error = KeyError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Element> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for TupleBuilder {
+    type Ok = Element;
+    type Error = KeyError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Element> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for TupleBuilder {
+    type Ok = Element;
+    type Error = KeyError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Element> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for TupleBuilder {
+    type Ok = Element;
+    type Error = KeyError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Element> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeMap for MapBuilder {
+    type Ok = Element;
+    type Error = KeyError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.key = Some(to_element(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let key = self.key.take().ok_or_else(|| KeyError::Custom("map value without a key".to_string()))?;
+        self.pairs.push(Element::Tuple(vec![key, to_element(value)?]));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Element> {
+        Ok(Element::Tuple(self.pairs))
+    }
+}
+
+struct ElementDeserializer(Element);
+
+impl ElementDeserializer {
+    fn into_tuple(self, expected: &'static str) -> Result<Vec<Element>> {
+        match self.0 {
+            Element::Tuple(elements) => Ok(elements),
+            other => Err(KeyError::Custom(format!("expected {}, found {:?}", expected, other))),
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ElementDeserializer {
+    type Error = KeyError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.0 {
+            Element::Null => visitor.visit_none(),
+            Element::Bytes(v) => visitor.visit_byte_buf(v),
+            Element::String(v) => visitor.visit_string(v),
+            Element::Tuple(elements) => visitor.visit_seq(SeqAccess(elements.into_iter())),
+            Element::Uint(v) => visitor.visit_u64(v),
+            Element::Int(v) => visitor.visit_i64(v),
+            Element::Float(v) => visitor.visit_f64(v),
+            Element::Bool(v) => visitor.visit_bool(v),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.0 {
+            Element::Null => visitor.visit_none(),
+            other => visitor.visit_some(ElementDeserializer(other)),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.into_tuple("a unit")?;
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let pairs = self.into_tuple("a map")?;
+        visitor.visit_map(MapAccess { pairs: pairs.into_iter(), value: None })
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        let mut elements = self.into_tuple("an enum variant")?.into_iter();
+        let index = match elements.next() {
+            Some(Element::Uint(index)) => u32::try_from(index)
+                .map_err(|_| KeyError::Custom(format!("variant index {} out of range", index)))?,
+            other => return Err(KeyError::Custom(format!("expected a variant index, found {:?}", other))),
+        };
+        visitor.visit_enum(EnumAccess { index, fields: elements })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf seq tuple tuple_struct struct identifier ignored_any
+    }
+}
+
+struct SeqAccess(IntoIter<Element>);
+
+impl<'de> de::SeqAccess<'de> for SeqAccess {
+    type Error = KeyError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        match self.0.next() {
+            Some(element) => seed.deserialize(ElementDeserializer(element)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct MapAccess {
+    pairs: IntoIter<Element>,
+    value: Option<Element>,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess {
+    type Error = KeyError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        let Some(pair) = self.pairs.next() else {
+            return Ok(None);
+        };
+        let mut pair = ElementDeserializer(pair).into_tuple("a map entry")?.into_iter();
+        match (pair.next(), pair.next(), pair.next()) {
+            (Some(key), Some(value), None) => {
+                self.value = Some(value);
+                seed.deserialize(ElementDeserializer(key)).map(Some)
+            }
+            _ => Err(KeyError::Custom("map entries must be (key, value) pairs".to_string())),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let value = self.value.take().ok_or_else(|| KeyError::Custom("map key without a value".to_string()))?;
+        seed.deserialize(ElementDeserializer(value))
+    }
+}
+
+struct EnumAccess {
+    index: u32,
+    fields: IntoIter<Element>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = KeyError;
+    type Variant = VariantAccess;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, VariantAccess)> {
+        let index: de::value::U32Deserializer<KeyError> = self.index.into_deserializer();
+        let variant = seed.deserialize(index)?;
+        Ok((variant, VariantAccess(self.fields)))
+    }
+}
+
+struct VariantAccess(IntoIter<Element>);
+
+impl<'de> de::VariantAccess<'de> for VariantAccess {
+    type Error = KeyError;
+
+    fn unit_variant(self) -> Result<()> {
+        match self.0.len() {
+            0 => Ok(()),
+            n => Err(KeyError::Custom(format!("unit variant has {} fields", n))),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(mut self, seed: T) -> Result<T::Value> {
+        match (self.0.next(), self.0.next()) {
+            (Some(value), None) => seed.deserialize(ElementDeserializer(value)),
+            _ => Err(KeyError::Custom("newtype variant must have exactly one field".to_string())),
+        }
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(SeqAccess(self.0))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(SeqAccess(self.0))
+    }
+}
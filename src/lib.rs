@@ -1,4 +1,21 @@
 pub mod db;
 pub mod page;
+pub mod storage;
 pub mod btree;
-pub mod search;
\ No newline at end of file
+pub mod freelist;
+pub mod journal;
+pub mod checksum;
+pub mod compression;
+pub mod encryption;
+pub mod batch;
+pub mod search;
+pub mod comparator;
+pub mod merge;
+pub mod keys;
+pub mod cursor;
+pub mod stats;
+pub mod order;
+pub mod salvage;
+pub mod upgrade;
+#[cfg(feature = "serde")]
+pub mod table;
@@ -0,0 +1,107 @@
+// Read-modify-write updates applied at the leaf by `WriteTxn::merge`.
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MergeError {
+    InvalidWidth { operator: String, expected: usize, found: usize },
+    Custom(String),
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::InvalidWidth { operator, expected, found } => {
+                write!(f, "Merge operator {} expects {} byte values, found {}", operator, expected, found)
+            }
+            MergeError::Custom(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+/// Combines the stored value of a key with an operand.
+pub trait MergeOperator: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// The new value for `key`, or None to delete it. `existing` is None when the key is absent.
+    fn merge(&self, key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Result<Option<Vec<u8>>, MergeError>;
+}
+
+/// Adds little-endian u64s, wrapping on overflow. An absent key counts as 0.
+pub struct U64Add;
+
+/// Keeps the larger little-endian u64.
+pub struct U64Max;
+
+/// Keeps the smaller little-endian u64.
+pub struct U64Min;
+
+/// Appends the operand to the stored bytes.
+pub struct Append;
+
+fn read_u64(operator: &dyn MergeOperator, bytes: &[u8]) -> Result<u64, MergeError> {
+    let bytes: [u8; 8] = bytes.try_into().map_err(|_| MergeError::InvalidWidth {
+        operator: operator.name().to_string(),
+        expected: 8,
+        found: bytes.len(),
+    })?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+// Applies `combine` to the stored and operand u64s, storing the operand as is when the key is absent.
+fn merge_u64(
+    operator: &dyn MergeOperator,
+    existing: Option<&[u8]>,
+    operand: &[u8],
+    combine: fn(u64, u64) -> u64,
+) -> Result<Option<Vec<u8>>, MergeError> {
+    let operand = read_u64(operator, operand)?;
+    let merged = match existing {
+        Some(existing) => combine(read_u64(operator, existing)?, operand),
+        None => operand,
+    };
+    Ok(Some(merged.to_le_bytes().to_vec()))
+}
+
+impl MergeOperator for U64Add {
+    fn name(&self) -> &str {
+        "rbolt.u64_add"
+    }
+
+    fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Result<Option<Vec<u8>>, MergeError> {
+        merge_u64(self, existing, operand, u64::wrapping_add)
+    }
+}
+
+impl MergeOperator for U64Max {
+    fn name(&self) -> &str {
+        "rbolt.u64_max"
+    }
+
+    fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Result<Option<Vec<u8>>, MergeError> {
+        merge_u64(self, existing, operand, u64::max)
+    }
+}
+
+impl MergeOperator for U64Min {
+    fn name(&self) -> &str {
+        "rbolt.u64_min"
+    }
+
+    fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Result<Option<Vec<u8>>, MergeError> {
+        merge_u64(self, existing, operand, u64::min)
+    }
+}
+
+impl MergeOperator for Append {
+    fn name(&self) -> &str {
+        "rbolt.append"
+    }
+
+    fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Result<Option<Vec<u8>>, MergeError> {
+        let mut merged = existing.unwrap_or_default().to_vec();
+        merged.extend_from_slice(operand);
+        Ok(Some(merged))
+    }
+}
@@ -0,0 +1,95 @@
+// Rank and position queries over trees that keep subtree key counts in their
+// branch elements. Each walks one root-to-leaf path, adding up the counts of
+// the children it passes over.
+use crate::cursor::Iter;
+use crate::db::{DbError, ReadTxn, Result};
+use crate::page::{self, PageType};
+use crate::search;
+
+/// Keys that sort before `key`, plus `key` itself when `inclusive` and stored.
+pub(crate) fn keys_before(txn: &ReadTxn<'_>, key: &[u8], inclusive: bool) -> Result<u64> {
+    let comparator = txn.comparator();
+    let mut page_id = txn.root_page_id();
+    let mut before = 0;
+    for _ in 0..page::MAX_DEPTH {
+        let (page, body) = page::parse(page_id, txn.page_bytes(page_id)?)?;
+        let count = page.count.get() as usize;
+        match page.page_type {
+            t if t == PageType::Leaf as u8 => {
+                let (index, found) = search::search_leaf_elements(body, count, key, comparator)
+                    .map_err(|err| DbError::PageFormat { page_id, index: Some(err.index) })?;
+                return Ok(before + index as u64 + (found && inclusive) as u64);
+            }
+            t if t == PageType::Branch as u8 => {
+                let (index, found) = search::search_branch_elements(body, count, key, comparator)
+                    .map_err(|err| DbError::PageFormat { page_id, index: Some(err.index) })?;
+                let child_index = if found { index } else { index.saturating_sub(1) };
+                for passed in 0..child_index {
+                    before += page::branch_child_count(body, passed)
+                        .ok_or(DbError::PageFormat { page_id, index: Some(passed) })?;
+                }
+                page_id = page::branch_entry(body, child_index)
+                    .ok_or(DbError::PageFormat { page_id, index: Some(child_index) })?.1;
+            }
+            // nothing has been written yet
+            _ => return Ok(0),
+        }
+    }
+    Err(DbError::PageFormat { page_id, index: None })
+}
+
+/// The entry at position `n`, or None past the last key.
+pub(crate) fn nth<'t>(txn: &'t ReadTxn<'_>, mut n: u64) -> Result<Option<(&'t [u8], &'t [u8])>> {
+    let mut page_id = txn.root_page_id();
+    'descend: for _ in 0..page::MAX_DEPTH {
+        let (page, body) = page::parse(page_id, txn.page_bytes(page_id)?)?;
+        let count = page.count.get() as usize;
+        match page.page_type {
+            t if t == PageType::Leaf as u8 => {
+                if n >= count as u64 {
+                    return Ok(None);
+                }
+                return page::leaf_entry(body, n as usize).map(Some)
+                    .ok_or(DbError::PageFormat { page_id, index: Some(n as usize) });
+            }
+            t if t == PageType::Branch as u8 => {
+                for index in 0..=count {
+                    let child_keys = page::branch_child_count(body, index)
+                        .ok_or(DbError::PageFormat { page_id, index: Some(index) })?;
+                    if n < child_keys {
+                        page_id = page::branch_entry(body, index)
+                            .ok_or(DbError::PageFormat { page_id, index: Some(index) })?.1;
+                        continue 'descend;
+                    }
+                    n -= child_keys;
+                }
+                return Ok(None);
+            }
+            _ => return Ok(None),
+        }
+    }
+    Err(DbError::PageFormat { page_id, index: None })
+}
+
+/// Keys in the whole tree, from the counts in the root.
+pub(crate) fn total(txn: &ReadTxn<'_>) -> Result<u64> {
+    let page_id = txn.root_page_id();
+    let (page, body) = page::parse(page_id, txn.page_bytes(page_id)?)?;
+    match page.page_type {
+        t if t == PageType::Leaf as u8 => Ok(page.count.get() as u64),
+        t if t == PageType::Branch as u8 => (0..=page.count.get() as usize)
+            .map(|index| page::branch_child_count(body, index).ok_or(DbError::PageFormat { page_id, index: Some(index) }))
+            .sum(),
+        _ => Ok(0),
+    }
+}
+
+/// Counts the keys one by one, for trees without order statistics.
+pub(crate) fn count_by_scan(iter: Iter<'_>) -> Result<u64> {
+    let mut count = 0;
+    for entry in iter {
+        entry?;
+        count += 1;
+    }
+    Ok(count)
+}
@@ -1,12 +1,22 @@
-use crate::db::PAGE_SIZE;
-use std::sync::RwLockReadGuard;
-use memmap2::MmapMut;
-use zerocopy::{FromBytes, IntoBytes, Immutable, KnownLayout};
+use crate::db::DbError;
+use crate::compression;
+use crate::encryption::Cipher;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLockReadGuard};
+use crate::storage::Storage;
+use zerocopy::little_endian::{U16, U32, U64};
+use zerocopy::{FromBytes, IntoBytes, Immutable, KnownLayout, Unaligned};
 
 pub const PAGE_HEADER_SIZE: usize = std::mem::size_of::<Page>();
-pub const PAGE_BODY_SIZE: usize = PAGE_SIZE - PAGE_HEADER_SIZE;
 pub const LEAF_ELEMENT_SIZE: usize = std::mem::size_of::<LeafElement>();
 pub const BRANCH_ELEMENT_SIZE: usize = std::mem::size_of::<BranchElement>();
+pub(crate) const CHECKSUM_OFFSET: usize = std::mem::offset_of!(Page, checksum);
+/// Deeper than any tree the crate builds; a longer path from the root means the
+/// branch pages of a damaged file point back at each other.
+pub const MAX_DEPTH: usize = 64;
+/// Decoded pages a database keeps between transactions, see [`PageCache`].
+pub const PAGE_CACHE_PAGES: usize = 1024;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PageError {
@@ -40,44 +50,250 @@ pub enum PageType {
     FreeList = 2, //pages that have been freed and can be reused
     Leaf = 3, //contains actual KV
     Branch = 4, //internal nodes of B tree. key or key range, page id
+    Packed = 5, //a compressed leaf, heading the run of pages it is stored in. see compression.rs
+    Overflow = 6, //the rest of such a run
 }
 
+// Every multi-byte field on disk is little-endian whatever the host, so a file moves
+// between architectures as is. The wrappers are unaligned, so pages parse at any offset.
 #[repr(C)]
-#[derive(Clone, Copy, FromBytes, IntoBytes, KnownLayout, Immutable)]
+#[derive(Clone, Copy, FromBytes, IntoBytes, KnownLayout, Immutable, Unaligned)]
 pub struct Page {
-    pub id: u64, // 8 bytes, 2^64 very large
+    pub id: U64, // 8 bytes, 2^64 very large
     pub page_type: u8, // 1 byte, mapped to PageType
-    pub _padding: u8, // 1 byte of explicit padding
-    pub count: u16, // The number of kv or child pointers, 2^16 = 65535
-    pub overflow: u32, // overflow multiple pages, 2^32 = 4294967296
+    pub codec: u8, // how a Packed page's run is encoded, mapped to Compression. 0 on other page types
+    pub count: U16, // The number of kv or child pointers, 2^16 = 65535
+    pub checksum: U32, // of the whole page with this field zeroed, set when the page is committed
+    pub next: U64, // next leaf in key order, 0 for none. unused by other page types
+    pub prev: U64, // previous leaf in key order, 0 for none
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, FromBytes, IntoBytes, KnownLayout, Immutable)]
+#[derive(Clone, Copy, FromBytes, IntoBytes, KnownLayout, Immutable, Unaligned)]
 pub struct BranchElement {
-    pub page_id: u64, // 8 bytes, the ID of the child page this element points to.
-    pub ksize: u16, // Size of the key, 2^16 = 65535
-    pub kptr: u16, // Offset to the key data within the page
-    pub _padding: [u8; 4], // Explicit padding to 16 bytes
+    pub page_id: U64, // 8 bytes, the ID of the child page this element points to.
+    pub ksize: U16, // Size of the key, 2^16 = 65535
+    pub kptr: U16, // Offset to the key data within the page
+    pub count: U32, // keys under the child page when the tree keeps order statistics, otherwise 0
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, FromBytes, IntoBytes, KnownLayout, Immutable)]
+#[derive(Clone, Copy, FromBytes, IntoBytes, KnownLayout, Immutable, Unaligned)]
 pub struct LeafElement {
-    pub ksize: u16,
-    pub vsize: u16,
-    pub kptr: u16,
-    pub vptr: u16,
+    pub ksize: U16,
+    pub vsize: U16,
+    pub kptr: U16,
+    pub vptr: U16,
 }
 
+/// Whether the element array `page` claims to hold fits in its body of `body_size` bytes.
+pub fn elements_fit(page: &Page, body_size: usize) -> bool {
+    let count = page.count.get() as usize;
+    let elements = match page.page_type {
+        t if t == PageType::Leaf as u8 => count * LEAF_ELEMENT_SIZE,
+        t if t == PageType::Branch as u8 => (count + 1) * BRANCH_ELEMENT_SIZE,
+        _ => 0,
+    };
+    elements <= body_size
+}
+
+/// Header and body of page `page_id`, once its element array is known to fit.
+/// Element offsets are checked as each element is read.
+pub(crate) fn parse(page_id: u64, page_bytes: &[u8]) -> Result<(&Page, &[u8]), DbError> {
+    match Page::ref_from_prefix(page_bytes) {
+        Ok((page, body)) if elements_fit(page, body.len()) => Ok((page, body)),
+        _ => Err(DbError::PageFormat { page_id, index: None }),
+    }
+}
+
+/// Key and value of leaf element `index`, or None if it doesn't fit in the body.
+pub fn leaf_entry(page_body: &[u8], index: usize) -> Option<(&[u8], &[u8])> {
+    let elem_bytes = page_body.get(index*LEAF_ELEMENT_SIZE..(index+1)*LEAF_ELEMENT_SIZE)?;
+    let elem = LeafElement::ref_from_bytes(elem_bytes).ok()?;
+    let key = page_body.get(elem.kptr.get() as usize..elem.kptr.get() as usize + elem.ksize.get() as usize)?;
+    let value = page_body.get(elem.vptr.get() as usize..elem.vptr.get() as usize + elem.vsize.get() as usize)?;
+    Some((key, value))
+}
+
+/// Separator key and child page of branch element `index`. The first element has an empty key.
+pub fn branch_entry(page_body: &[u8], index: usize) -> Option<(&[u8], u64)> {
+    let elem_bytes = page_body.get(index*BRANCH_ELEMENT_SIZE..(index+1)*BRANCH_ELEMENT_SIZE)?;
+    let elem = BranchElement::ref_from_bytes(elem_bytes).ok()?;
+    let key = page_body.get(elem.kptr.get() as usize..elem.kptr.get() as usize + elem.ksize.get() as usize)?;
+    Some((key, elem.page_id.get()))
+}
+
+/// Keys under the child of branch element `index`, as kept by trees with order statistics.
+pub fn branch_child_count(page_body: &[u8], index: usize) -> Option<u64> {
+    let elem_bytes = page_body.get(index*BRANCH_ELEMENT_SIZE..(index+1)*BRANCH_ELEMENT_SIZE)?;
+    let elem = BranchElement::ref_from_bytes(elem_bytes).ok()?;
+    Some(elem.count.get() as u64)
+}
+
+/// CRC32C of a page image, computed as if its checksum field were zero.
+pub fn page_checksum(page_bytes: &[u8]) -> u32 {
+    let crc = crc32c::crc32c(&page_bytes[..CHECKSUM_OFFSET]);
+    let crc = crc32c::crc32c_append(crc, &[0; 4]);
+    crc32c::crc32c_append(crc, &page_bytes[CHECKSUM_OFFSET + 4..])
+}
+
+/// Stores the checksum of a page image in its header.
+pub fn seal_page(page_bytes: &mut [u8]) {
+    let checksum = page_checksum(page_bytes);
+    page_bytes[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4].copy_from_slice(&checksum.to_le_bytes());
+}
+
+/// Page ids whose stored bytes have already matched their checksum, so each page
+/// is hashed on its first read rather than every read. Shared by every transaction
+/// of a database; a commit forgets the pages it rewrites.
+#[derive(Default)]
+pub(crate) struct VerifiedPages {
+    pages: Mutex<HashSet<u64>>,
+}
+
+impl VerifiedPages {
+    pub(crate) fn check(&self, page_id: u64, page_bytes: &[u8]) -> Result<(), DbError> {
+        if self.lock().contains(&page_id) {
+            return Ok(());
+        }
+        let found = u32::from_le_bytes(page_bytes[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4].try_into().unwrap());
+        let expected = page_checksum(page_bytes);
+        if found != expected {
+            return Err(DbError::Corrupted { page_id, expected, found });
+        }
+        self.lock().insert(page_id);
+        Ok(())
+    }
+
+    pub(crate) fn forget(&self, page_ids: impl IntoIterator<Item = u64>) {
+        let mut pages = self.lock();
+        for page_id in page_ids {
+            pages.remove(&page_id);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashSet<u64>> {
+        self.pages.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// The bytes of page `page_id` as stored, once they have matched their checksum.
+pub(crate) fn stored_page<'s>(storage: &'s dyn Storage, page_id: u64, page_size: usize, verified: &VerifiedPages) -> Result<&'s [u8], DbError> {
+    let page_bytes = storage.read_page(page_id, page_size)
+        .ok_or(DbError::PageOutOfBounds { page_id, file_size: storage.len() })?;
+    verified.check(page_id, page_bytes)?;
+    Ok(page_bytes)
+}
+
+/// A stored page with its body decrypted if the database is encrypted, see encryption.rs.
+pub(crate) fn plain_page<'s>(page_id: u64, page_bytes: &'s [u8], cipher: Option<&Cipher>) -> Result<Cow<'s, [u8]>, DbError> {
+    match cipher {
+        Some(cipher) => Ok(Cow::Owned(cipher.decrypt(page_id, page_bytes)?)),
+        None => Ok(Cow::Borrowed(page_bytes)),
+    }
+}
+
+/// Page `page_id` as the tree sees it: the stored page, decrypted, and for a Packed page
+/// the leaf decoded from its run, by way of `decoded`.
+pub(crate) fn read_page<'s>(
+    storage: &'s dyn Storage,
+    page_id: u64,
+    page_size: usize,
+    verified: &VerifiedPages,
+    decoded: &'s DecodedPages<'_>,
+) -> Result<&'s [u8], DbError> {
+    let cipher = decoded.cipher;
+    let page_bytes = stored_page(storage, page_id, page_size, verified)?;
+    if cipher.is_none() && page_bytes[8] != PageType::Packed as u8 {
+        return Ok(page_bytes);
+    }
+    decoded.get_or_decode(page_id, || {
+        let page_bytes = plain_page(page_id, page_bytes, cipher)?;
+        if page_bytes[8] != PageType::Packed as u8 {
+            return Ok(page_bytes.into_owned());
+        }
+        compression::unpack(page_id, &page_bytes, &|page_id| {
+            plain_page(page_id, stored_page(storage, page_id, page_size, verified)?, cipher)
+        })
+    })
+}
+
+/// Pages decrypted or leaves decoded from their runs, so a page is decoded on its first
+/// read rather than every read. Shared by every transaction of a database, holding up to
+/// PAGE_CACHE_PAGES of them; a commit forgets the pages it rewrites.
+#[derive(Default)]
+pub(crate) struct PageCache {
+    pages: Mutex<HashMap<u64, Arc<[u8]>>>,
+}
+
+impl PageCache {
+    pub(crate) fn forget(&self, page_ids: impl IntoIterator<Item = u64>) {
+        let mut pages = self.lock();
+        for page_id in page_ids {
+            pages.remove(&page_id);
+        }
+    }
+
+    fn get_or_decode(&self, page_id: u64, decode: impl FnOnce() -> Result<Vec<u8>, DbError>) -> Result<Arc<[u8]>, DbError> {
+        if let Some(page) = self.lock().get(&page_id) {
+            return Ok(page.clone());
+        }
+        let page: Arc<[u8]> = decode()?.into();
+        let mut pages = self.lock();
+        if pages.len() >= PAGE_CACHE_PAGES {
+            // any page will do, the transactions using it hold their own reference
+            let evicted = *pages.keys().next().unwrap();
+            pages.remove(&evicted);
+        }
+        pages.insert(page_id, page.clone());
+        Ok(page)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Arc<[u8]>>> {
+        self.pages.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// The decoded pages one transaction has read, held until it ends so the slices it
+/// hands out stay valid whatever the shared cache evicts meanwhile, and the cipher
+/// they are decrypted with if the database is encrypted.
+pub(crate) struct DecodedPages<'c> {
+    cache: &'c PageCache,
+    cipher: Option<&'c Cipher>,
+    pages: Mutex<HashMap<u64, Arc<[u8]>>>,
+}
 
+impl<'c> DecodedPages<'c> {
+    pub(crate) fn new(cache: &'c PageCache, cipher: Option<&'c Cipher>) -> Self {
+        DecodedPages { cache, cipher, pages: Mutex::new(HashMap::new()) }
+    }
+
+    pub(crate) fn cipher(&self) -> Option<&'c Cipher> {
+        self.cipher
+    }
+
+    fn get_or_decode(&self, page_id: u64, decode: impl FnOnce() -> Result<Vec<u8>, DbError>) -> Result<&[u8], DbError> {
+        let mut pages = self.pages.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let page = match pages.get(&page_id) {
+            Some(page) => page.clone(),
+            None => {
+                let page = self.cache.get_or_decode(page_id, decode)?;
+                pages.insert(page_id, page.clone());
+                page
+            }
+        };
+        // SAFETY: `pages` keeps a reference to the allocation until self is dropped, and
+        // never replaces an entry, so the bytes outlive this borrow of self
+        Ok(unsafe { &*Arc::as_ptr(&page) })
+    }
+}
 
 pub trait PageReader {
-    fn get_page(&self, page_id: u64, highest_page_id: u64) -> Result<&'static Page, PageError>;
+    fn get_page(&self, page_id: u64, highest_page_id: u64, page_size: usize) -> Result<&Page, PageError>;
 }
 
-impl PageReader for MmapMut {
-    fn get_page(&self, page_id: u64, highest_page_id: u64) -> Result<&'static Page, PageError> {
+impl<S: Storage + ?Sized> PageReader for S {
+    fn get_page(&self, page_id: u64, highest_page_id: u64, page_size: usize) -> Result<&Page, PageError> {
         // Logical validation
         if page_id > highest_page_id {
             return Err(PageError::InvalidPageId {
@@ -86,27 +302,20 @@ impl PageReader for MmapMut {
             });
         }
 
-        // Physical validation
-        let offset = page_id as usize * PAGE_SIZE;
-        if offset + PAGE_HEADER_SIZE > self.len() {
-            return Err(PageError::OutOfBounds {
+        // Physical validation. zero-copy, borrowed from the storage for as long as the caller holds it
+        self.read_page(page_id, page_size)
+            .and_then(|page_bytes| Page::ref_from_prefix(page_bytes).ok())
+            .map(|(page, _)| page)
+            .ok_or(PageError::OutOfBounds {
                 page_id,
                 mmap_size: self.len(),
-            });
-        }
-
-        // zero-copy, cast raw pointer to Page reference
-        unsafe {
-            let ptr = self.as_ptr().add(offset) as *const Page;
-            // reference lifetime to 'self' MmapMut reference, (tx RwLock)
-            Ok(&*ptr)
-        }
+            })
     }
 }
 
-impl<'a> PageReader for RwLockReadGuard<'a, MmapMut> {
-    fn get_page(&self, page_id: u64, highest_page_id: u64) -> Result<&'static Page, PageError> {
-        // Delegate to the MmapMut implementation
-        (**self).get_page(page_id, highest_page_id)
+impl<'a> PageReader for RwLockReadGuard<'a, Box<dyn Storage>> {
+    fn get_page(&self, page_id: u64, highest_page_id: u64, page_size: usize) -> Result<&Page, PageError> {
+        // Delegate to the storage implementation
+        (***self).get_page(page_id, highest_page_id, page_size)
     }
 }
@@ -0,0 +1,243 @@
+// Recovers what it can from a damaged file without opening it. Every page is read
+// whether or not the tree still reaches it, and the entries of those that parse as
+// leaves are bulk loaded into a fresh database.
+//
+// Pages carry no transaction id, so when a key turns up more than once the copy
+// reachable from the newest intact header wins, then the copy on the highest page.
+// Pages on the free list hold deleted or superseded entries and are skipped.
+// Compressed leaves are decoded from their runs; a run with a damaged page is lost whole.
+// Pages of an encrypted file are decrypted with the key it is salvaged with, and one
+// that fails its tag counts as damaged.
+use crate::btree::BTreeError;
+use crate::compression;
+use crate::db::{Db, DbError, DbOptions, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE, MIN_PAGE_SIZE};
+use crate::encryption::Cipher;
+use crate::freelist;
+use crate::page::{self, Page, PageType, VerifiedPages};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use zerocopy::FromBytes;
+
+/// What [`salvage`] found in the damaged file and what it had to leave behind.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SalvageReport {
+    /// tx_id of the newest intact header, None if neither header slot could be read.
+    pub tx_id: Option<u64>,
+    /// Why no header could be used, when `tx_id` is None.
+    pub header_error: Option<String>,
+    pub pages_scanned: u64,
+    /// Leaves reached from the root of the newest intact header.
+    pub reachable_leaves: u64,
+    /// Leaves found only by scanning, outside the free list.
+    pub unreachable_leaves: u64,
+    /// Pages that failed their checksum or didn't parse, in page order.
+    pub damaged_pages: Vec<u64>,
+    /// Leaf elements whose key or value lay outside their page.
+    pub lost_elements: u64,
+    pub keys_recovered: u64,
+    /// Keys recovered only from unreachable leaves.
+    pub keys_from_unreachable: u64,
+}
+
+impl fmt::Display for SalvageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.tx_id {
+            Some(tx_id) => write!(f, "header tx_id={}", tx_id)?,
+            None => write!(f, "no intact header ({})", self.header_error.as_deref().unwrap_or("unknown"))?,
+        }
+        write!(f, ", {} pages scanned, {} keys recovered ({} only from unreachable leaves), \
+                   {} reachable and {} unreachable leaves, {} damaged pages, {} elements lost",
+               self.pages_scanned, self.keys_recovered, self.keys_from_unreachable,
+               self.reachable_leaves, self.unreachable_leaves,
+               self.damaged_pages.len(), self.lost_elements)
+    }
+}
+
+enum Scanned {
+    Leaf(Vec<(Vec<u8>, Vec<u8>)>),
+    Branch(Vec<u64>),
+}
+
+/// Copies every entry that can still be read from the file at `src` into a new
+/// database at `dst`, opened with `options` but the page size and compression of
+/// `src`, so every entry fits. `src` is only read; `dst` must not exist.
+///
+/// An encrypted `src` is read with `options.key`, and the copy is encrypted with it
+/// too. If neither header slot is usable, the pages are taken to be encrypted when
+/// `options.key` is set.
+pub fn salvage(src: &Path, dst: &Path, options: DbOptions) -> Result<SalvageReport, BTreeError> {
+    if dst.exists() {
+        return Err(DbError::Io(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", dst.display()),
+        )).into());
+    }
+    let bytes = std::fs::read(src).map_err(DbError::Io)?;
+    let mut report = SalvageReport::default();
+
+    // without an intact header, every page is a candidate and nothing is reachable
+    let (image, page_size, compression, cipher, root_page_id, highest_page_id) = match Db::snapshot(&bytes) {
+        Ok(snapshot) => {
+            if snapshot.comparator != options.comparator.name() {
+                return Err(DbError::ComparatorMismatch {
+                    stored: snapshot.comparator,
+                    requested: options.comparator.name().to_string(),
+                }.into());
+            }
+            let cipher = snapshot.key_check
+                .map(|key_check| Cipher::unlock(&key_check, options.key.as_ref()))
+                .transpose()?;
+            report.tx_id = Some(snapshot.tx_id);
+            let last_page_id = (bytes.len() / snapshot.page_size).saturating_sub(1) as u64;
+            let highest_page_id = snapshot.highest_page_id.min(last_page_id);
+            (snapshot.image, snapshot.page_size, snapshot.compression, cipher, Some(snapshot.root_page_id), highest_page_id)
+        }
+        Err(err) => {
+            let page_size = guess_page_size(&bytes);
+            report.header_error = Some(err.to_string());
+            let cipher = options.key.as_ref().map(Cipher::new).transpose()?;
+            let last_page_id = (bytes.len() / page_size).saturating_sub(1) as u64;
+            (bytes, page_size, options.compression, cipher, None, last_page_id)
+        }
+    };
+
+    let free: HashSet<u64> = freelist::read(&image, page_size, highest_page_id, &VerifiedPages::default(), cipher.as_ref())
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    let mut pages = HashMap::new();
+    for page_id in 1..=highest_page_id {
+        if free.contains(&page_id) {
+            continue;
+        }
+        report.pages_scanned += 1;
+        if let Some(scanned) = scan_page(page_id, &image, page_size, cipher.as_ref(), &mut report) {
+            pages.insert(page_id, scanned);
+        }
+    }
+
+    let reachable = reachable_leaves(&pages, root_page_id);
+    let mut leaf_ids: Vec<u64> = pages.iter()
+        .filter(|(_, scanned)| matches!(scanned, Scanned::Leaf(_)))
+        .map(|(&page_id, _)| page_id)
+        .collect();
+    // reachable leaves first, then the most recently allocated
+    leaf_ids.sort_by_key(|page_id| (!reachable.contains(page_id), std::cmp::Reverse(*page_id)));
+    report.reachable_leaves = reachable.len() as u64;
+    report.unreachable_leaves = (leaf_ids.len() - reachable.len()) as u64;
+
+    let mut entries = Vec::new();
+    for page_id in &leaf_ids {
+        if let Some(Scanned::Leaf(leaf_entries)) = pages.remove(page_id) {
+            let from_reachable = reachable.contains(page_id);
+            entries.extend(leaf_entries.into_iter().map(|(key, value)| (key, value, from_reachable)));
+        }
+    }
+    // the sort is stable, so the preferred copy of each key comes first
+    let comparator = options.comparator.clone();
+    entries.sort_by(|a, b| comparator.compare(&a.0, &b.0));
+    entries.dedup_by(|later, first| comparator.compare(&later.0, &first.0) == Ordering::Equal);
+    report.keys_recovered = entries.len() as u64;
+    report.keys_from_unreachable = entries.iter().filter(|(_, _, from_reachable)| !from_reachable).count() as u64;
+
+    let fill_percent = options.fill_percent;
+    let db = Db::open_with_options(dst, DbOptions { page_size, compression, ..options })?;
+    db.bulk_load(entries.into_iter().map(|(key, value, _)| (key, value)), fill_percent)?;
+    Ok(report)
+}
+
+// The page size either header slot records, even if the slot is damaged elsewhere,
+// or the default if neither holds a plausible one.
+fn guess_page_size(bytes: &[u8]) -> usize {
+    [8, MIN_PAGE_SIZE / 2 + 8].iter()
+        .filter_map(|&offset| bytes.get(offset..offset + 4))
+        .map(|field| u32::from_le_bytes(field.try_into().unwrap()) as usize)
+        .find(|page_size| page_size.is_power_of_two() && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(page_size))
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+}
+
+// A page of the image once it has matched its checksum and holds its own id, decrypted
+// if the file is encrypted.
+fn intact_page<'i>(image: &'i [u8], page_size: usize, page_id: u64, cipher: Option<&Cipher>) -> Result<Cow<'i, [u8]>, DbError> {
+    let page_bytes = usize::try_from(page_id).ok()
+        .and_then(|page_id| image.get(page_id * page_size..(page_id + 1) * page_size))
+        .ok_or(DbError::PageOutOfBounds { page_id, file_size: image.len() })?;
+    let (page, _) = Page::ref_from_prefix(page_bytes).map_err(|_| DbError::PageFormat { page_id, index: None })?;
+    let (expected, found) = (page::page_checksum(page_bytes), page.checksum.get());
+    if page.id.get() != page_id || expected != found {
+        return Err(DbError::Corrupted { page_id, expected, found });
+    }
+    page::plain_page(page_id, page_bytes, cipher)
+}
+
+// The entries or children of a page, or None if it is neither a leaf nor a branch.
+// Damaged pages are noted in the report; never written (all zero) pages are not.
+fn scan_page(page_id: u64, image: &[u8], page_size: usize, cipher: Option<&Cipher>, report: &mut SalvageReport) -> Option<Scanned> {
+    let page_bytes = &image[page_id as usize * page_size..(page_id as usize + 1) * page_size];
+    if page_bytes.iter().all(|&b| b == 0) {
+        return None;
+    }
+    let page_bytes = match intact_page(image, page_size, page_id, cipher) {
+        Ok(head) if head[8] == PageType::Packed as u8 => {
+            match compression::unpack(page_id, &head, &|page_id| intact_page(image, page_size, page_id, cipher)) {
+                Ok(leaf) => Cow::Owned(leaf),
+                Err(_) => {
+                    report.damaged_pages.push(page_id);
+                    return None;
+                }
+            }
+        }
+        Ok(page_bytes) => page_bytes,
+        Err(_) => {
+            report.damaged_pages.push(page_id);
+            return None;
+        }
+    };
+    let Ok((page, body)) = page::parse(page_id, &page_bytes) else {
+        report.damaged_pages.push(page_id);
+        return None;
+    };
+
+    let count = page.count.get() as usize;
+    match page.page_type {
+        t if t == PageType::Leaf as u8 => {
+            let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..count)
+                .filter_map(|index| page::leaf_entry(body, index))
+                .map(|(key, value)| (key.to_vec(), value.to_vec()))
+                .collect();
+            if entries.len() < count {
+                report.lost_elements += (count - entries.len()) as u64;
+                report.damaged_pages.push(page_id);
+            }
+            Some(Scanned::Leaf(entries))
+        }
+        t if t == PageType::Branch as u8 => Some(Scanned::Branch(
+            (0..=count).filter_map(|index| page::branch_entry(body, index)).map(|(_, child_id)| child_id).collect(),
+        )),
+        _ => None,
+    }
+}
+
+// Leaves under `root_page_id`, following only pages that scanned cleanly.
+fn reachable_leaves(pages: &HashMap<u64, Scanned>, root_page_id: Option<u64>) -> HashSet<u64> {
+    let mut leaves = HashSet::new();
+    let mut visited = HashSet::new();
+    let mut pending: Vec<u64> = root_page_id.into_iter().collect();
+    while let Some(page_id) = pending.pop() {
+        if !visited.insert(page_id) {
+            continue;
+        }
+        match pages.get(&page_id) {
+            Some(Scanned::Leaf(_)) => {
+                leaves.insert(page_id);
+            }
+            Some(Scanned::Branch(children)) => pending.extend(children),
+            None => {}
+        }
+    }
+    leaves
+}
@@ -1,10 +1,17 @@
 use std::cmp::Ordering;
 use zerocopy::FromBytes;
+use crate::comparator::Comparator;
 use crate::page::{LeafElement, BranchElement};
 
-pub fn binary_search<F>(start: usize, end: usize, mut compare: F) -> Result<(usize, bool), ()>
+/// Element slot that couldn't be parsed while searching a page.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchError {
+    pub index: usize,
+}
+
+pub fn binary_search<F>(start: usize, end: usize, mut compare: F) -> Result<(usize, bool), SearchError>
 where
-    F: FnMut(usize) -> Result<Ordering, ()>,
+    F: FnMut(usize) -> Result<Ordering, SearchError>,
 {
     let mut left = start;
     let mut right = end;
@@ -33,18 +40,19 @@ pub fn search_leaf_elements(
     page_body: &[u8],
     element_count: usize,
     search_key: &[u8],
-) -> Result<(usize, bool), ()> {
+    comparator: &dyn Comparator,
+) -> Result<(usize, bool), SearchError> {
     let element_size = std::mem::size_of::<LeafElement>();
 
     binary_search(0, element_count, |mid| {
-        let elem_bytes = &page_body[(mid*element_size)..(mid+1)*element_size];
-        let elem = LeafElement::ref_from_bytes(elem_bytes).map_err(|_| ())?;
+        let elem_bytes = page_body.get(mid*element_size..(mid+1)*element_size).ok_or(SearchError { index: mid })?;
+        let elem = LeafElement::ref_from_bytes(elem_bytes).map_err(|_| SearchError { index: mid })?;
 
-        let key_start = elem.kptr as usize;
-        let key_end = key_start + elem.ksize as usize;
-        let stored_key = &page_body[key_start..key_end];
+        let key_start = elem.kptr.get() as usize;
+        let key_end = key_start + elem.ksize.get() as usize;
+        let stored_key = page_body.get(key_start..key_end).ok_or(SearchError { index: mid })?;
 
-        Ok(stored_key.cmp(search_key))
+        Ok(comparator.compare(stored_key, search_key))
     })
 }
 
@@ -52,21 +60,22 @@ pub fn search_branch_elements(
     page_body: &[u8],
     element_count: usize,
     search_key: &[u8],
-) -> Result<(usize, bool), ()> {
+    comparator: &dyn Comparator,
+) -> Result<(usize, bool), SearchError> {
     let element_size = std::mem::size_of::<BranchElement>();
 
     binary_search(1, element_count + 1, |mid| {
-        let elem_bytes = &page_body[(mid*element_size)..(mid+1)*element_size];
-        let elem = BranchElement::ref_from_bytes(elem_bytes).map_err(|_| ())?;
+        let elem_bytes = page_body.get(mid*element_size..(mid+1)*element_size).ok_or(SearchError { index: mid })?;
+        let elem = BranchElement::ref_from_bytes(elem_bytes).map_err(|_| SearchError { index: mid })?;
 
-        if elem.ksize == 0 {
+        if elem.ksize.get() == 0 {
             return Ok(Ordering::Greater);
         }
 
-        let key_start = elem.kptr as usize;
-        let key_end = key_start + elem.ksize as usize;
-        let key_data = &page_body[key_start..key_end];
+        let key_start = elem.kptr.get() as usize;
+        let key_end = key_start + elem.ksize.get() as usize;
+        let key_data = page_body.get(key_start..key_end).ok_or(SearchError { index: mid })?;
 
-        Ok(key_data.cmp(search_key))
+        Ok(comparator.compare(key_data, search_key))
     })
 }
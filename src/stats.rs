@@ -0,0 +1,87 @@
+use crate::db::{DbError, ReadTxn, Result};
+use crate::page::{self, PageType, BRANCH_ELEMENT_SIZE, LEAF_ELEMENT_SIZE, PAGE_HEADER_SIZE};
+use std::fmt;
+
+/// Page counts and fill of a tree, like bbolt's `BucketStats`.
+/// "In use" bytes are element headers plus live key and value bytes; space
+/// orphaned by deletes and overwrites counts as free.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TreeStats {
+    pub key_count: u64,
+    pub depth: usize,
+    pub leaf_pages: u64,
+    pub branch_pages: u64,
+    pub leaf_in_use: u64,
+    pub branch_in_use: u64,
+    pub page_size: usize,
+    /// Bytes per leaf before compression: the page size, less the room encryption needs
+    /// if the database is encrypted, unless leaves are compressed.
+    pub leaf_size: usize,
+}
+
+impl TreeStats {
+    /// Fraction of the leaf page bodies holding live data, 0.0 for an empty tree.
+    pub fn leaf_fill(&self) -> f64 {
+        fill(self.leaf_in_use, self.leaf_pages, self.leaf_size)
+    }
+
+    pub fn branch_fill(&self) -> f64 {
+        fill(self.branch_in_use, self.branch_pages, self.page_size)
+    }
+}
+
+fn fill(in_use: u64, pages: u64, page_size: usize) -> f64 {
+    match pages {
+        0 => 0.0,
+        pages => in_use as f64 / (pages as f64 * page_size.saturating_sub(PAGE_HEADER_SIZE) as f64),
+    }
+}
+
+impl fmt::Display for TreeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} keys, depth {}, {} leaf pages ({:.1}% full), {} branch pages ({:.1}% full)",
+               self.key_count, self.depth,
+               self.leaf_pages, self.leaf_fill() * 100.0,
+               self.branch_pages, self.branch_fill() * 100.0)
+    }
+}
+
+pub(crate) fn collect(txn: &ReadTxn<'_>) -> Result<TreeStats> {
+    let mut stats = TreeStats { page_size: txn.page_size(), leaf_size: txn.leaf_size(), ..TreeStats::default() };
+    visit(txn, txn.root_page_id(), 1, &mut stats)?;
+    Ok(stats)
+}
+
+fn visit(txn: &ReadTxn<'_>, page_id: u64, depth: usize, stats: &mut TreeStats) -> Result<()> {
+    if depth > page::MAX_DEPTH {
+        return Err(DbError::PageFormat { page_id, index: None });
+    }
+    let (page, body) = page::parse(page_id, txn.page_bytes(page_id)?)?;
+    let count = page.count.get() as usize;
+    match page.page_type {
+        t if t == PageType::Leaf as u8 => {
+            stats.depth = stats.depth.max(depth);
+            stats.leaf_pages += 1;
+            stats.key_count += count as u64;
+            let mut in_use = count * LEAF_ELEMENT_SIZE;
+            for index in 0..count {
+                let (key, value) = page::leaf_entry(body, index).ok_or(DbError::PageFormat { page_id, index: Some(index) })?;
+                in_use += key.len() + value.len();
+            }
+            stats.leaf_in_use += in_use as u64;
+        }
+        t if t == PageType::Branch as u8 => {
+            stats.branch_pages += 1;
+            let mut in_use = (count + 1) * BRANCH_ELEMENT_SIZE;
+            for index in 0..=count {
+                let (key, child_id) = page::branch_entry(body, index).ok_or(DbError::PageFormat { page_id, index: Some(index) })?;
+                in_use += key.len();
+                visit(txn, child_id, depth + 1, stats)?;
+            }
+            stats.branch_in_use += in_use as u64;
+        }
+        // nothing has been written yet
+        _ => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,171 @@
+// Backends holding the pages of a database. Reads borrow one contiguous view of
+// every page, so transactions can hand out page bytes for as long as they hold
+// the storage lock; writes only happen under the write lock.
+use memmap2::{MmapMut, MmapOptions};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+mod simulated;
+pub use simulated::{CrashMode, SimulatedDisk, SimulatedStorage};
+
+pub trait Storage: Send + Sync {
+    /// Every stored byte, page 0 first.
+    fn bytes(&self) -> &[u8];
+
+    /// Copies `data` in at byte `offset`, which must lie within the storage.
+    fn write_at(&mut self, offset: usize, data: &[u8]) -> io::Result<()>;
+
+    /// Extends the storage to `len` bytes, reading as zero. Never shrinks it.
+    fn grow(&mut self, len: usize) -> io::Result<()>;
+
+    /// Cuts the storage down to `len` bytes. Never grows it.
+    fn truncate(&mut self, len: usize) -> io::Result<()>;
+
+    /// Makes everything written so far durable.
+    fn sync(&mut self) -> io::Result<()>;
+
+    fn len(&self) -> usize {
+        self.bytes().len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The bytes of page `page_id` in a database of `page_size` byte pages, or None past the end.
+    fn read_page(&self, page_id: u64, page_size: usize) -> Option<&[u8]> {
+        let offset = usize::try_from(page_id).ok()?.checked_mul(page_size)?;
+        self.bytes().get(offset..offset.checked_add(page_size)?)
+    }
+
+    /// Writes whole page images of `page_size` bytes, growing the storage to fit the highest page first.
+    fn write_pages(&mut self, page_size: usize, pages: &[(u64, &[u8])]) -> io::Result<()> {
+        if let Some(highest) = pages.iter().map(|&(page_id, _)| page_id).max() {
+            let required = (highest as usize + 1) * page_size;
+            if required > self.len() {
+                self.grow(required)?;
+            }
+        }
+        for &(page_id, page_bytes) in pages {
+            self.write_at(page_id as usize * page_size, page_bytes)?;
+        }
+        Ok(())
+    }
+}
+
+fn out_of_bounds(offset: usize, len: usize, size: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Write of {} bytes at offset {} is past the end ({} bytes)", len, offset, size),
+    )
+}
+
+/// A file mapped into memory, the default backend of [`Db::open`](crate::db::Db::open).
+pub struct FileStorage {
+    file: File,
+    mmap: MmapMut,
+    // the file's length changed since the last sync, which msync doesn't make durable
+    resized: bool,
+}
+
+impl FileStorage {
+    /// Opens `path`, creating an empty file if there is none.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mmap = unsafe { MmapOptions::new().map_mut(&file)? };
+        Ok(FileStorage { file, mmap, resized: false })
+    }
+}
+
+impl Storage for FileStorage {
+    fn bytes(&self) -> &[u8] {
+        &self.mmap
+    }
+
+    fn write_at(&mut self, offset: usize, data: &[u8]) -> io::Result<()> {
+        let size = self.mmap.len();
+        self.mmap.get_mut(offset..offset + data.len())
+            .ok_or_else(|| out_of_bounds(offset, data.len(), size))?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
+    fn grow(&mut self, len: usize) -> io::Result<()> {
+        if len <= self.mmap.len() {
+            return Ok(());
+        }
+        self.file.set_len(len as u64)?;
+        self.resized = true;
+        // the old mapping can't outlive the lock held by the caller, so remapping is safe
+        self.mmap = unsafe { MmapMut::map_mut(&self.file)? };
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) -> io::Result<()> {
+        if len >= self.mmap.len() {
+            return Ok(());
+        }
+        self.mmap.flush()?;
+        self.file.set_len(len as u64)?;
+        self.resized = true;
+        self.mmap = unsafe { MmapMut::map_mut(&self.file)? };
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.mmap.flush()?;
+        if self.resized {
+            self.file.sync_all()?;
+            self.resized = false;
+        }
+        Ok(())
+    }
+}
+
+/// Pages held in a Vec, gone once the database is dropped. For tests and caches.
+#[derive(Default)]
+pub struct MemoryStorage {
+    bytes: Vec<u8>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn write_at(&mut self, offset: usize, data: &[u8]) -> io::Result<()> {
+        let size = self.bytes.len();
+        self.bytes.get_mut(offset..offset + data.len())
+            .ok_or_else(|| out_of_bounds(offset, data.len(), size))?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
+    fn grow(&mut self, len: usize) -> io::Result<()> {
+        if len > self.bytes.len() {
+            self.bytes.resize(len, 0);
+        }
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) -> io::Result<()> {
+        self.bytes.truncate(len);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
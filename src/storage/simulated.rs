@@ -0,0 +1,203 @@
+// Storage that records every write and sync so tests can cut the power at any point
+// and look at what a real disk might have kept.
+use super::{out_of_bounds, Storage};
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// What survives of the writes made since the last sync when the power is cut.
+/// Size changes always survive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrashMode {
+    /// None of them.
+    DropUnsynced,
+    /// All of them.
+    KeepUnsynced,
+    /// Every other one, as if the disk had reordered them.
+    KeepAlternate,
+    /// All of them, plus the first half of the write the power was cut during.
+    TearLastWrite,
+}
+
+#[derive(Clone)]
+enum Op {
+    Write { offset: usize, data: Vec<u8> },
+    SetLen(usize),
+    Sync,
+}
+
+#[derive(Default)]
+struct DiskState {
+    durable: Vec<u8>,
+    unsynced: Vec<Op>,
+    // the operation the power was cut during
+    interrupted: Option<Op>,
+    operations: u64,
+    crash_at: Option<u64>,
+    crashed: bool,
+    // the one operation to fail without cutting the power
+    fail_at: Option<u64>,
+}
+
+fn apply(bytes: &mut Vec<u8>, op: &Op) {
+    match op {
+        Op::Write { offset, data } => {
+            if bytes.len() < offset + data.len() {
+                bytes.resize(offset + data.len(), 0);
+            }
+            bytes[*offset..offset + data.len()].copy_from_slice(data);
+        }
+        Op::SetLen(len) => bytes.resize(*len, 0),
+        Op::Sync => {}
+    }
+}
+
+/// The medium behind a [`SimulatedStorage`]. Clones share the same disk, so a test
+/// can keep one to crash it and read back what survived.
+#[derive(Clone, Default)]
+pub struct SimulatedDisk {
+    state: Arc<Mutex<DiskState>>,
+}
+
+impl SimulatedDisk {
+    pub fn new() -> Self {
+        SimulatedDisk::default()
+    }
+
+    /// Cuts the power once `operations` more writes, size changes and syncs have
+    /// completed. Every operation after that fails.
+    pub fn crash_after(&self, operations: u64) {
+        let mut state = self.lock();
+        state.crash_at = Some(state.operations + operations);
+    }
+
+    /// Fails the operation after the next `operations` with an IO error, as a full disk
+    /// would. It isn't performed, and the ones after it go through as usual.
+    pub fn fail_after(&self, operations: u64) {
+        let mut state = self.lock();
+        state.fail_at = Some(state.operations + operations);
+    }
+
+    /// Operations completed so far.
+    pub fn operations(&self) -> u64 {
+        self.lock().operations
+    }
+
+    pub fn crashed(&self) -> bool {
+        self.lock().crashed
+    }
+
+    /// The bytes left on the disk if the power went now.
+    pub fn image(&self, mode: CrashMode) -> Vec<u8> {
+        let state = self.lock();
+        let mut bytes = state.durable.clone();
+        let mut writes = 0;
+        for op in &state.unsynced {
+            let keep = match op {
+                Op::Write { .. } => {
+                    writes += 1;
+                    match mode {
+                        CrashMode::DropUnsynced => false,
+                        CrashMode::KeepUnsynced | CrashMode::TearLastWrite => true,
+                        CrashMode::KeepAlternate => writes % 2 == 1,
+                    }
+                }
+                _ => true,
+            };
+            if keep {
+                apply(&mut bytes, op);
+            }
+        }
+        if let (CrashMode::TearLastWrite, Some(Op::Write { offset, data })) = (mode, &state.interrupted) {
+            let torn = Op::Write { offset: *offset, data: data[..data.len() / 2].to_vec() };
+            apply(&mut bytes, &torn);
+        }
+        bytes
+    }
+
+    fn lock(&self) -> MutexGuard<'_, DiskState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Records `op`, or cuts the power instead if its time has come.
+    fn perform(&self, op: Op) -> io::Result<()> {
+        let mut state = self.lock();
+        if state.crashed {
+            return Err(io::Error::other("simulated power loss"));
+        }
+        if state.crash_at.is_some_and(|crash_at| state.operations >= crash_at) {
+            state.crashed = true;
+            state.interrupted = Some(op);
+            return Err(io::Error::other("simulated power loss"));
+        }
+        if state.fail_at == Some(state.operations) {
+            state.fail_at = None;
+            return Err(io::Error::other("simulated write failure"));
+        }
+        state.operations += 1;
+        match op {
+            Op::Sync => {
+                let unsynced = std::mem::take(&mut state.unsynced);
+                for op in &unsynced {
+                    apply(&mut state.durable, op);
+                }
+            }
+            op => state.unsynced.push(op),
+        }
+        Ok(())
+    }
+}
+
+/// A [`Storage`] on a [`SimulatedDisk`]. Reads see every completed write, as they
+/// would through the page cache; only synced writes are sure to survive a crash.
+pub struct SimulatedStorage {
+    bytes: Vec<u8>,
+    disk: SimulatedDisk,
+}
+
+impl SimulatedStorage {
+    /// Storage on `disk`, starting from everything written to it so far.
+    pub fn new(disk: &SimulatedDisk) -> Self {
+        SimulatedStorage {
+            bytes: disk.image(CrashMode::KeepUnsynced),
+            disk: disk.clone(),
+        }
+    }
+}
+
+impl Storage for SimulatedStorage {
+    fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn write_at(&mut self, offset: usize, data: &[u8]) -> io::Result<()> {
+        if offset + data.len() > self.bytes.len() {
+            return Err(out_of_bounds(offset, data.len(), self.bytes.len()));
+        }
+        let op = Op::Write { offset, data: data.to_vec() };
+        self.disk.perform(op.clone())?;
+        apply(&mut self.bytes, &op);
+        Ok(())
+    }
+
+    fn grow(&mut self, len: usize) -> io::Result<()> {
+        if len <= self.bytes.len() {
+            return Ok(());
+        }
+        self.disk.perform(Op::SetLen(len))?;
+        self.bytes.resize(len, 0);
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) -> io::Result<()> {
+        if len >= self.bytes.len() {
+            return Ok(());
+        }
+        self.disk.perform(Op::SetLen(len))?;
+        self.bytes.truncate(len);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.disk.perform(Op::Sync)
+    }
+}
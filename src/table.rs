@@ -0,0 +1,249 @@
+// Typed tables on top of the raw byte tree. Every table lives under its own
+// tuple-encoded name prefix, keys are serialized with the order preserving
+// tuple layer and values with a pluggable codec.
+// Tables rely on bytewise key order, so use them with the default comparator.
+use crate::btree::{BTreeError, WriteTxn};
+use crate::cursor::Iter;
+use crate::db::{DbError, ReadTxn};
+use crate::keys::{self, KeyError, TupleElement};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+
+#[derive(Debug)]
+pub enum TableError {
+    Db(DbError),
+    BTree(BTreeError),
+    EncodeKey(KeyError),
+    DecodeKey { key: Vec<u8>, error: KeyError },
+    EncodeValue { codec: &'static str, message: String },
+    DecodeValue { codec: &'static str, key: Vec<u8>, message: String },
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TableError::Db(err) => write!(f, "{}", err),
+            TableError::BTree(err) => write!(f, "{}", err),
+            TableError::EncodeKey(err) => write!(f, "Failed to encode key: {}", err),
+            TableError::DecodeKey { key, error } => {
+                write!(f, "Failed to decode stored key {:02x?}: {}", key, error)
+            }
+            TableError::EncodeValue { codec, message } => {
+                write!(f, "Failed to encode value with {}: {}", codec, message)
+            }
+            TableError::DecodeValue { codec, key, message } => {
+                write!(f, "Failed to decode value of key {:02x?} with {}: {}", key, codec, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TableError {}
+
+impl From<DbError> for TableError {
+    fn from(err: DbError) -> Self {
+        TableError::Db(err)
+    }
+}
+
+impl From<BTreeError> for TableError {
+    fn from(err: BTreeError) -> Self {
+        TableError::BTree(err)
+    }
+}
+
+type Result<T> = std::result::Result<T, TableError>;
+
+/// Serializes table values.
+pub trait Codec {
+    const NAME: &'static str;
+    fn encode<T: Serialize>(value: &T) -> std::result::Result<Vec<u8>, String>;
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> std::result::Result<T, String>;
+}
+
+#[cfg(feature = "json")]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    const NAME: &'static str = "json";
+
+    fn encode<T: Serialize>(value: &T) -> std::result::Result<Vec<u8>, String> {
+        serde_json::to_vec(value).map_err(|e| e.to_string())
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> std::result::Result<T, String> {
+        serde_json::from_slice(bytes).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(feature = "bincode")]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    const NAME: &'static str = "bincode";
+
+    fn encode<T: Serialize>(value: &T) -> std::result::Result<Vec<u8>, String> {
+        bincode::serialize(value).map_err(|e| e.to_string())
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> std::result::Result<T, String> {
+        bincode::deserialize(bytes).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(feature = "postcard")]
+pub struct Postcard;
+
+#[cfg(feature = "postcard")]
+impl Codec for Postcard {
+    const NAME: &'static str = "postcard";
+
+    fn encode<T: Serialize>(value: &T) -> std::result::Result<Vec<u8>, String> {
+        postcard::to_allocvec(value).map_err(|e| e.to_string())
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> std::result::Result<T, String> {
+        postcard::from_bytes(bytes).map_err(|e| e.to_string())
+    }
+}
+
+/// A transaction a table can read from.
+pub trait Readable {
+    fn get_raw(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+}
+
+impl Readable for ReadTxn<'_> {
+    fn get_raw(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.get(key)?)
+    }
+}
+
+impl Readable for WriteTxn<'_> {
+    fn get_raw(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.get(key)?)
+    }
+}
+
+// fn pointer so tables are Send + Sync whatever K, V and C are
+type Types<K, V, C> = PhantomData<fn() -> (K, V, C)>;
+
+pub struct Table<K, V, C> {
+    prefix: Vec<u8>,
+    _types: Types<K, V, C>,
+}
+
+impl<K, V, C> Table<K, V, C>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    pub fn new(name: &str) -> Self {
+        Table {
+            prefix: keys::pack(&(name,)),
+            _types: PhantomData,
+        }
+    }
+
+    pub fn insert(&self, txn: &mut WriteTxn, key: &K, value: &V) -> Result<()> {
+        let key = self.encode_key(key)?;
+        let value = C::encode(value).map_err(|message| TableError::EncodeValue { codec: C::NAME, message })?;
+        txn.insert(&key, &value)?;
+        Ok(())
+    }
+
+    pub fn get<T: Readable>(&self, txn: &T, key: &K) -> Result<Option<V>> {
+        let key = self.encode_key(key)?;
+        match txn.get_raw(&key)? {
+            Some(value) => Ok(Some(decode_value::<V, C>(&key, &value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes `key`, returning its previous value.
+    pub fn remove(&self, txn: &mut WriteTxn, key: &K) -> Result<Option<V>> {
+        let key = self.encode_key(key)?;
+        let Some(value) = txn.get(&key)? else {
+            return Ok(None);
+        };
+        txn.delete(&key)?;
+        Ok(Some(decode_value::<V, C>(&key, &value)?))
+    }
+
+    /// Iterates over the entries whose keys fall inside `range`, in key order.
+    pub fn range<'t, R: RangeBounds<K>>(&self, txn: &'t ReadTxn<'_>, range: R) -> Result<TableIter<'t, K, V, C>> {
+        let start = match range.start_bound() {
+            Bound::Included(key) => Bound::Included(self.encode_key(key)?),
+            Bound::Excluded(key) => Bound::Excluded(self.encode_key(key)?),
+            Bound::Unbounded => Bound::Included(self.prefix.clone()),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(key) => Bound::Included(self.encode_key(key)?),
+            Bound::Excluded(key) => Bound::Excluded(self.encode_key(key)?),
+            Bound::Unbounded => {
+                let mut end = self.prefix.clone();
+                end.push(0xFF);
+                Bound::Excluded(end)
+            }
+        };
+        let inner = txn.range((start.as_ref().map(Vec::as_slice), end.as_ref().map(Vec::as_slice)))?;
+        Ok(TableIter {
+            inner,
+            prefix_len: self.prefix.len(),
+            _types: PhantomData,
+        })
+    }
+
+    pub fn iter<'t>(&self, txn: &'t ReadTxn<'_>) -> Result<TableIter<'t, K, V, C>> {
+        self.range(txn, ..)
+    }
+
+    fn encode_key(&self, key: &K) -> Result<Vec<u8>> {
+        let element = keys::to_element(key).map_err(TableError::EncodeKey)?;
+        let mut out = self.prefix.clone();
+        element.encode(&mut out, false);
+        Ok(out)
+    }
+}
+
+fn decode_value<V: DeserializeOwned, C: Codec>(key: &[u8], value: &[u8]) -> Result<V> {
+    C::decode(value).map_err(|message| TableError::DecodeValue { codec: C::NAME, key: key.to_vec(), message })
+}
+
+fn decode_key<K: DeserializeOwned>(key: &[u8], prefix_len: usize) -> Result<K> {
+    let corrupt = |error| TableError::DecodeKey { key: key.to_vec(), error };
+    let mut elements = keys::unpack(&key[prefix_len..]).map_err(corrupt)?;
+    if elements.len() != 1 {
+        return Err(corrupt(KeyError::LengthMismatch { expected: 1, found: elements.len() }));
+    }
+    keys::from_element(elements.remove(0)).map_err(corrupt)
+}
+
+/// Iterator over a table range, decoding each entry.
+pub struct TableIter<'t, K, V, C> {
+    inner: Iter<'t>,
+    prefix_len: usize,
+    _types: Types<K, V, C>,
+}
+
+impl<K, V, C> Iterator for TableIter<'_, K, V, C>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+    C: Codec,
+{
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = match self.inner.next()? {
+            Ok(entry) => entry,
+            Err(err) => return Some(Err(err.into())),
+        };
+        Some(decode_key(key, self.prefix_len).and_then(|k| Ok((k, decode_value::<V, C>(key, value)?))))
+    }
+}
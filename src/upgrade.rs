@@ -0,0 +1,168 @@
+// Rewrites files from older format versions in the current one. Every version so far
+// keeps the same leaf and branch elements, so the tree of an old file is walked with
+// the page header of its version and its entries bulk loaded into a new file:
+//
+//   1  16 byte page header, a single header in page 0
+//   2  the current format: 32 byte page headers carrying leaf sibling links and a checksum,
+//      verified as the walk reads the page, two checksummed header slots naming a redo
+//      journal, replayed before the walk, leaves of a database with a codec stored
+//      compressed in runs of pages, and page bodies encrypted when the header has a key check
+use crate::btree::BTreeError;
+use crate::compression;
+use crate::db::{self, Db, DbError, DbOptions, Snapshot, VERSION};
+use crate::encryption::Cipher;
+use crate::page::{self, PageType, VerifiedPages};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// the page header of version 1 files, before leaves carried sibling links
+const V1_PAGE_HEADER_SIZE: usize = 16;
+
+/// The versions [`upgrade`] or [`upgrade_in_place`] converted between.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpgradeReport {
+    pub from_version: u32,
+    pub to_version: u32,
+    /// Keys copied into the new file, 0 if the file was already current.
+    pub keys: u64,
+}
+
+impl fmt::Display for UpgradeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.from_version == self.to_version {
+            write!(f, "already at version {}, nothing to upgrade", self.to_version)
+        } else {
+            write!(f, "upgraded version {} to version {}, {} keys copied", self.from_version, self.to_version, self.keys)
+        }
+    }
+}
+
+/// Writes the contents of the file at `src`, of any version up to the current one,
+/// to a new database at `dst` in the current format. `src` is only read; `dst` must
+/// not exist. The page size, order statistics setting and compression of `src` are
+/// kept whatever `options` says. The copy is encrypted if `options.key` is set, which
+/// has to be the key of `src` if that is encrypted.
+pub fn upgrade(src: &Path, dst: &Path, options: DbOptions) -> Result<UpgradeReport, BTreeError> {
+    if dst.exists() {
+        return Err(DbError::Io(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", dst.display()),
+        )).into());
+    }
+    let bytes = std::fs::read(src).map_err(DbError::Io)?;
+    let snapshot = Db::snapshot_any_version(&bytes)?;
+    convert(&snapshot, dst, options)
+}
+
+/// Upgrades the file at `path` to the current format, replacing it only once the
+/// new file is complete. A file already at the current version is left alone.
+pub fn upgrade_in_place(path: &Path, options: DbOptions) -> Result<UpgradeReport, BTreeError> {
+    let bytes = std::fs::read(path).map_err(DbError::Io)?;
+    let snapshot = Db::snapshot_any_version(&bytes)?;
+    if snapshot.version == VERSION {
+        return Ok(UpgradeReport { from_version: VERSION, to_version: VERSION, keys: 0 });
+    }
+
+    // a file left behind by an upgrade that was cut short holds nothing the original doesn't
+    let mut staging = PathBuf::from(path);
+    staging.as_mut_os_string().push(".upgrade");
+    if staging.exists() {
+        std::fs::remove_file(&staging).map_err(DbError::Io)?;
+    }
+    let report = convert(&snapshot, &staging, options)?;
+    std::fs::rename(&staging, path).map_err(DbError::Io)?;
+    Ok(report)
+}
+
+fn convert(snapshot: &Snapshot, dst: &Path, options: DbOptions) -> Result<UpgradeReport, BTreeError> {
+    if snapshot.comparator != options.comparator.name() {
+        return Err(DbError::ComparatorMismatch {
+            stored: snapshot.comparator.clone(),
+            requested: options.comparator.name().to_string(),
+        }.into());
+    }
+    let cipher = snapshot.key_check
+        .map(|key_check| Cipher::unlock(&key_check, options.key.as_ref()))
+        .transpose()?;
+    let entries = entries(snapshot, cipher.as_ref())?;
+    let keys = entries.len() as u64;
+
+    let fill_percent = options.fill_percent;
+    let options = DbOptions {
+        order_statistics: snapshot.order_statistics,
+        page_size: snapshot.page_size,
+        compression: snapshot.compression,
+        ..options
+    };
+    let db = Db::open_with_options(dst, options)?;
+    db.bulk_load(entries, fill_percent)?;
+    Ok(UpgradeReport { from_version: snapshot.version, to_version: VERSION, keys })
+}
+
+// Every entry of the tree under the snapshot's root, in key order, decrypting pages
+// with `cipher` if the file is encrypted.
+fn entries(snapshot: &Snapshot, cipher: Option<&Cipher>) -> db::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let header_size = if snapshot.version == 1 { V1_PAGE_HEADER_SIZE } else { page::PAGE_HEADER_SIZE };
+    let verified = VerifiedPages::default();
+    let mut visited = HashSet::new();
+    let mut entries = Vec::new();
+    let mut pending = vec![(snapshot.root_page_id, 0)];
+    while let Some((page_id, depth)) = pending.pop() {
+        let page_bytes = page_bytes(&snapshot.image, snapshot.page_size, page_id);
+        // version 1 files left the root unwritten until their first commit
+        if page_id == snapshot.root_page_id && page_bytes.is_none_or(|page_bytes| page_bytes[8] == 0) {
+            break;
+        }
+        let page_bytes = page_bytes.ok_or(DbError::PageOutOfBounds { page_id, file_size: snapshot.image.len() })?;
+        // a page reached twice, or a path longer than any tree, means branches point back at each other
+        if depth == page::MAX_DEPTH || !visited.insert(page_id) {
+            return Err(DbError::PageFormat { page_id, index: None });
+        }
+        if snapshot.version >= 2 {
+            verified.check(page_id, page_bytes)?;
+        }
+        let page_bytes = page::plain_page(page_id, page_bytes, cipher)?;
+        let page_bytes = match page_bytes[8] {
+            t if t == PageType::Packed as u8 && snapshot.version >= 2 => {
+                let stored_page = |page_id| {
+                    let overflow = self::page_bytes(&snapshot.image, snapshot.page_size, page_id)
+                        .ok_or(DbError::PageOutOfBounds { page_id, file_size: snapshot.image.len() })?;
+                    verified.check(page_id, overflow)?;
+                    page::plain_page(page_id, overflow, cipher)
+                };
+                Cow::Owned(compression::unpack(page_id, &page_bytes, &stored_page)?)
+            }
+            _ => page_bytes,
+        };
+
+        let count = u16::from_le_bytes([page_bytes[10], page_bytes[11]]) as usize;
+        let body = &page_bytes[header_size..];
+        match page_bytes[8] {
+            t if t == PageType::Leaf as u8 => {
+                for index in 0..count {
+                    let (key, value) = page::leaf_entry(body, index)
+                        .ok_or(DbError::PageFormat { page_id, index: Some(index) })?;
+                    entries.push((key.to_vec(), value.to_vec()));
+                }
+            }
+            // children go on the stack last first, so they come off in key order
+            t if t == PageType::Branch as u8 => {
+                for index in (0..=count).rev() {
+                    let (_, child_id) = page::branch_entry(body, index)
+                        .ok_or(DbError::PageFormat { page_id, index: Some(index) })?;
+                    pending.push((child_id, depth + 1));
+                }
+            }
+            _ => return Err(DbError::PageFormat { page_id, index: None }),
+        }
+    }
+    Ok(entries)
+}
+
+fn page_bytes(image: &[u8], page_size: usize, page_id: u64) -> Option<&[u8]> {
+    let start = usize::try_from(page_id).ok()?.checked_mul(page_size)?;
+    image.get(start..start.checked_add(page_size)?)
+}
@@ -1,13 +1,11 @@
 use rbolt::db::Db;
-use std::path::Path;
+
+mod common;
+use common::fresh;
 
 #[test]
 fn test_insert_and_get_single_key() {
-    let db_path = Path::new("test_insert_single.rdb");
-
-    if db_path.exists() {
-        std::fs::remove_file(db_path).unwrap();
-    }
+    let db_path = &fresh("test_insert_single.rdb");
 
     {
         let db = Db::open(db_path).unwrap();
@@ -29,11 +27,7 @@ fn test_insert_and_get_single_key() {
 
 #[test]
 fn test_insert_multiple_keys() {
-    let db_path = Path::new("test_insert_multiple.rdb");
-
-    if db_path.exists() {
-        std::fs::remove_file(db_path).unwrap();
-    }
+    let db_path = &fresh("test_insert_multiple.rdb");
 
     {
         let db = Db::open(db_path).unwrap();
@@ -0,0 +1,13 @@
+// Helpers shared by the integration tests. Cargo builds each file in tests/ as its own
+// binary, and they run side by side.
+use std::path::PathBuf;
+
+// A path for the test database `name` with nothing at it yet. It's under the temp
+// directory and this process's id, so no two test binaries ever share a file.
+pub fn fresh(name: &str) -> PathBuf {
+    let db_path = std::env::temp_dir().join(format!("rbolt_{}_{}", std::process::id(), name));
+    if db_path.exists() {
+        std::fs::remove_file(&db_path).unwrap();
+    }
+    db_path
+}
@@ -0,0 +1,102 @@
+use rbolt::btree::BTreeError;
+use rbolt::db::{Db, DbOptions};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_batch_from_many_threads() {
+    let db = Arc::new(Db::open_in_memory().unwrap());
+
+    let handles: Vec<_> = (0..8u32)
+        .map(|t| {
+            let db = Arc::clone(&db);
+            thread::spawn(move || {
+                for i in 0..50u32 {
+                    db.batch(move |txn| {
+                        txn.insert(format!("t{}_k{:03}", t, i).as_bytes(), format!("{}", i).as_bytes())
+                    })
+                    .unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert_eq!(rtxn.iter().unwrap().count(), 400);
+    for t in 0..8 {
+        for i in 0..50 {
+            let value = rtxn.get(format!("t{}_k{:03}", t, i).as_bytes()).unwrap();
+            assert_eq!(value, Some(format!("{}", i).into_bytes()));
+        }
+    }
+    println!("   [OK] 400 batched writes from 8 threads are all visible");
+}
+
+#[test]
+fn test_full_batch_runs_before_the_delay() {
+    let options = DbOptions {
+        max_batch_size: 4,
+        max_batch_delay: Duration::from_secs(30),
+        ..DbOptions::default()
+    };
+    let db = Arc::new(Db::open_in_memory_with_options(options).unwrap());
+
+    // none of these could finish before the delay unless the four of them share a batch
+    let started = Instant::now();
+    let handles: Vec<_> = (0..4u32)
+        .map(|i| {
+            let db = Arc::clone(&db);
+            thread::spawn(move || db.batch(move |txn| txn.insert(format!("key_{}", i).as_bytes(), b"v")))
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap().unwrap();
+    }
+    assert!(started.elapsed() < Duration::from_secs(10));
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert_eq!(rtxn.iter().unwrap().count(), 4);
+    println!("   [OK] Full batch committed after {:?}", started.elapsed());
+}
+
+#[test]
+fn test_failing_call_does_not_poison_the_batch() {
+    let options = DbOptions {
+        max_batch_size: 3,
+        max_batch_delay: Duration::from_secs(30),
+        ..DbOptions::default()
+    };
+    let db = Arc::new(Db::open_in_memory_with_options(options).unwrap());
+
+    let handles: Vec<_> = (0..3u32)
+        .map(|i| {
+            let db = Arc::clone(&db);
+            thread::spawn(move || {
+                db.batch(move |txn| {
+                    txn.insert(format!("key_{}", i).as_bytes(), b"v")?;
+                    if i == 1 {
+                        // larger than any value a page can hold
+                        txn.insert(b"too_large", &vec![0u8; 70_000])?;
+                    }
+                    Ok(())
+                })
+            })
+        })
+        .collect();
+    let results: Vec<_> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+
+    assert!(results[0].is_ok());
+    assert!(matches!(results[1], Err(BTreeError::ValueTooLarge { .. })));
+    assert!(results[2].is_ok());
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert_eq!(rtxn.get(b"key_0").unwrap(), Some(b"v".to_vec()));
+    assert_eq!(rtxn.get(b"key_1").unwrap(), None);
+    assert_eq!(rtxn.get(b"key_2").unwrap(), Some(b"v".to_vec()));
+    assert_eq!(rtxn.get(b"too_large").unwrap(), None);
+    println!("   [OK] Failed call returned its own error, the rest committed");
+}
@@ -0,0 +1,129 @@
+use rbolt::btree::BTreeError;
+use rbolt::db::Db;
+
+mod common;
+use common::fresh;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:06}", i).into_bytes()
+}
+
+fn value(i: u32) -> Vec<u8> {
+    format!("value_{}", i).into_bytes()
+}
+
+#[test]
+fn test_bulk_load_sorted_keys() {
+    let db_path = &fresh("test_bulk_load_sorted.rdb");
+    let db = Db::open(db_path).unwrap();
+
+    let loaded = db.bulk_load((0..20_000u32).map(|i| (key(i), value(i))), 1.0).unwrap();
+    assert_eq!(loaded, 20_000);
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    for i in (0..20_000u32).step_by(97) {
+        assert_eq!(rtxn.get(&key(i)).unwrap(), Some(value(i)));
+    }
+    assert_eq!(rtxn.get(b"key_999999").unwrap(), None);
+
+    let mut count = 0u32;
+    for entry in rtxn.iter().unwrap() {
+        let (k, v) = entry.unwrap();
+        assert_eq!(k, key(count).as_slice());
+        assert_eq!(v, value(count).as_slice());
+        count += 1;
+    }
+    assert_eq!(count, 20_000);
+    println!("   [OK] Bulk loaded 20000 keys and read them back in order");
+
+    drop(rtxn);
+    drop(db);
+
+    // the tree survives a reopen and takes ordinary inserts afterwards
+    let db = Db::open(db_path).unwrap();
+    {
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        wtxn.insert(b"key_010000a", b"between").unwrap();
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert_eq!(rtxn.get(b"key_010000a").unwrap(), Some(b"between".to_vec()));
+    assert_eq!(rtxn.get(&key(10_001)).unwrap(), Some(value(10_001)));
+    assert_eq!(rtxn.iter().unwrap().count(), 20_001);
+
+    drop(rtxn);
+    drop(db);
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_bulk_load_is_denser_than_inserts() {
+    let bulk_path = &fresh("test_bulk_load_dense_bulk.rdb");
+    let insert_path = &fresh("test_bulk_load_dense_insert.rdb");
+
+    {
+        let db = Db::open(bulk_path).unwrap();
+        db.bulk_load((0..5_000u32).map(|i| (key(i), value(i))), 1.0).unwrap();
+    }
+    {
+        let db = Db::open(insert_path).unwrap();
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        // 7919 is coprime with 5000, so this visits every key once out of order
+        for i in (0..5_000u32).map(|i| (i * 7919) % 5_000) {
+            wtxn.insert(&key(i), &value(i)).unwrap();
+        }
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    let bulk_size = std::fs::metadata(bulk_path).unwrap().len();
+    let insert_size = std::fs::metadata(insert_path).unwrap().len();
+    println!("   [OK] Bulk loaded file is {} bytes, inserted file is {} bytes", bulk_size, insert_size);
+    assert!(bulk_size < insert_size);
+
+    std::fs::remove_file(bulk_path).unwrap();
+    std::fs::remove_file(insert_path).unwrap();
+}
+
+#[test]
+fn test_bulk_load_rejects_unsorted_input() {
+    let db = Db::open_in_memory().unwrap();
+
+    let entries = vec![(key(1), value(1)), (key(3), value(3)), (key(2), value(2))];
+    match db.bulk_load(entries, 1.0) {
+        Err(BTreeError::OutOfOrder { index }) => assert_eq!(index, 2),
+        other => panic!("expected OutOfOrder, got {:?}", other),
+    }
+
+    let entries = vec![(key(1), value(1)), (key(1), value(2))];
+    match db.bulk_load(entries, 1.0) {
+        Err(BTreeError::OutOfOrder { index }) => assert_eq!(index, 1),
+        other => panic!("expected OutOfOrder, got {:?}", other),
+    }
+
+    // nothing from the failed loads was committed
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert_eq!(rtxn.get(&key(1)).unwrap(), None);
+    println!("   [OK] Unsorted and duplicate keys were rejected");
+}
+
+#[test]
+fn test_bulk_load_appends_to_existing_tree() {
+    let db = Db::open_in_memory().unwrap();
+
+    db.bulk_load((0..1_000u32).map(|i| (key(i), value(i))), 0.5).unwrap();
+
+    // new keys must sort after the current last key
+    match db.bulk_load(vec![(key(500), value(500))], 1.0) {
+        Err(BTreeError::OutOfOrder { index }) => assert_eq!(index, 0),
+        other => panic!("expected OutOfOrder, got {:?}", other),
+    }
+    let loaded = db.bulk_load((1_000..3_000u32).map(|i| (key(i), value(i))), 1.0).unwrap();
+    assert_eq!(loaded, 2_000);
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    let keys: Vec<_> = rtxn.iter().unwrap().map(|entry| entry.unwrap().0.to_vec()).collect();
+    assert_eq!(keys, (0..3_000u32).map(key).collect::<Vec<_>>());
+    println!("   [OK] Appended 2000 sorted keys to a bulk loaded tree");
+}
@@ -1,16 +1,14 @@
 use rbolt::db::Db;
-use std::path::Path;
+
+mod common;
+use common::fresh;
 
 #[test]
 fn test_compaction_triggered() {
-    let db_path = Path::new("test_compaction.rdb");
-
-    if db_path.exists() {
-        std::fs::remove_file(db_path).ok();
-    }
+    let db_path = &fresh("test_compaction.rdb");
 
     {
-        let db = Db::open(&db_path).unwrap();
+        let db = Db::open(db_path).unwrap();
         let mut wtxn = db.begin_write_transaction().unwrap();
 
         for i in 0..30 {
@@ -24,7 +22,7 @@ fn test_compaction_triggered() {
     }
 
     {
-        let db = Db::open(&db_path).unwrap();
+        let db = Db::open(db_path).unwrap();
         let mut wtxn = db.begin_write_transaction().unwrap();
 
         for i in (0..30).step_by(2) {
@@ -38,7 +36,7 @@ fn test_compaction_triggered() {
     }
 
     {
-        let db = Db::open(&db_path).unwrap();
+        let db = Db::open(db_path).unwrap();
         let mut wtxn = db.begin_write_transaction().unwrap();
 
         for i in 30..50 {
@@ -52,7 +50,7 @@ fn test_compaction_triggered() {
     }
 
     {
-        let db = Db::open(&db_path).unwrap();
+        let db = Db::open(db_path).unwrap();
         let rtxn = db.begin_read_transaction().unwrap();
 
         for i in (0..30).step_by(2) {
@@ -81,19 +79,15 @@ fn test_compaction_triggered() {
         }
     }
 
-    std::fs::remove_file(&db_path).ok();
+    std::fs::remove_file(db_path).ok();
 }
 
 #[test]
 fn test_compaction_vs_split() {
-    let db_path = Path::new("test_compact_vs_split.rdb");
-
-    if db_path.exists() {
-        std::fs::remove_file(db_path).ok();
-    }
+    let db_path = &fresh("test_compact_vs_split.rdb");
 
     {
-        let db = Db::open(&db_path).unwrap();
+        let db = Db::open(db_path).unwrap();
         let mut wtxn = db.begin_write_transaction().unwrap();
 
         for i in 0..100 {
@@ -107,7 +101,7 @@ fn test_compaction_vs_split() {
     }
 
     {
-        let db = Db::open(&db_path).unwrap();
+        let db = Db::open(db_path).unwrap();
         let mut wtxn = db.begin_write_transaction().unwrap();
 
         for i in (0..100).step_by(3) {
@@ -121,7 +115,7 @@ fn test_compaction_vs_split() {
     }
 
     {
-        let db = Db::open(&db_path).unwrap();
+        let db = Db::open(db_path).unwrap();
         let mut wtxn = db.begin_write_transaction().unwrap();
 
         for i in 100..120 {
@@ -135,7 +129,7 @@ fn test_compaction_vs_split() {
     }
 
     {
-        let db = Db::open(&db_path).unwrap();
+        let db = Db::open(db_path).unwrap();
         let rtxn = db.begin_read_transaction().unwrap();
 
         for i in 0..120 {
@@ -145,5 +139,5 @@ fn test_compaction_vs_split() {
         }
     }
 
-    std::fs::remove_file(&db_path).ok();
+    std::fs::remove_file(db_path).ok();
 }
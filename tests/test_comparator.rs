@@ -0,0 +1,133 @@
+use rbolt::comparator::Comparator;
+use rbolt::db::{Db, DbError, DbOptions};
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+mod common;
+use common::fresh;
+
+struct U64LittleEndian;
+
+impl Comparator for U64LittleEndian {
+    fn name(&self) -> &str {
+        "test.u64le"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        let a = u64::from_le_bytes(a.try_into().unwrap());
+        let b = u64::from_le_bytes(b.try_into().unwrap());
+        a.cmp(&b)
+    }
+}
+
+struct CaseInsensitive;
+
+impl Comparator for CaseInsensitive {
+    fn name(&self) -> &str {
+        "test.case_insensitive"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase())
+    }
+}
+
+fn options(comparator: Arc<dyn Comparator>) -> DbOptions {
+    DbOptions { comparator, ..DbOptions::default() }
+}
+
+#[test]
+fn test_little_endian_integer_keys() {
+    let db_path = &fresh("test_comparator_u64.rdb");
+
+    {
+        let db = Db::open_with_options(db_path, options(Arc::new(U64LittleEndian))).unwrap();
+        let mut wtxn = db.begin_write_transaction().unwrap();
+
+        // 255 and 256 are out of order bytewise in little endian
+        for i in (0u64..600).rev() {
+            let value = format!("value_{}", i);
+            wtxn.insert(&i.to_le_bytes(), value.as_bytes()).unwrap();
+        }
+
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    {
+        let db = Db::open_with_options(db_path, options(Arc::new(U64LittleEndian))).unwrap();
+        let rtxn = db.begin_read_transaction().unwrap();
+
+        for i in 0u64..600 {
+            let expected_value = format!("value_{}", i);
+            let result = rtxn.get(&i.to_le_bytes()).unwrap();
+            assert_eq!(result, Some(expected_value.as_bytes().to_vec()), "Key {} mismatch", i);
+        }
+        assert_eq!(rtxn.get(&600u64.to_le_bytes()).unwrap(), None);
+    }
+
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_comparator_defines_key_equality() {
+    let db_path = &fresh("test_comparator_case.rdb");
+
+    {
+        let db = Db::open_with_options(db_path, options(Arc::new(CaseInsensitive))).unwrap();
+        let mut wtxn = db.begin_write_transaction().unwrap();
+
+        for i in 0..200 {
+            let key = format!("key_{:04}", i);
+            wtxn.insert(key.as_bytes(), b"lower").unwrap();
+        }
+        // overwrites land on full pages, so these also go through split_leaf
+        for i in 0..200 {
+            let key = format!("KEY_{:04}", i);
+            wtxn.insert(key.as_bytes(), b"upper").unwrap();
+        }
+
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    {
+        let db = Db::open_with_options(db_path, options(Arc::new(CaseInsensitive))).unwrap();
+        let rtxn = db.begin_read_transaction().unwrap();
+
+        for i in 0..200 {
+            let key = format!("Key_{:04}", i);
+            assert_eq!(rtxn.get(key.as_bytes()).unwrap(), Some(b"upper".to_vec()), "Key {} mismatch", key);
+        }
+    }
+
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_reopen_with_other_comparator_is_rejected() {
+    let db_path = &fresh("test_comparator_mismatch.rdb");
+
+    {
+        let db = Db::open_with_options(db_path, options(Arc::new(CaseInsensitive))).unwrap();
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        wtxn.insert(b"hello", b"world").unwrap();
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    match Db::open(db_path) {
+        Err(DbError::ComparatorMismatch { stored, requested }) => {
+            assert_eq!(stored, "test.case_insensitive");
+            assert_eq!(requested, "rbolt.bytewise");
+        }
+        other => panic!("expected comparator mismatch, got {:?}", other.err()),
+    }
+
+    assert!(matches!(
+        Db::open_with_options(db_path, options(Arc::new(U64LittleEndian))),
+        Err(DbError::ComparatorMismatch { .. })
+    ));
+
+    std::fs::remove_file(db_path).unwrap();
+}
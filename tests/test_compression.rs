@@ -0,0 +1,278 @@
+use rbolt::checksum::Checksum;
+use rbolt::compression::{Compression, LEAF_SPAN};
+use rbolt::btree::BTreeError;
+use rbolt::db::{Db, DbError, DbOptions, DEFAULT_PAGE_SIZE, HEADER_SIZE};
+use rbolt::page::{BRANCH_ELEMENT_SIZE, PAGE_HEADER_SIZE};
+use rbolt::salvage::salvage;
+use rbolt::upgrade::upgrade;
+
+mod common;
+use common::fresh;
+
+// The codecs this build was compiled with; the tests below check each of them
+fn codecs() -> Vec<Compression> {
+    [Compression::Lz4, Compression::Zstd].into_iter().filter(|codec| codec.is_available()).collect()
+}
+
+fn options(compression: Compression) -> DbOptions {
+    DbOptions { compression, ..DbOptions::default() }
+}
+
+fn key(i: u32) -> Vec<u8> {
+    format!("order:{:06}", i).into_bytes()
+}
+
+// JSON like the values the feature is for, about 200 bytes and very repetitive
+fn json(i: u32, round: u32) -> Vec<u8> {
+    format!(r#"{{"id":{},"round":{},"customer":{{"name":"customer {}","country":"NZ","tier":"gold"}},"items":[{{"sku":"sku-{}","qty":{},"price":19.99}}],"status":"shipped"}}"#,
+            i, round, i % 97, i % 13, i % 5 + 1).into_bytes()
+}
+
+// Bytes no codec can shrink
+fn noise(seed: u32, len: usize) -> Vec<u8> {
+    let mut state = (seed as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+    (0..len).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as u8
+    }).collect()
+}
+
+fn commit_all(db: &Db, entries: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>) {
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    for (key, value) in entries {
+        wtxn.insert(&key, &value).unwrap();
+    }
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+}
+
+fn entries(db: &Db) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let rtxn = db.begin_read_transaction().unwrap();
+    rtxn.iter().unwrap().map(|entry| {
+        let (key, value) = entry.unwrap();
+        (key.to_vec(), value.to_vec())
+    }).collect()
+}
+
+#[test]
+fn test_compressed_leaves_shrink_the_file() {
+    let expected: Vec<_> = (0..3_000).map(|i| (key(i), json(i, 0))).collect();
+
+    let plain_path = &fresh("test_compression_plain.rdb");
+    commit_all(&Db::open(plain_path).unwrap(), expected.clone());
+    let plain_size = std::fs::metadata(plain_path).unwrap().len();
+
+    for codec in codecs() {
+        let db_path = &fresh("test_compression_shrink.rdb");
+        let db = Db::open_with_options(db_path, options(codec)).unwrap();
+        commit_all(&db, expected.clone());
+        assert_eq!(entries(&db), expected);
+        drop(db);
+
+        let size = std::fs::metadata(db_path).unwrap().len();
+        assert!(size * 2 < plain_size, "{:?}: {} bytes, uncompressed {}", codec, size, plain_size);
+        println!("   [OK] {:?}: {} bytes against {} uncompressed", codec, size, plain_size);
+
+        // the codec is kept by the file, whatever it is opened with later
+        let db = Db::open(db_path).unwrap();
+        assert_eq!(db.compression(), codec);
+        assert_eq!(entries(&db), expected);
+        let stats = db.begin_read_transaction().unwrap().stats().unwrap();
+        assert_eq!(stats.leaf_size, DEFAULT_PAGE_SIZE * LEAF_SPAN);
+        assert_eq!(stats.key_count, 3_000);
+        println!("   [OK] {:?}: reopened, {}", codec, stats);
+        drop(db);
+
+        std::fs::remove_file(db_path).unwrap();
+    }
+    std::fs::remove_file(plain_path).unwrap();
+}
+
+#[test]
+fn test_mixed_runs_and_rewrites() {
+    for codec in codecs() {
+        let db_path = &fresh("test_compression_mixed.rdb");
+        let db = Db::open_with_options(db_path, options(codec)).unwrap();
+
+        // leaves that compress, and leaves of noise stored as runs as they are,
+        // with values longer than a page in both
+        let value = |i: u32, round: u32| match i % 4 {
+            0 => noise(i + round * 1_000, 3_000),
+            1 => json(i, round).repeat(30),
+            _ => json(i, round),
+        };
+        let mut sizes = Vec::new();
+        for round in 0..6 {
+            commit_all(&db, (0..400).map(|i| (key(i), value(i, round))));
+            sizes.push(std::fs::metadata(db_path).unwrap().len());
+        }
+        let expected: Vec<_> = (0..400).map(|i| (key(i), value(i, 5))).collect();
+        assert_eq!(entries(&db), expected);
+
+        // rewriting every leaf frees the runs of the old ones, so the file stops growing
+        assert!(sizes[5] <= sizes[2] * 3 / 2, "{:?}: file grew from {} to {} bytes", codec, sizes[2], sizes[5]);
+        println!("   [OK] {:?}: six rounds of rewrites, file sizes {:?}", codec, sizes);
+
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        assert_eq!(wtxn.delete_range(key(100).as_slice()..key(300).as_slice()).unwrap(), 200);
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+        drop(db);
+
+        let db = Db::open(db_path).unwrap();
+        let expected: Vec<_> = expected.into_iter().filter(|(key, _)| !(100..300).contains(&key_index(key))).collect();
+        assert_eq!(entries(&db), expected);
+        println!("   [OK] {:?}: {} keys left after deleting a range and reopening", codec, expected.len());
+        drop(db);
+
+        std::fs::remove_file(db_path).unwrap();
+    }
+}
+
+fn key_index(key: &[u8]) -> u32 {
+    std::str::from_utf8(&key[6..]).unwrap().parse().unwrap()
+}
+
+#[test]
+fn test_damaged_overflow_page_is_detected() {
+    for codec in codecs() {
+        let db_path = &fresh("test_compression_damaged.rdb");
+        let db = Db::open_with_options(db_path, options(codec)).unwrap();
+        commit_all(&db, (0..20).map(|i| (key(i), noise(i, 3_000))));
+        drop(db);
+
+        let mut bytes = std::fs::read(db_path).unwrap();
+        let overflow_page = (3..bytes.len() / DEFAULT_PAGE_SIZE)
+            .find(|page_id| bytes[page_id * DEFAULT_PAGE_SIZE + 8] == 6)
+            .expect("runs of noise have overflow pages");
+        bytes[overflow_page * DEFAULT_PAGE_SIZE + 100] ^= 0xFF;
+        std::fs::write(db_path, &bytes).unwrap();
+
+        let db = Db::open(db_path).unwrap();
+        let rtxn = db.begin_read_transaction().unwrap();
+        let damaged = (0..20).map(|i| rtxn.get(&key(i))).find_map(Result::err);
+        match damaged {
+            Some(DbError::Corrupted { page_id, .. }) => assert_eq!(page_id, overflow_page as u64),
+            Some(other) => panic!("expected Corrupted, got {}", other),
+            None => panic!("the damaged overflow page went unnoticed"),
+        }
+        println!("   [OK] Damaged overflow page {} was caught by its checksum", overflow_page);
+        drop(rtxn);
+        drop(db);
+
+        std::fs::remove_file(db_path).unwrap();
+    }
+}
+
+#[test]
+fn test_salvage_and_upgrade_keep_compression() {
+    for codec in codecs() {
+        let db_path = &fresh("test_compression_src.rdb");
+        let salvaged = &fresh("test_compression_salvaged.rdb");
+        let copied = &fresh("test_compression_copied.rdb");
+        let db = Db::open_with_options(db_path, options(codec)).unwrap();
+        // values longer than a page only fit in the leaves of a compressed database
+        let expected: Vec<_> = (0..300).map(|i| (key(i), json(i, 0).repeat(i as usize % 3 * 20 + 1))).collect();
+        commit_all(&db, expected.clone());
+        drop(db);
+
+        let report = salvage(db_path, salvaged, DbOptions::default()).unwrap();
+        assert_eq!(report.keys_recovered, 300);
+        assert!(report.damaged_pages.is_empty());
+        let report = upgrade(db_path, copied, DbOptions::default()).unwrap();
+        assert_eq!((report.from_version, report.keys), (2, 300));
+
+        for db_path in [salvaged, copied] {
+            let db = Db::open(db_path).unwrap();
+            assert_eq!(db.compression(), codec);
+            assert_eq!(entries(&db), expected);
+        }
+        println!("   [OK] Salvaged and upgraded copies kept {:?} and every entry", codec);
+
+        for db_path in [db_path, salvaged, copied] {
+            std::fs::remove_file(db_path).unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_large_value_into_empty_database() {
+    let db_path = &fresh("test_compression_empty.rdb");
+    for codec in codecs() {
+        // the root leaf of a new file spans as many pages as any other leaf
+        let db = Db::open_with_options(db_path, options(codec)).unwrap();
+        commit_all(&db, [(b"k".to_vec(), vec![7; 6_000])]);
+        commit_all(&db, [(b"noise".to_vec(), noise(1, 6_000))]);
+        drop(db);
+
+        let db = Db::open(db_path).unwrap();
+        assert_eq!(entries(&db), vec![(b"k".to_vec(), vec![7; 6_000]), (b"noise".to_vec(), noise(1, 6_000))]);
+        println!("   [OK] {:?}: 6000 byte values went into an empty database", codec);
+        drop(db);
+        fresh("test_compression_empty.rdb");
+    }
+}
+
+#[test]
+fn test_key_size_is_limited_by_branch_pages() {
+    for codec in codecs() {
+        let db = Db::open_in_memory_with_options(options(codec)).unwrap();
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        // leaves span several pages, but separators still go into one page branches
+        let max_key_size = wtxn.max_key_size();
+        assert_eq!(max_key_size, (DEFAULT_PAGE_SIZE - PAGE_HEADER_SIZE - BRANCH_ELEMENT_SIZE) / 2 - BRANCH_ELEMENT_SIZE);
+        for key_size in [max_key_size + 1, 6_000] {
+            match wtxn.insert(&vec![b'k'; key_size], b"v") {
+                Err(BTreeError::KeyTooLarge { max_size, .. }) => assert_eq!(max_size, max_key_size),
+                Err(other) => panic!("expected KeyTooLarge, got {}", other),
+                Ok(()) => panic!("{:?}: a key of {} bytes was taken", codec, key_size),
+            }
+        }
+
+        for i in (0..300).map(|i| (i * 7919) % 300) {
+            let mut key = key(i);
+            key.extend(noise(i, max_key_size - key.len()));
+            wtxn.insert(&key, &json(i, 0)).unwrap();
+        }
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+        let found = entries(&db);
+        assert_eq!(found.len(), 300);
+        assert!(found.iter().enumerate().all(|(i, (key, value))| key.len() == max_key_size && *value == json(i as u32, 0)));
+        println!("   [OK] {:?}: {} byte keys: {}", codec, max_key_size, db.begin_read_transaction().unwrap().stats().unwrap());
+    }
+}
+
+#[test]
+fn test_unavailable_codec_is_rejected() {
+    let db_path = &fresh("test_compression_codec.rdb");
+    for codec in [Compression::Lz4, Compression::Zstd] {
+        if !codec.is_available() {
+            match Db::open_with_options(db_path, options(codec)) {
+                Err(DbError::UnsupportedCodec { codec: found }) => assert_eq!(found, codec as u8),
+                Err(other) => panic!("expected UnsupportedCodec, got {}", other),
+                Ok(_) => panic!("created a {:?} database without the feature", codec),
+            }
+            println!("   [OK] {:?} was refused by a build without it", codec);
+            fresh("test_compression_codec.rdb");
+        }
+    }
+
+    // a codec no build knows, in a header that still checks out
+    drop(Db::open(db_path).unwrap());
+    let mut bytes = std::fs::read(db_path).unwrap();
+    bytes[13] = 9;
+    let mut checksum = Checksum::new();
+    checksum.update(&bytes[..HEADER_SIZE - 8]);
+    bytes[HEADER_SIZE - 8..HEADER_SIZE].copy_from_slice(&checksum.finish().to_le_bytes());
+    std::fs::write(db_path, &bytes).unwrap();
+    match Db::open(db_path) {
+        Err(err @ DbError::UnsupportedCodec { codec: 9 }) => println!("   [OK] {}", err),
+        Err(other) => panic!("expected UnsupportedCodec, got {}", other),
+        Ok(_) => panic!("expected UnsupportedCodec, the file opened"),
+    }
+
+    std::fs::remove_file(db_path).unwrap();
+}
@@ -0,0 +1,77 @@
+use rbolt::btree::CompareAndSwapError;
+use rbolt::db::Db;
+
+mod common;
+use common::fresh;
+
+#[test]
+fn test_compare_and_swap() {
+    let db = Db::open_in_memory().unwrap();
+    let mut wtxn = db.begin_write_transaction().unwrap();
+
+    // None as expected value means "only if absent"
+    assert_eq!(wtxn.compare_and_swap(b"version", None, Some(b"1")).unwrap(), Ok(()));
+    assert_eq!(
+        wtxn.compare_and_swap(b"version", None, Some(b"2")).unwrap(),
+        Err(CompareAndSwapError { current: Some(b"1".to_vec()), proposed: Some(b"2".to_vec()) })
+    );
+    assert_eq!(wtxn.get(b"version").unwrap(), Some(b"1".to_vec()));
+
+    assert_eq!(wtxn.compare_and_swap(b"version", Some(b"1"), Some(b"2")).unwrap(), Ok(()));
+    assert_eq!(wtxn.get(b"version").unwrap(), Some(b"2".to_vec()));
+
+    // None as new value deletes
+    let stale = wtxn.compare_and_swap(b"version", Some(b"1"), None).unwrap();
+    assert_eq!(stale.unwrap_err().current, Some(b"2".to_vec()));
+    assert_eq!(wtxn.compare_and_swap(b"version", Some(b"2"), None).unwrap(), Ok(()));
+    assert_eq!(wtxn.get(b"version").unwrap(), None);
+
+    let missing = wtxn.compare_and_swap(b"other", Some(b"x"), Some(b"y")).unwrap();
+    assert_eq!(missing.unwrap_err().current, None);
+    assert_eq!(wtxn.get(b"other").unwrap(), None);
+    println!("   [OK] Compare and swap applied only on matching values");
+}
+
+#[test]
+fn test_insert_if_absent_and_get_and_replace() {
+    let db_path = &fresh("test_conditional_insert.rdb");
+    {
+        let db = Db::open(db_path).unwrap();
+        let mut wtxn = db.begin_write_transaction().unwrap();
+
+        assert!(wtxn.insert_if_absent(b"a", b"first").unwrap());
+        assert!(!wtxn.insert_if_absent(b"a", b"second").unwrap());
+        assert_eq!(wtxn.get(b"a").unwrap(), Some(b"first".to_vec()));
+
+        assert_eq!(wtxn.get_and_replace(b"a", b"third").unwrap(), Some(b"first".to_vec()));
+        assert_eq!(wtxn.get_and_replace(b"b", b"new").unwrap(), None);
+
+        // conditional writes split pages like plain inserts
+        for i in 0..1_000u32 {
+            let key = format!("key_{:04}", i);
+            assert!(wtxn.insert_if_absent(key.as_bytes(), b"v").unwrap());
+        }
+        for i in 0..1_000u32 {
+            let key = format!("key_{:04}", i);
+            assert_eq!(wtxn.get_and_replace(key.as_bytes(), b"w").unwrap(), Some(b"v".to_vec()));
+        }
+
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    let db = Db::open(db_path).unwrap();
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert_eq!(rtxn.get(b"a").unwrap(), Some(b"third".to_vec()));
+    assert_eq!(rtxn.get(b"b").unwrap(), Some(b"new".to_vec()));
+    assert_eq!(rtxn.iter().unwrap().count(), 1_002);
+    assert!(rtxn.iter().unwrap().all(|entry| {
+        let (key, value) = entry.unwrap();
+        !key.starts_with(b"key_") || value == b"w"
+    }));
+    println!("   [OK] insert_if_absent and get_and_replace persisted");
+
+    drop(rtxn);
+    drop(db);
+    std::fs::remove_file(db_path).unwrap();
+}
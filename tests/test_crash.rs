@@ -0,0 +1,194 @@
+use rbolt::btree::WriteTxn;
+use rbolt::db::{Db, DbError, DbOptions};
+use rbolt::storage::{CrashMode, SimulatedDisk, SimulatedStorage};
+use std::collections::BTreeMap;
+
+mod common;
+use common::fresh;
+
+const TRANSACTIONS: usize = 6;
+const MODES: [CrashMode; 4] = [
+    CrashMode::DropUnsynced,
+    CrashMode::KeepUnsynced,
+    CrashMode::KeepAlternate,
+    CrashMode::TearLastWrite,
+];
+
+type Model = BTreeMap<Vec<u8>, Vec<u8>>;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+fn put(wtxn: &mut WriteTxn<'_>, model: &mut Model, i: u32, value: Vec<u8>) {
+    wtxn.insert(&key(i), &value).unwrap();
+    model.insert(key(i), value);
+}
+
+// Transaction `n` of the workload: splits, overwrites, range deletes that free pages,
+// inserts that reuse them, and a clear
+fn apply(n: usize, wtxn: &mut WriteTxn<'_>, model: &mut Model) {
+    match n {
+        0 => (0..300).for_each(|i| put(wtxn, model, i, format!("value_{}", i).into_bytes())),
+        1 => (0..300).step_by(3).for_each(|i| put(wtxn, model, i, vec![b'x'; 40])),
+        2 => {
+            let (start, end) = (key(50), key(150));
+            wtxn.delete_range(start.as_slice()..end.as_slice()).unwrap();
+            model.retain(|k, _| *k < start || *k >= end);
+        }
+        3 => (1_000..1_200).for_each(|i| put(wtxn, model, i, vec![b'y'; 24])),
+        4 => {
+            for i in (0..1_200).step_by(5) {
+                wtxn.delete(&key(i)).unwrap();
+                model.remove(&key(i));
+            }
+        }
+        _ => {
+            wtxn.clear().unwrap();
+            model.clear();
+            (0..20).for_each(|i| put(wtxn, model, i, b"after clear".to_vec()));
+        }
+    }
+}
+
+// Runs the workload on `disk`, cutting the power after `crash_after` storage operations.
+// Returns the number of commits that went through and the operations the workload took.
+fn run(disk: &SimulatedDisk, crash_after: Option<u64>) -> (usize, u64) {
+    let db = Db::open_with_storage(Box::new(SimulatedStorage::new(disk)), DbOptions::default()).unwrap();
+    let opened = disk.operations();
+    if let Some(operations) = crash_after {
+        disk.crash_after(operations);
+    }
+
+    let mut model = Model::new();
+    let mut committed = 0;
+    for n in 0..TRANSACTIONS {
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        apply(n, &mut wtxn, &mut model);
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        if db.commit(dirty_pages, highest_page_id, root_page_id).is_err() {
+            break;
+        }
+        committed += 1;
+    }
+    (committed, disk.operations() - opened)
+}
+
+fn contents(db: &Db) -> Model {
+    db.begin_read_transaction().unwrap().iter().unwrap()
+        .map(|entry| entry.map(|(k, v)| (k.to_vec(), v.to_vec())).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_crash_at_every_step() {
+    let db_path = &fresh("test_crash_image.rdb");
+
+    // the database after each prefix of the workload
+    let mut states = vec![Model::new()];
+    {
+        let db = Db::open_in_memory().unwrap();
+        let mut model = Model::new();
+        for n in 0..TRANSACTIONS {
+            let mut wtxn = db.begin_write_transaction().unwrap();
+            apply(n, &mut wtxn, &mut model);
+            let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+            db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+            states.push(model.clone());
+        }
+    }
+
+    let (committed, total) = run(&SimulatedDisk::new(), None);
+    assert_eq!(committed, TRANSACTIONS);
+    println!("   [OK] Workload of {} transactions takes {} storage operations", TRANSACTIONS, total);
+
+    let (mut rolled_back, mut rolled_forward) = (0, 0);
+    for crash_after in 0..total {
+        let disk = SimulatedDisk::new();
+        let (committed, _) = run(&disk, Some(crash_after));
+        assert!(disk.crashed());
+
+        for mode in MODES {
+            std::fs::write(db_path, disk.image(mode)).unwrap();
+            let db = Db::open(db_path)
+                .unwrap_or_else(|err| panic!("reopen after {} operations, {:?}: {}", crash_after, mode, err));
+            let found = contents(&db);
+            // a commit interrupted after its header was written is finished on reopen
+            if found == states[committed] {
+                rolled_back += 1;
+            } else if found == states[committed + 1] {
+                rolled_forward += 1;
+            } else {
+                panic!("after {} operations, {:?}: {} keys match neither {} nor {} commits",
+                       crash_after, mode, found.len(), committed, committed + 1);
+            }
+
+            // the recovered file takes further writes
+            let mut wtxn = db.begin_write_transaction().unwrap();
+            wtxn.insert(b"zz_after_crash", b"v").unwrap();
+            let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+            db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+            assert_eq!(db.begin_read_transaction().unwrap().stats().unwrap().key_count, found.len() as u64 + 1);
+        }
+    }
+    println!("   [OK] {} crash images: {} rolled back, {} rolled forward", total * MODES.len() as u64, rolled_back, rolled_forward);
+    assert!(rolled_back > 0 && rolled_forward > 0);
+
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_failed_commit_keeps_the_last_root() {
+    // fail the commit that splits the root at each of its storage operations in turn
+    let (mut kept, mut poisoned) = (0, 0);
+    for fail_after in 0.. {
+        let disk = SimulatedDisk::new();
+        let db = Db::open_with_storage(Box::new(SimulatedStorage::new(&disk)), DbOptions::default()).unwrap();
+        let mut model = Model::new();
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        (0..10).for_each(|i| put(&mut wtxn, &mut model, i, b"v".to_vec()));
+        wtxn.commit().unwrap();
+        let root_page_id = db.begin_read_transaction().unwrap().root_page_id();
+        let before = model.clone();
+
+        disk.fail_after(fail_after);
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        (10..300).for_each(|i| put(&mut wtxn, &mut model, i, format!("value_{}", i).into_bytes()));
+        if wtxn.commit().is_ok() {
+            assert_ne!(db.begin_read_transaction().unwrap().root_page_id(), root_page_id);
+            assert_eq!(contents(&db), model);
+            println!("   [OK] A commit failed at each of its {} operations: {} kept the last commit, {} needed a reopen",
+                     fail_after, kept, poisoned);
+            break;
+        }
+
+        // either the last commit is still what's read, or nothing is until the database is reopened
+        let (db, mut model) = match db.begin_read_transaction().map(drop) {
+            Ok(()) => {
+                assert_eq!(db.begin_read_transaction().unwrap().root_page_id(), root_page_id,
+                           "root published by a commit that failed after {} operations", fail_after);
+                assert_eq!(contents(&db), before);
+                kept += 1;
+                (db, before)
+            }
+            Err(DbError::Poisoned) => {
+                assert!(matches!(db.begin_write_transaction().map(drop), Err(DbError::Poisoned)));
+                drop(db);
+                let db = Db::open_with_storage(Box::new(SimulatedStorage::new(&disk)), DbOptions::default()).unwrap();
+                let found = contents(&db);
+                assert!(found == before || found == model,
+                        "after failing at operation {}, {} keys match neither commit", fail_after, found.len());
+                poisoned += 1;
+                (db, found)
+            }
+            Err(other) => panic!("expected the last commit or Poisoned, got {}", other),
+        };
+
+        // the next commit goes through on top of whichever one is there
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        (300..320).for_each(|i| put(&mut wtxn, &mut model, i, b"next".to_vec()));
+        wtxn.commit().unwrap();
+        assert_eq!(contents(&db), model);
+    }
+    assert!(kept > 0 && poisoned > 0);
+}
@@ -0,0 +1,113 @@
+use rbolt::db::Db;
+use std::ops::Bound;
+
+mod common;
+use common::fresh;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+#[test]
+fn test_iterate_in_key_order() {
+    let db = Db::open_in_memory().unwrap();
+    {
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        // 7919 is coprime with 500, so this visits every key once out of order
+        for i in 0..500u32 {
+            let k = (i * 7919) % 500;
+            wtxn.insert(&key(k), format!("value_{}", k).as_bytes()).unwrap();
+        }
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    let all: Vec<_> = rtxn.iter().unwrap().map(|entry| entry.unwrap()).collect();
+    assert_eq!(all.len(), 500);
+    for (i, (k, v)) in all.iter().enumerate() {
+        assert_eq!(*k, key(i as u32).as_slice());
+        assert_eq!(*v, format!("value_{}", i).as_bytes());
+    }
+
+    let start = key(100);
+    let end = key(200);
+    let keys: Vec<_> = rtxn.range(start.as_slice()..end.as_slice()).unwrap()
+        .map(|entry| entry.unwrap().0.to_vec())
+        .collect();
+    assert_eq!(keys, (100..200).map(key).collect::<Vec<_>>());
+
+    let keys: Vec<_> = rtxn.range((Bound::Excluded(start.as_slice()), Bound::Included(end.as_slice()))).unwrap()
+        .map(|entry| entry.unwrap().0.to_vec())
+        .collect();
+    assert_eq!(keys, (101..=200).map(key).collect::<Vec<_>>());
+
+    // bounds between stored keys
+    let keys: Vec<_> = rtxn.range(b"key_00099x".as_slice()..b"key_00103".as_slice()).unwrap()
+        .map(|entry| entry.unwrap().0.to_vec())
+        .collect();
+    assert_eq!(keys, (100..103).map(key).collect::<Vec<_>>());
+
+    assert_eq!(rtxn.range(b"zzz".as_slice()..).unwrap().count(), 0);
+
+    let mut cursor = rtxn.cursor();
+    assert_eq!(cursor.seek(&key(498)).unwrap().unwrap().0, key(498).as_slice());
+    assert_eq!(cursor.move_next().unwrap().unwrap().0, key(499).as_slice());
+    assert_eq!(cursor.move_next().unwrap(), None);
+    assert_eq!(cursor.move_next().unwrap(), None);
+}
+
+#[test]
+fn test_iterate_empty_database() {
+    let db = Db::open_in_memory().unwrap();
+    {
+        let rtxn = db.begin_read_transaction().unwrap();
+        assert_eq!(rtxn.iter().unwrap().count(), 0);
+    }
+    {
+        let wtxn = db.begin_write_transaction().unwrap();
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert_eq!(rtxn.iter().unwrap().count(), 0);
+    assert_eq!(rtxn.cursor().first().unwrap(), None);
+}
+
+#[test]
+fn test_delete_keys() {
+    let db_path = &fresh("test_cursor_delete.rdb");
+
+    {
+        let db = Db::open(db_path).unwrap();
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        for i in 0..300 {
+            wtxn.insert(&key(i), b"value").unwrap();
+        }
+        for i in (0..300).step_by(2) {
+            assert!(wtxn.delete(&key(i)).unwrap());
+        }
+        assert!(!wtxn.delete(&key(0)).unwrap());
+        assert!(!wtxn.delete(b"missing").unwrap());
+        assert_eq!(wtxn.get(&key(0)).unwrap(), None);
+        assert_eq!(wtxn.get(&key(1)).unwrap(), Some(b"value".to_vec()));
+
+        // a whole leaf worth of keys, leaving empty leaves behind
+        for i in (1..150).step_by(2) {
+            assert!(wtxn.delete(&key(i)).unwrap());
+        }
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    {
+        let db = Db::open(db_path).unwrap();
+        let rtxn = db.begin_read_transaction().unwrap();
+        let keys: Vec<_> = rtxn.iter().unwrap().map(|entry| entry.unwrap().0.to_vec()).collect();
+        assert_eq!(keys, (151..300).step_by(2).map(key).collect::<Vec<_>>());
+        assert_eq!(rtxn.get(&key(10)).unwrap(), None);
+        assert_eq!(rtxn.get(&key(151)).unwrap(), Some(b"value".to_vec()));
+    }
+
+    std::fs::remove_file(db_path).unwrap();
+}
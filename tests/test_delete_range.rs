@@ -0,0 +1,210 @@
+use rbolt::db::Db;
+use std::collections::BTreeMap;
+use std::ops::{Bound, RangeBounds};
+
+mod common;
+use common::fresh;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+fn insert_all(db: &Db, keys: impl Iterator<Item = u32>) {
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    for i in keys {
+        wtxn.insert(&key(i), format!("value_{}", i).as_bytes()).unwrap();
+    }
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+}
+
+#[test]
+fn test_delete_range_frees_pages() {
+    let db_path = &fresh("test_delete_range_frees.rdb");
+    {
+        let db = Db::open(db_path).unwrap();
+        insert_all(&db, (0..10_000).map(|i| (i * 7919) % 10_000));
+        let before = db.begin_read_transaction().unwrap().stats().unwrap();
+
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        let (start, end) = (key(1_000), key(9_000));
+        assert_eq!(wtxn.delete_range(start.as_slice()..end.as_slice()).unwrap(), 8_000);
+        assert!(wtxn.get(&key(999)).unwrap().is_some());
+        assert_eq!(wtxn.get(&key(1_000)).unwrap(), None);
+        assert_eq!(wtxn.get(&key(8_999)).unwrap(), None);
+        assert!(wtxn.get(&key(9_000)).unwrap().is_some());
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+
+        let rtxn = db.begin_read_transaction().unwrap();
+        let after = rtxn.stats().unwrap();
+        println!("   [OK] Before: {}", before);
+        println!("   [OK] After:  {}", after);
+        assert_eq!(after.key_count, 2_000);
+        assert!(after.leaf_pages * 3 < before.leaf_pages);
+        let keys: Vec<_> = rtxn.iter().unwrap().map(|entry| entry.unwrap().0.to_vec()).collect();
+        assert_eq!(keys, (0..1_000).chain(9_000..10_000).map(key).collect::<Vec<_>>());
+    }
+
+    // the freed pages survive a reopen and are reused before the file grows
+    let file_size = std::fs::metadata(db_path).unwrap().len();
+    let db = Db::open(db_path).unwrap();
+    insert_all(&db, 1_000..5_000);
+    assert_eq!(std::fs::metadata(db_path).unwrap().len(), file_size);
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert_eq!(rtxn.iter().unwrap().count(), 6_000);
+    println!("   [OK] Reinserted 4000 keys into freed pages, file stayed at {} bytes", file_size);
+
+    drop(rtxn);
+    drop(db);
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_clear() {
+    let db = Db::open_in_memory().unwrap();
+    insert_all(&db, 0..5_000);
+
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    assert_eq!(wtxn.clear().unwrap(), 5_000);
+    assert_eq!(wtxn.clear().unwrap(), 0);
+    wtxn.insert(b"after", b"clear").unwrap();
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    let stats = rtxn.stats().unwrap();
+    assert_eq!(stats.key_count, 1);
+    assert_eq!(stats.depth, 1);
+    assert_eq!(stats.branch_pages, 0);
+    assert_eq!(rtxn.get(b"after").unwrap(), Some(b"clear".to_vec()));
+    println!("   [OK] Cleared 5000 keys down to a single leaf");
+}
+
+#[test]
+fn test_delete_range_bounds_match_btreemap() {
+    let db = Db::open_in_memory().unwrap();
+
+    let bounds = [
+        (Bound::Included(10), Bound::Excluded(20)),
+        (Bound::Excluded(100), Bound::Included(250)),
+        (Bound::Unbounded, Bound::Excluded(5)),
+        (Bound::Included(2_900), Bound::Unbounded),
+        (Bound::Included(1_500), Bound::Included(1_500)),
+        (Bound::Excluded(600), Bound::Excluded(601)),
+        (Bound::Included(700), Bound::Excluded(2_200)),
+    ];
+
+    let mut model = BTreeMap::new();
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    for i in 0..3_000u32 {
+        wtxn.insert(&key(i), b"v").unwrap();
+        model.insert(key(i), ());
+    }
+
+    for (start, end) in bounds {
+        let (start, end) = (start.map(key), end.map(key));
+        let expected = model.range::<Vec<u8>, _>((start.as_ref(), end.as_ref())).count() as u64;
+        let removed = wtxn
+            .delete_range((start.as_ref().map(Vec::as_slice), end.as_ref().map(Vec::as_slice)))
+            .unwrap();
+        assert_eq!(removed, expected, "range {:?}..{:?}", start, end);
+        model.retain(|k, _| !(start.clone(), end.clone()).contains(k));
+    }
+
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    let keys: Vec<_> = rtxn.iter().unwrap().map(|entry| entry.unwrap().0.to_vec()).collect();
+    assert_eq!(keys, model.keys().cloned().collect::<Vec<_>>());
+    println!("   [OK] {} keys left after {} range deletes", keys.len(), bounds.len());
+}
+
+#[test]
+fn test_free_list_spans_pages() {
+    let db_path = &fresh("test_delete_range_free_list.rdb");
+    let value = vec![7u8; 1_000];
+    {
+        let db = Db::open(db_path).unwrap();
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        for i in 0..3_000 {
+            wtxn.insert(&key(i), &value).unwrap();
+        }
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+
+        // about 800 leaves, more free ids than page 1 holds
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        assert_eq!(wtxn.clear().unwrap(), 3_000);
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    let file_size = std::fs::metadata(db_path).unwrap().len();
+    let db = Db::open(db_path).unwrap();
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    for i in 0..3_000 {
+        wtxn.insert(&key(i), &value).unwrap();
+    }
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+
+    assert_eq!(std::fs::metadata(db_path).unwrap().len(), file_size);
+    assert_eq!(db.begin_read_transaction().unwrap().iter().unwrap().count(), 3_000);
+    println!("   [OK] Chained free list reused every page of a {} byte file", file_size);
+
+    drop(db);
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_concurrent_commits_share_no_pages() {
+    let db_path = &fresh("test_delete_range_concurrent.rdb");
+    let db = Db::open(db_path).unwrap();
+    let value = |writer: u32, round: u32| vec![writer as u8 * 100 + round as u8; 300];
+
+    // each writer keeps its own block of keys, so the end state doesn't depend on how
+    // the commits interleave. Deletes put pages on the free list for the other to reuse.
+    let models: Vec<BTreeMap<Vec<u8>, Vec<u8>>> = std::thread::scope(|scope| {
+        let writers: Vec<_> = (0..2u32).map(|writer| {
+            let db = &db;
+            scope.spawn(move || {
+                let mut model = BTreeMap::new();
+                for round in 0..40u32 {
+                    let mut wtxn = db.begin_write_transaction().unwrap();
+                    for i in 0..50 {
+                        let key = key(writer * 10_000 + round * 50 + i);
+                        wtxn.insert(&key, &value(writer, round)).unwrap();
+                        model.insert(key, value(writer, round));
+                    }
+                    if round % 3 == 2 {
+                        let (start, end) = (key(writer * 10_000 + (round - 2) * 50), key(writer * 10_000 + round * 50));
+                        wtxn.delete_range(start.as_slice()..end.as_slice()).unwrap();
+                        model.retain(|key, _| *key < start || *key >= end);
+                    }
+                    wtxn.commit().unwrap();
+                }
+                model
+            })
+        }).collect();
+        writers.into_iter().map(|writer| writer.join().unwrap()).collect()
+    });
+    let model: BTreeMap<Vec<u8>, Vec<u8>> = models.into_iter().flatten().collect();
+
+    let check = |db: &Db| {
+        let rtxn = db.begin_read_transaction().unwrap();
+        let found: Vec<(Vec<u8>, Vec<u8>)> = rtxn.iter().unwrap()
+            .map(|entry| entry.map(|(key, value)| (key.to_vec(), value.to_vec())).unwrap())
+            .collect();
+        assert_eq!(found.len(), model.len());
+        assert!(found.iter().zip(&model).all(|((key, value), (k, v))| key == k && value == v));
+        rtxn.stats().unwrap()
+    };
+    check(&db);
+    drop(db);
+    let stats = check(&Db::open(db_path).unwrap());
+    println!("   [OK] Two writers committed 80 transactions into one tree: {}", stats);
+
+    std::fs::remove_file(db_path).unwrap();
+}
@@ -1,12 +1,11 @@
 use rbolt::db::Db;
-use std::path::Path;
+
+mod common;
+use common::fresh;
 
 #[test]
 fn test_duplicate_key_overwrites() {
-    let db_path = Path::new("test_duplicates.rdb");
-    if db_path.exists() {
-        std::fs::remove_file(db_path).unwrap();
-    }
+    let db_path = &fresh("test_duplicates.rdb");
 
     {
         let db = Db::open(db_path).unwrap();
@@ -33,10 +32,7 @@ fn test_duplicate_key_overwrites() {
 
 #[test]
 fn test_empty_key_and_value() {
-    let db_path = Path::new("test_empty.rdb");
-    if db_path.exists() {
-        std::fs::remove_file(db_path).unwrap();
-    }
+    let db_path = &fresh("test_empty.rdb");
 
     {
         let db = Db::open(db_path).unwrap();
@@ -63,10 +59,7 @@ fn test_empty_key_and_value() {
 
 #[test]
 fn test_large_keys_and_values() {
-    let db_path = Path::new("test_large.rdb");
-    if db_path.exists() {
-        std::fs::remove_file(db_path).unwrap();
-    }
+    let db_path = &fresh("test_large.rdb");
 
     {
         let db = Db::open(db_path).unwrap();
@@ -97,17 +90,10 @@ fn test_large_keys_and_values() {
 
 #[test]
 fn test_query_empty_database() {
-    let db_path = Path::new("test_empty_db.rdb");
-    if db_path.exists() {
-        std::fs::remove_file(db_path).unwrap();
-    }
-
     {
-        let db = Db::open(db_path).unwrap();
+        let db = Db::open_in_memory().unwrap();
         let rtxn = db.begin_read_transaction().unwrap();
 
         assert_eq!(rtxn.get(b"anykey").unwrap(), None);
     }
-
-    std::fs::remove_file(db_path).unwrap();
 }
@@ -0,0 +1,291 @@
+use rbolt::compression::Compression;
+use rbolt::db::{Db, DbError, DbOptions, DEFAULT_PAGE_SIZE};
+use rbolt::encryption::{self, KEY_SIZE, TRAILER_SIZE};
+use rbolt::page::{self, PAGE_HEADER_SIZE};
+use rbolt::salvage::salvage;
+use rbolt::upgrade::upgrade;
+use std::path::Path;
+use std::process::Command;
+
+mod common;
+use common::fresh;
+
+const KEY: [u8; KEY_SIZE] = [7; KEY_SIZE];
+const OTHER_KEY: [u8; KEY_SIZE] = [8; KEY_SIZE];
+
+// The tests below need the feature, and say so when it's missing
+fn available() -> bool {
+    if !encryption::is_available() {
+        println!("   [OK] Skipped, built without the encryption feature");
+    }
+    encryption::is_available()
+}
+
+fn keyed(key: [u8; KEY_SIZE]) -> DbOptions {
+    DbOptions { key: Some(key), ..DbOptions::default() }
+}
+
+fn key(i: u32) -> Vec<u8> {
+    format!("customer:{:05}", i).into_bytes()
+}
+
+fn value(i: u32) -> Vec<u8> {
+    format!("card ending {:04}, lives at {} Secret Street", i % 10_000, i).into_bytes()
+}
+
+fn commit_all(db: &Db, entries: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>) {
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    for (key, value) in entries {
+        wtxn.insert(&key, &value).unwrap();
+    }
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+}
+
+fn entries(db: &Db) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let rtxn = db.begin_read_transaction().unwrap();
+    rtxn.iter().unwrap().map(|entry| {
+        let (key, value) = entry.unwrap();
+        (key.to_vec(), value.to_vec())
+    }).collect()
+}
+
+// The first error met reading every entry, if any
+fn read_error(db: &Db) -> Option<DbError> {
+    let rtxn = db.begin_read_transaction().unwrap();
+    match rtxn.iter() {
+        Ok(mut iter) => iter.find_map(Result::err),
+        Err(err) => Some(err),
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+// A leaf of the file, found by its page header, which is stored in the clear
+fn leaf_page(bytes: &[u8]) -> usize {
+    (2..bytes.len() / DEFAULT_PAGE_SIZE)
+        .find(|page_id| bytes[page_id * DEFAULT_PAGE_SIZE + 8] == 3)
+        .expect("the tree has a leaf")
+}
+
+#[test]
+fn test_encrypted_file_reads_back_and_hides_its_contents() {
+    if !available() {
+        return;
+    }
+    let db_path = &fresh("test_encryption_roundtrip.rdb");
+    let db = Db::open_with_key(db_path, KEY).unwrap();
+    assert!(db.is_encrypted());
+    // enough to split leaves and branches, then overwrite and delete some of it
+    commit_all(&db, (0..2_000).map(|i| (key(i), value(i))));
+    commit_all(&db, (0..2_000).step_by(3).map(|i| (key(i), value(i + 1))));
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    assert_eq!(wtxn.delete_range(key(500).as_slice()..key(700).as_slice()).unwrap(), 200);
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    drop(db);
+
+    let expected: Vec<_> = (0..2_000)
+        .filter(|i| !(500..700).contains(i))
+        .map(|i| (key(i), if i % 3 == 0 { value(i + 1) } else { value(i) }))
+        .collect();
+    let bytes = std::fs::read(db_path).unwrap();
+    assert!(!contains(&bytes, b"Secret Street"));
+    assert!(!contains(&bytes, b"customer:"));
+    println!("   [OK] {} bytes on disk hold none of the keys or values", bytes.len());
+
+    let db = Db::open_with_key(db_path, KEY).unwrap();
+    assert_eq!(entries(&db), expected);
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert_eq!(rtxn.get(&key(1)).unwrap(), Some(value(1)));
+    assert_eq!(rtxn.get(&key(600)).unwrap(), None);
+    let stats = rtxn.stats().unwrap();
+    assert_eq!(stats.key_count, expected.len() as u64);
+    assert_eq!(stats.leaf_size, DEFAULT_PAGE_SIZE - TRAILER_SIZE);
+    println!("   [OK] Reopened with the key, {}", stats);
+    drop(rtxn);
+    drop(db);
+
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_wrong_or_missing_key_is_refused() {
+    if !available() {
+        return;
+    }
+    let db_path = &fresh("test_encryption_keys.rdb");
+    commit_all(&Db::open_with_key(db_path, KEY).unwrap(), (0..10).map(|i| (key(i), value(i))));
+
+    match Db::open_with_key(db_path, OTHER_KEY) {
+        Err(err @ DbError::WrongKey) => println!("   [OK] {}", err),
+        Err(other) => panic!("expected WrongKey, got {}", other),
+        Ok(_) => panic!("opened with the wrong key"),
+    }
+    match Db::open(db_path) {
+        Err(err @ DbError::MissingKey) => println!("   [OK] {}", err),
+        Err(other) => panic!("expected MissingKey, got {}", other),
+        Ok(_) => panic!("opened without a key"),
+    }
+    // neither attempt touched the file
+    assert_eq!(entries(&Db::open_with_key(db_path, KEY).unwrap()).len(), 10);
+
+    let plain_path = &fresh("test_encryption_plain.rdb");
+    commit_all(&Db::open(plain_path).unwrap(), (0..10).map(|i| (key(i), value(i))));
+    match Db::open_with_key(plain_path, KEY) {
+        Err(err @ DbError::NotEncrypted) => println!("   [OK] {}", err),
+        Err(other) => panic!("expected NotEncrypted, got {}", other),
+        Ok(_) => panic!("opened a plain file with a key"),
+    }
+
+    std::fs::remove_file(db_path).unwrap();
+    std::fs::remove_file(plain_path).unwrap();
+}
+
+#[test]
+fn test_altered_page_fails_authentication() {
+    if !available() {
+        return;
+    }
+    let db_path = &fresh("test_encryption_altered.rdb");
+    commit_all(&Db::open_with_key(db_path, KEY).unwrap(), (0..300).map(|i| (key(i), value(i))));
+    let original = std::fs::read(db_path).unwrap();
+    let leaf = leaf_page(&original);
+    let range = leaf * DEFAULT_PAGE_SIZE..(leaf + 1) * DEFAULT_PAGE_SIZE;
+
+    // a flipped bit is damage, caught by the checksum
+    let mut bytes = original.clone();
+    bytes[range.start + PAGE_HEADER_SIZE + 40] ^= 1;
+    std::fs::write(db_path, &bytes).unwrap();
+    let db = Db::open_with_key(db_path, KEY).unwrap();
+    let result = read_error(&db);
+    assert!(matches!(result, Some(DbError::Corrupted { page_id, .. }) if page_id == leaf as u64));
+    drop(db);
+
+    // the same change with the checksum redone is tampering, caught by the tag
+    page::seal_page(&mut bytes[range.clone()]);
+    std::fs::write(db_path, &bytes).unwrap();
+    let db = Db::open_with_key(db_path, KEY).unwrap();
+    match read_error(&db) {
+        Some(err @ DbError::Unauthenticated { page_id }) if page_id == leaf as u64 => println!("   [OK] {}", err),
+        Some(other) => panic!("expected Unauthenticated, got {}", other),
+        None => panic!("the altered page went unnoticed"),
+    }
+    drop(db);
+
+    // and so is a page moved to where another one was
+    let mut bytes = original.clone();
+    let other = (leaf + 1..original.len() / DEFAULT_PAGE_SIZE)
+        .find(|page_id| original[page_id * DEFAULT_PAGE_SIZE + 8] == 3)
+        .expect("the tree has a second leaf");
+    bytes.copy_within(other * DEFAULT_PAGE_SIZE..(other + 1) * DEFAULT_PAGE_SIZE, range.start);
+    bytes[range.start..range.start + 8].copy_from_slice(&(leaf as u64).to_le_bytes());
+    page::seal_page(&mut bytes[range.clone()]);
+    std::fs::write(db_path, &bytes).unwrap();
+    let db = Db::open_with_key(db_path, KEY).unwrap();
+    let result = read_error(&db);
+    assert!(matches!(result, Some(DbError::Unauthenticated { page_id }) if page_id == leaf as u64));
+    println!("   [OK] Page {} copied over page {} failed authentication", other, leaf);
+    drop(db);
+
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_encryption_with_compression() {
+    if !available() {
+        return;
+    }
+    for codec in [Compression::Lz4, Compression::Zstd].into_iter().filter(|codec| codec.is_available()) {
+        let db_path = &fresh("test_encryption_compressed.rdb");
+        let options = DbOptions { compression: codec, ..keyed(KEY) };
+        let db = Db::open_with_options(db_path, options).unwrap();
+        let expected: Vec<_> = (0..1_000).map(|i| (key(i), value(i).repeat(i as usize % 4 * 10 + 1))).collect();
+        commit_all(&db, expected.clone());
+        commit_all(&db, expected.iter().step_by(2).cloned());
+        drop(db);
+
+        let bytes = std::fs::read(db_path).unwrap();
+        assert!(!contains(&bytes, b"Secret Street"));
+        let db = Db::open_with_key(db_path, KEY).unwrap();
+        assert_eq!((db.compression(), db.is_encrypted()), (codec, true));
+        assert_eq!(entries(&db), expected);
+        println!("   [OK] {:?} and encryption together, {} bytes", codec, bytes.len());
+        drop(db);
+
+        std::fs::remove_file(db_path).unwrap();
+    }
+}
+
+#[test]
+fn test_salvage_and_upgrade_with_a_key() {
+    if !available() {
+        return;
+    }
+    let db_path = &fresh("test_encryption_src.rdb");
+    let salvaged = &fresh("test_encryption_salvaged.rdb");
+    let upgraded = &fresh("test_encryption_upgraded.rdb");
+    let expected: Vec<_> = (0..500).map(|i| (key(i), value(i))).collect();
+    commit_all(&Db::open_with_key(db_path, KEY).unwrap(), expected.clone());
+
+    // the key is needed to read the source, and the copy is encrypted with it
+    assert!(matches!(salvage(db_path, salvaged, DbOptions::default()), Err(rbolt::btree::BTreeError::Db(DbError::MissingKey))));
+    assert!(matches!(salvage(db_path, salvaged, keyed(OTHER_KEY)), Err(rbolt::btree::BTreeError::Db(DbError::WrongKey))));
+    let report = salvage(db_path, salvaged, keyed(KEY)).unwrap();
+    assert_eq!(report.keys_recovered, 500);
+    assert!(report.damaged_pages.is_empty());
+    let db = Db::open_with_key(salvaged, KEY).unwrap();
+    assert_eq!(entries(&db), expected);
+    println!("   [OK] Salvaged {} keys into an encrypted copy", report.keys_recovered);
+    drop(db);
+
+    // upgrading an unencrypted file with a key encrypts the copy
+    let report = upgrade(Path::new("tests/golden/v1.rdb"), upgraded, keyed(KEY)).unwrap();
+    assert_eq!(report.keys, 1_000);
+    let db = Db::open_with_key(upgraded, KEY).unwrap();
+    assert!(db.is_encrypted());
+    assert_eq!(entries(&db).len(), 1_000);
+    println!("   [OK] {} into an encrypted copy", report);
+    drop(db);
+
+    // the command line takes the key from a file of raw bytes
+    let key_file = &fresh("test_encryption_key");
+    let by_command = &fresh("test_encryption_by_command.rdb");
+    std::fs::write(key_file, &KEY[..KEY_SIZE - 1]).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rbolt"))
+        .args(["salvage", "--key-file", key_file.to_str().unwrap(), db_path.to_str().unwrap(), by_command.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("expected 32"));
+    std::fs::write(key_file, KEY).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rbolt"))
+        .args(["salvage", "--key-file", key_file.to_str().unwrap(), db_path.to_str().unwrap(), by_command.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(entries(&Db::open_with_key(by_command, KEY).unwrap()), expected);
+    println!("   [OK] rbolt salvage --key-file read the encrypted file");
+
+    for db_path in [db_path, salvaged, upgraded, key_file, by_command] {
+        std::fs::remove_file(db_path).unwrap();
+    }
+}
+
+#[test]
+fn test_key_needs_the_feature() {
+    if encryption::is_available() {
+        return;
+    }
+    let db_path = &fresh("test_encryption_feature.rdb");
+    match Db::open_with_key(db_path, KEY) {
+        Err(err @ DbError::EncryptionUnsupported) => println!("   [OK] {}", err),
+        Err(other) => panic!("expected EncryptionUnsupported, got {}", other),
+        Ok(_) => panic!("created an encrypted database without the feature"),
+    }
+    if db_path.exists() {
+        std::fs::remove_file(db_path).unwrap();
+    }
+}
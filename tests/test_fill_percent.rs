@@ -0,0 +1,103 @@
+use rbolt::btree::{MAX_FILL_PERCENT, MIN_FILL_PERCENT};
+use rbolt::db::{Db, DbOptions};
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:06}", i).into_bytes()
+}
+
+fn insert_all(db: &Db, keys: impl Iterator<Item = u32>) {
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    for i in keys {
+        wtxn.insert(&key(i), format!("value_{}", i).as_bytes()).unwrap();
+    }
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+}
+
+#[test]
+fn test_sequential_inserts_fill_pages() {
+    let db = Db::open_in_memory().unwrap();
+
+    let stats = db.begin_read_transaction().unwrap().stats().unwrap();
+    assert_eq!(stats.key_count, 0);
+    // the empty root leaf written when the file is created
+    assert_eq!(stats.leaf_pages, 1);
+
+    insert_all(&db, 0..5_000);
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    let stats = rtxn.stats().unwrap();
+    println!("   [OK] Sequential inserts: {}", stats);
+    assert_eq!(stats.key_count, 5_000);
+    assert!(stats.depth >= 2);
+    // half-and-half splits would leave every leaf but the last about 50% full
+    assert!(stats.leaf_fill() > 0.9, "leaf fill {}", stats.leaf_fill());
+    assert_eq!(rtxn.iter().unwrap().count(), 5_000);
+    for i in (0..5_000).step_by(37) {
+        assert_eq!(rtxn.get(&key(i)).unwrap(), Some(format!("value_{}", i).into_bytes()));
+    }
+}
+
+#[test]
+fn test_random_inserts_use_fill_percent() {
+    let db = Db::open_in_memory().unwrap();
+
+    // 7919 is coprime with 5000, so this visits every key once out of order
+    insert_all(&db, (0..5_000).map(|i| (i * 7919) % 5_000));
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    let stats = rtxn.stats().unwrap();
+    println!("   [OK] Random inserts: {}", stats);
+    assert_eq!(stats.key_count, 5_000);
+    assert!(stats.leaf_fill() > 0.4 && stats.leaf_fill() < 0.9, "leaf fill {}", stats.leaf_fill());
+    let keys: Vec<_> = rtxn.iter().unwrap().map(|entry| entry.unwrap().0.to_vec()).collect();
+    assert_eq!(keys, (0..5_000).map(key).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_interleaved_appends_use_fill_percent() {
+    let options = DbOptions { fill_percent: 0.6, ..DbOptions::default() };
+    let db = Db::open_in_memory_with_options(options).unwrap();
+
+    // ten streams, each headed by an entry that takes a leaf of its own
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    for stream in 0..10 {
+        wtxn.insert(&key(stream * 100_000), &[0; 3_000]).unwrap();
+    }
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+
+    // each key sorts after the last key of a leaf in the middle of the tree, but only
+    // the last leaf of the tree is kept full as keys are appended
+    insert_all(&db, (1..=800).flat_map(|seq| (0..10).map(move |stream| stream * 100_000 + seq)));
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    let stats = rtxn.stats().unwrap();
+    println!("   [OK] Interleaved appends: {}", stats);
+    assert_eq!(stats.key_count, 8_010);
+    assert!(stats.leaf_fill() > 0.5 && stats.leaf_fill() < 0.75, "leaf fill {}", stats.leaf_fill());
+    assert_eq!(rtxn.iter().unwrap().count(), 8_010);
+}
+
+#[test]
+fn test_fill_percent_is_clamped() {
+    let options = DbOptions { fill_percent: 5.0, ..DbOptions::default() };
+    let db = Db::open_in_memory_with_options(options).unwrap();
+
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    assert_eq!(wtxn.fill_percent(), MAX_FILL_PERCENT);
+    wtxn.set_fill_percent(0.0);
+    assert_eq!(wtxn.fill_percent(), MIN_FILL_PERCENT);
+
+    // splits near the minimum still keep every key reachable
+    for i in (0..2_000).rev() {
+        wtxn.insert(&key(i), b"v").unwrap();
+    }
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert_eq!(rtxn.stats().unwrap().key_count, 2_000);
+    assert_eq!(rtxn.iter().unwrap().count(), 2_000);
+    println!("   [OK] Fill percent clamped to {}..={}", MIN_FILL_PERCENT, MAX_FILL_PERCENT);
+}
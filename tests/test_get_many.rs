@@ -0,0 +1,73 @@
+use rbolt::comparator::Comparator;
+use rbolt::db::{Db, DbOptions};
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+#[test]
+fn test_get_many_matches_get() {
+    let db = Db::open_in_memory().unwrap();
+    {
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        for i in (0..20_000u32).step_by(2) {
+            wtxn.insert(&key(i), format!("value_{}", i).as_bytes()).unwrap();
+        }
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    // unsorted, half of them missing, some repeated, plus keys past either end
+    let mut wanted: Vec<Vec<u8>> = (0..3_000u32).map(|i| key((i * 7919) % 20_000)).collect();
+    wanted.extend([key(42), key(42), key(19_999), b"a".to_vec(), b"z".to_vec(), Vec::new()]);
+    let refs: Vec<&[u8]> = wanted.iter().map(Vec::as_slice).collect();
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    let values = rtxn.get_many(&refs).unwrap();
+    assert_eq!(values.len(), refs.len());
+    for (wanted, value) in refs.iter().zip(&values) {
+        assert_eq!(value, &rtxn.get(wanted).unwrap(), "key {:?}", wanted);
+    }
+    let found = values.iter().filter(|value| value.is_some()).count();
+    assert!(found > 1_000 && found < 2_000);
+    assert_eq!(rtxn.get_many(&[]).unwrap(), Vec::<Option<Vec<u8>>>::new());
+    println!("   [OK] get_many found {} of {} keys in caller order", found, refs.len());
+}
+
+struct Descending;
+
+impl Comparator for Descending {
+    fn name(&self) -> &str {
+        "test.descending"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+#[test]
+fn test_get_many_uses_comparator_order() {
+    let db = Db::open_in_memory_with_options(DbOptions { comparator: Arc::new(Descending), ..DbOptions::default() }).unwrap();
+    {
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        for i in 0..5_000u32 {
+            wtxn.insert(&key(i), &i.to_le_bytes()).unwrap();
+        }
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    let wanted: Vec<Vec<u8>> = [4_999, 0, 2_500, 6_000, 1, 4_998].into_iter().map(key).collect();
+    let refs: Vec<&[u8]> = wanted.iter().map(Vec::as_slice).collect();
+    let rtxn = db.begin_read_transaction().unwrap();
+    let values = rtxn.get_many(&refs).unwrap();
+    let expected: Vec<_> = [Some(4_999u32), Some(0), Some(2_500), None, Some(1), Some(4_998)]
+        .into_iter()
+        .map(|i| i.map(|i| i.to_le_bytes().to_vec()))
+        .collect();
+    assert_eq!(values, expected);
+    println!("   [OK] get_many walked a descending tree");
+}
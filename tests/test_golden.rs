@@ -0,0 +1,123 @@
+use rbolt::checksum::Checksum;
+use rbolt::db::{Db, DbError, HEADER_SIZE, DEFAULT_PAGE_SIZE};
+use rbolt::page::{PAGE_HEADER_SIZE, LEAF_ELEMENT_SIZE};
+use std::path::Path;
+
+mod common;
+use common::fresh;
+
+// After a deliberate format change, rewrite the golden file with
+// RBOLT_UPDATE_GOLDEN=1 cargo test --test test_golden test_matches_golden_file
+const GOLDEN: &str = "tests/golden/small_v2.rdb";
+
+// The same three keys in the same commit always make the same file
+fn build(db_path: &Path) -> Vec<u8> {
+    let db = Db::open(db_path).unwrap();
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    wtxn.insert(b"apple", b"red").unwrap();
+    wtxn.insert(b"banana", b"yellow").unwrap();
+    wtxn.insert(b"cherry", b"dark red").unwrap();
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    drop(db);
+    std::fs::read(db_path).unwrap()
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[test]
+fn test_matches_golden_file() {
+    let db_path = &fresh("test_golden_build.rdb");
+    let bytes = build(db_path);
+    std::fs::remove_file(db_path).unwrap();
+
+    if std::env::var_os("RBOLT_UPDATE_GOLDEN").is_some() {
+        std::fs::write(GOLDEN, &bytes).unwrap();
+        println!("   [OK] Rewrote {}", GOLDEN);
+    }
+    let golden = std::fs::read(GOLDEN).unwrap();
+    assert_eq!(bytes.len(), golden.len());
+    if let Some(offset) = bytes.iter().zip(&golden).position(|(a, b)| a != b) {
+        panic!("byte {} (page {}, offset {}) differs from {}: 0x{:02x}, golden 0x{:02x}",
+               offset, offset / DEFAULT_PAGE_SIZE, offset % DEFAULT_PAGE_SIZE, GOLDEN, bytes[offset], golden[offset]);
+    }
+    println!("   [OK] {} bytes match {}", bytes.len(), GOLDEN);
+}
+
+#[test]
+fn test_golden_layout() {
+    let bytes = std::fs::read(GOLDEN).unwrap();
+
+    // header slot 0, written when the file was created
+    assert_eq!(&bytes[0..4], &[0x63, 0x6E, 0x79, 0x73]); // magic 0x73796E63
+    assert_eq!(u32_at(&bytes, 4), 2); // version
+    assert_eq!(u32_at(&bytes, 8), DEFAULT_PAGE_SIZE as u32);
+    assert_eq!(u32_at(&bytes, 12) & 2, 2); // FLAG_LITTLE_ENDIAN
+    assert_eq!(u64_at(&bytes, 24), 1); // free list page
+    assert_eq!(u64_at(&bytes, 40), 0); // tx_id
+
+    // header slot 1, written by the commit
+    let slot = DEFAULT_PAGE_SIZE / 2;
+    assert_eq!(u32_at(&bytes, slot), 0x73796E63);
+    assert_eq!(u64_at(&bytes, slot + 40), 1);
+    let root_page_id = u64_at(&bytes, slot + 16) as usize;
+
+    // the root leaf: id, type, count, then the element array
+    let page = root_page_id * DEFAULT_PAGE_SIZE;
+    assert_eq!(u64_at(&bytes, page), root_page_id as u64);
+    assert_eq!(bytes[page + 8], 3);
+    assert_eq!(u16_at(&bytes, page + 10), 3);
+    assert_eq!(u64_at(&bytes, page + 16), 0); // next
+    assert_eq!(u64_at(&bytes, page + 24), 0); // prev
+    let element = page + PAGE_HEADER_SIZE + LEAF_ELEMENT_SIZE;
+    assert_eq!(u16_at(&bytes, element), 6); // ksize of "banana"
+    assert_eq!(u16_at(&bytes, element + 2), 6); // vsize of "yellow"
+    let kptr = u16_at(&bytes, element + 4) as usize;
+    let vptr = u16_at(&bytes, element + 6) as usize;
+    let body = page + PAGE_HEADER_SIZE;
+    assert_eq!(&bytes[body + kptr..body + kptr + 6], b"banana");
+    assert_eq!(&bytes[body + vptr..body + vptr + 6], b"yellow");
+    println!("   [OK] Header and root leaf fields sit at their pinned little-endian offsets");
+}
+
+#[test]
+fn test_opens_golden_file() {
+    let db_path = &fresh("test_golden_open.rdb");
+    std::fs::copy(GOLDEN, db_path).unwrap();
+    let db = Db::open(db_path).unwrap();
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert_eq!(rtxn.get(b"apple").unwrap(), Some(b"red".to_vec()));
+    assert_eq!(rtxn.get(b"banana").unwrap(), Some(b"yellow".to_vec()));
+    assert_eq!(rtxn.get(b"cherry").unwrap(), Some(b"dark red".to_vec()));
+    println!("   [OK] Golden file opened and read back");
+    drop(rtxn);
+    drop(db);
+
+    // a file without the little-endian flag predates the fixed byte order
+    let mut bytes = std::fs::read(db_path).unwrap();
+    for slot in [0, DEFAULT_PAGE_SIZE / 2] {
+        bytes[slot + 12] &= !2;
+        let mut checksum = Checksum::new();
+        checksum.update(&bytes[slot..slot + HEADER_SIZE - 8]);
+        bytes[slot + HEADER_SIZE - 8..slot + HEADER_SIZE].copy_from_slice(&checksum.finish().to_le_bytes());
+    }
+    std::fs::write(db_path, &bytes).unwrap();
+    match Db::open(db_path) {
+        Err(DbError::MissingFormatFlags { flags: 0, required: 2 }) => {}
+        Err(other) => panic!("expected MissingFormatFlags, got {}", other),
+        Ok(_) => panic!("expected MissingFormatFlags, the file opened"),
+    }
+    println!("   [OK] File without the little-endian flag was rejected");
+
+    std::fs::remove_file(db_path).unwrap();
+}
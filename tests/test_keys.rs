@@ -0,0 +1,136 @@
+use rbolt::db::Db;
+use rbolt::keys::{self, Element, KeyError};
+
+#[test]
+fn test_integer_order_matches_byte_order() {
+    let values: Vec<i64> = vec![
+        i64::MIN, -1 << 40, -65536, -65535, -256, -255, -2, -1, 0, 1, 2, 255, 256, 65535, 65536, 1 << 40, i64::MAX,
+    ];
+    let packed: Vec<Vec<u8>> = values.iter().map(|v| keys::pack(&(*v,))).collect();
+    for pair in packed.windows(2) {
+        assert!(pair[0] < pair[1], "{:?} should sort before {:?}", pair[0], pair[1]);
+    }
+
+    // unsigned values share the integer encoding
+    assert_eq!(keys::pack(&(5u64,)), keys::pack(&(5i64,)));
+    assert!(keys::pack(&(i64::MAX,)) < keys::pack(&(u64::MAX,)));
+}
+
+#[test]
+fn test_float_order_matches_byte_order() {
+    let values = [f64::NEG_INFINITY, -1e300, -1.5, -0.0, 0.0, 1e-300, 1.5, 1e300, f64::INFINITY];
+    let packed: Vec<Vec<u8>> = values.iter().map(|v| keys::pack(&(*v,))).collect();
+    for pair in packed.windows(2) {
+        assert!(pair[0] < pair[1]);
+    }
+}
+
+#[test]
+fn test_strings_and_bytes_with_embedded_nulls() {
+    let values: Vec<&[u8]> = vec![b"", b"\x00", b"\x00\x00", b"\x00\xff", b"\x01", b"a", b"a\x00", b"a\x00b", b"ab", b"b"];
+    let packed: Vec<Vec<u8>> = values.iter().map(|v| keys::pack(&(*v,))).collect();
+    for pair in packed.windows(2) {
+        assert!(pair[0] < pair[1]);
+    }
+    for (value, bytes) in values.iter().zip(&packed) {
+        assert_eq!(keys::unpack_as::<(Vec<u8>,)>(bytes).unwrap().0, value.to_vec());
+    }
+
+    assert!(keys::pack(&("apple",)) < keys::pack(&("apple pie",)));
+    assert!(keys::pack(&("apple", 9u64)) < keys::pack(&("apple pie", 0u64)));
+}
+
+#[test]
+fn test_composite_order() {
+    let tuples = [
+        ("tenant", 1u64, -5i64),
+        ("tenant", 1u64, 7i64),
+        ("tenant", 2u64, -9i64),
+        ("tenant", 300u64, 0i64),
+        ("tenants", 0u64, 0i64),
+    ];
+    let packed: Vec<Vec<u8>> = tuples.iter().map(keys::pack).collect();
+    for pair in packed.windows(2) {
+        assert!(pair[0] < pair[1]);
+    }
+}
+
+#[test]
+fn test_roundtrip_with_nested_tuples() {
+    let tuple = (
+        "user",
+        42u64,
+        -7i64,
+        2.5f64,
+        true,
+        (b"raw\x00bytes".as_slice(), ("deep", None::<u64>)),
+    );
+    let bytes = keys::pack(&tuple);
+    let decoded = keys::unpack(&bytes).unwrap();
+    assert_eq!(
+        decoded,
+        vec![
+            Element::String("user".to_string()),
+            Element::Uint(42),
+            Element::Int(-7),
+            Element::Float(2.5),
+            Element::Bool(true),
+            Element::Tuple(vec![
+                Element::Bytes(b"raw\x00bytes".to_vec()),
+                Element::Tuple(vec![Element::String("deep".to_string()), Element::Null]),
+            ]),
+        ]
+    );
+    assert_eq!(keys::pack(&decoded), bytes);
+
+    let (name, id, delta): (String, u64, i64) = keys::unpack_as(&keys::pack(&("user", 42u64, -7i64))).unwrap();
+    assert_eq!((name.as_str(), id, delta), ("user", 42, -7));
+}
+
+#[test]
+fn test_decode_errors() {
+    assert_eq!(keys::unpack(&[0x02, b'a']), Err(KeyError::Truncated { offset: 2 }));
+    assert_eq!(keys::unpack(&[0x16, 0x01]), Err(KeyError::Truncated { offset: 2 }));
+    assert_eq!(keys::unpack(&[0x99]), Err(KeyError::InvalidTypeCode { offset: 0, code: 0x99 }));
+    assert_eq!(
+        keys::unpack_as::<(u64,)>(&keys::pack(&("x",))),
+        Err(KeyError::TypeMismatch { index: 0, expected: "an unsigned integer" })
+    );
+    assert_eq!(
+        keys::unpack_as::<(u64, u64)>(&keys::pack(&(1u64,))),
+        Err(KeyError::LengthMismatch { expected: 2, found: 1 })
+    );
+}
+
+#[test]
+fn test_prefix_range_bounds_stored_keys() {
+    let db = Db::open_in_memory().unwrap();
+    let mut all_keys = Vec::new();
+    {
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        for user in 0u64..20 {
+            for order in 0u64..10 {
+                let key = keys::pack(&("orders", user, order));
+                wtxn.insert(&key, b"").unwrap();
+                all_keys.push(key);
+            }
+        }
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    let range = keys::prefix_range(&("orders", 7u64));
+    let rtxn = db.begin_read_transaction().unwrap();
+    let mut in_range = 0;
+    for key in &all_keys {
+        assert!(rtxn.get(key).unwrap().is_some());
+        let (_, user, _): (String, u64, u64) = keys::unpack_as(key).unwrap();
+        assert_eq!(range.contains(key), user == 7);
+        if range.contains(key) {
+            in_range += 1;
+        }
+    }
+    assert_eq!(in_range, 10);
+    assert!(range.contains(&keys::pack(&("orders", 7u64))));
+    assert!(!range.contains(&keys::pack(&("orders", 8u64))));
+}
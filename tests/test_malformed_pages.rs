@@ -0,0 +1,132 @@
+use rbolt::db::{Db, DbError, DEFAULT_PAGE_SIZE};
+use rbolt::page::{self, PAGE_HEADER_SIZE};
+use std::path::Path;
+
+mod common;
+use common::fresh;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+// xorshift64, so every run damages the same bytes
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+// Opens `bytes` and looks up every key, returning how many lookups failed.
+// Any outcome but a panic or a hang is fine
+fn probe(db_path: &Path, bytes: &[u8], keys: &[Vec<u8>]) -> Result<usize, DbError> {
+    std::fs::write(db_path, bytes).unwrap();
+    let db = Db::open(db_path)?;
+    let rtxn = db.begin_read_transaction()?;
+    let failed = keys.iter().filter(|key| rtxn.get(key).is_err()).count();
+    let slices: Vec<&[u8]> = keys.iter().map(|key| key.as_slice()).collect();
+    let _ = rtxn.get_many(&slices);
+    let mut cursor = rtxn.cursor();
+    let _ = cursor.first();
+    let _ = cursor.last();
+    Ok(failed)
+}
+
+#[test]
+fn test_random_files_never_panic() {
+    let db_path = &fresh("test_malformed_pages_random.rdb");
+    let keys: Vec<Vec<u8>> = (0..50).map(key).collect();
+    let mut rng = Rng(0x9E3779B97F4A7C15);
+
+    let mut rejected = 0;
+    for round in 0..200 {
+        let pages = 1 + round % 6;
+        let bytes: Vec<u8> = (0..pages * DEFAULT_PAGE_SIZE).map(|_| rng.next() as u8).collect();
+        if probe(db_path, &bytes, &keys).is_err() {
+            rejected += 1;
+        }
+    }
+    println!("   [OK] {} of 200 files of random bytes were rejected, none panicked", rejected);
+
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_damaged_pages_never_panic() {
+    let db_path = &fresh("test_malformed_pages_damaged.rdb");
+    let db = Db::open(db_path).unwrap();
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    for i in 0..1_500u32 {
+        wtxn.insert(&key(i), format!("value_{}", i).as_bytes()).unwrap();
+    }
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    drop(db);
+    let original = std::fs::read(db_path).unwrap();
+    let page_count = original.len() / DEFAULT_PAGE_SIZE;
+    let keys: Vec<Vec<u8>> = (0..1_500).step_by(7).map(key).collect();
+
+    // damage the headers and element arrays, then reseal the checksums so the
+    // parser, not the checksum, has to catch it
+    let mut rng = Rng(0xD1B54A32D192ED03);
+    let mut failed = 0;
+    for _ in 0..300 {
+        let mut bytes = original.clone();
+        let page_id = 1 + rng.below(page_count - 1);
+        let start = page_id * DEFAULT_PAGE_SIZE;
+        for _ in 0..1 + rng.below(8) {
+            let offset = start + 8 + rng.below(PAGE_HEADER_SIZE + 256 - 8);
+            bytes[offset] = rng.next() as u8;
+        }
+        page::seal_page(&mut bytes[start..start + DEFAULT_PAGE_SIZE]);
+
+        // the header is untouched, so the file always opens
+        failed += probe(db_path, &bytes, &keys).unwrap();
+    }
+    assert!(failed > 0);
+    println!("   [OK] 300 damaged files searched without panicking, {} lookups failed", failed);
+
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_malformed_element_is_reported() {
+    let db_path = &fresh("test_malformed_pages_element.rdb");
+    let db = Db::open(db_path).unwrap();
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    wtxn.insert(b"apple", b"red").unwrap();
+    wtxn.insert(b"banana", b"yellow").unwrap();
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    drop(db);
+
+    // point the value of the second element of the root leaf past the end of the page
+    let mut bytes = std::fs::read(db_path).unwrap();
+    let start = 2 * DEFAULT_PAGE_SIZE;
+    let element = start + PAGE_HEADER_SIZE + 8;
+    bytes[element + 6..element + 8].copy_from_slice(&u16::MAX.to_le_bytes());
+    page::seal_page(&mut bytes[start..start + DEFAULT_PAGE_SIZE]);
+    std::fs::write(db_path, &bytes).unwrap();
+
+    let db = Db::open(db_path).unwrap();
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert_eq!(rtxn.get(b"apple").unwrap(), Some(b"red".to_vec()));
+    match rtxn.get(b"banana") {
+        Err(DbError::PageFormat { page_id: 2, index: Some(1) }) => {}
+        Err(other) => panic!("expected PageFormat for element 1 of page 2, got {}", other),
+        Ok(value) => panic!("expected PageFormat, read {:?}", value),
+    }
+    println!("   [OK] Value pointer past the page reported as element 1 of page 2");
+
+    drop(rtxn);
+    drop(db);
+    std::fs::remove_file(db_path).unwrap();
+}
@@ -0,0 +1,127 @@
+use rbolt::btree::BTreeError;
+use rbolt::db::{Db, DbOptions};
+use rbolt::merge::{Append, MergeError, MergeOperator, U64Add, U64Max, U64Min};
+use std::sync::Arc;
+
+mod common;
+use common::fresh;
+
+fn options(operator: Arc<dyn MergeOperator>) -> DbOptions {
+    DbOptions { merge_operator: Some(operator), ..DbOptions::default() }
+}
+
+fn as_u64(value: Option<Vec<u8>>) -> u64 {
+    u64::from_le_bytes(value.unwrap().try_into().unwrap())
+}
+
+#[test]
+fn test_counter_merge() {
+    let db_path = &fresh("test_merge_counter.rdb");
+    {
+        let db = Db::open_with_options(db_path, options(Arc::new(U64Add))).unwrap();
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        for round in 0..20u64 {
+            for i in 0..200u32 {
+                wtxn.merge(format!("counter_{:03}", i).as_bytes(), &(round + 1).to_le_bytes()).unwrap();
+            }
+        }
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    let db = Db::open_with_options(db_path, options(Arc::new(U64Add))).unwrap();
+    let rtxn = db.begin_read_transaction().unwrap();
+    for i in 0..200u32 {
+        assert_eq!(as_u64(rtxn.get(format!("counter_{:03}", i).as_bytes()).unwrap()), 210);
+    }
+    // every increment after the first rewrote the 8 bytes in place
+    let stats = rtxn.stats().unwrap();
+    assert_eq!(stats.key_count, 200);
+    assert!(stats.leaf_fill() > 0.5, "{}", stats);
+    println!("   [OK] 4000 increments over 200 counters: {}", stats);
+
+    drop(rtxn);
+    drop(db);
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_builtin_operators() {
+    let db_path = &fresh("test_merge_builtins.rdb");
+
+    {
+        let db = Db::open_with_options(db_path, options(Arc::new(U64Max))).unwrap();
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        for value in [5u64, 9, 3] {
+            wtxn.merge(b"max", &value.to_le_bytes()).unwrap();
+        }
+        assert_eq!(as_u64(wtxn.get(b"max").unwrap()), 9);
+    }
+    {
+        let db = Db::open_with_options(db_path, options(Arc::new(U64Min))).unwrap();
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        for value in [5u64, 9, 3] {
+            wtxn.merge(b"min", &value.to_le_bytes()).unwrap();
+        }
+        assert_eq!(as_u64(wtxn.get(b"min").unwrap()), 3);
+
+        match wtxn.merge(b"min", b"short") {
+            Err(BTreeError::Merge(MergeError::InvalidWidth { expected: 8, found: 5, .. })) => {}
+            other => panic!("expected InvalidWidth, got {:?}", other),
+        }
+        assert_eq!(as_u64(wtxn.get(b"min").unwrap()), 3);
+    }
+    {
+        let db = Db::open_with_options(db_path, options(Arc::new(Append))).unwrap();
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        for part in [b"a".as_slice(), b"bc", b"def"] {
+            wtxn.merge(b"log", part).unwrap();
+        }
+        assert_eq!(wtxn.get(b"log").unwrap(), Some(b"abcdef".to_vec()));
+    }
+    println!("   [OK] max, min and append operators applied");
+
+    std::fs::remove_file(db_path).unwrap();
+}
+
+// Decrements a counter and deletes it once it reaches zero
+struct Release;
+
+impl MergeOperator for Release {
+    fn name(&self) -> &str {
+        "test.release"
+    }
+
+    fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Result<Option<Vec<u8>>, MergeError> {
+        let existing = existing.ok_or_else(|| MergeError::Custom("nothing to release".to_string()))?;
+        let remaining = existing[0].saturating_sub(operand[0]);
+        Ok((remaining > 0).then(|| vec![remaining]))
+    }
+}
+
+#[test]
+fn test_custom_operator_and_missing_operator() {
+    let db_path = &fresh("test_merge_custom.rdb");
+    {
+        let db = Db::open_with_options(db_path, options(Arc::new(Release))).unwrap();
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        wtxn.insert(b"refs", &[3]).unwrap();
+        wtxn.merge(b"refs", &[1]).unwrap();
+        assert_eq!(wtxn.get(b"refs").unwrap(), Some(vec![2]));
+        wtxn.merge(b"refs", &[2]).unwrap();
+        assert_eq!(wtxn.get(b"refs").unwrap(), None);
+
+        match wtxn.merge(b"refs", &[1]) {
+            Err(BTreeError::Merge(MergeError::Custom(message))) => assert_eq!(message, "nothing to release"),
+            other => panic!("expected a custom merge error, got {:?}", other),
+        }
+    }
+    {
+        let db = Db::open(db_path).unwrap();
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        assert!(matches!(wtxn.merge(b"refs", &[1]), Err(BTreeError::NoMergeOperator)));
+    }
+    println!("   [OK] Custom operator deleted the key, missing operator was reported");
+
+    std::fs::remove_file(db_path).unwrap();
+}
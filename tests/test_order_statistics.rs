@@ -0,0 +1,154 @@
+use rbolt::btree::WriteTxn;
+use rbolt::db::{Db, DbOptions, ReadTxn};
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+mod common;
+use common::fresh;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+fn counted() -> DbOptions {
+    DbOptions { order_statistics: true, ..DbOptions::default() }
+}
+
+// Checks rank, nth and count_range against the model at a spread of probe keys
+fn assert_matches(rtxn: &ReadTxn<'_>, model: &BTreeMap<Vec<u8>, Vec<u8>>) {
+    let keys: Vec<_> = model.keys().cloned().collect();
+    for probe in (0..12_000).step_by(97).map(key) {
+        let rank = keys.partition_point(|k| *k < probe) as u64;
+        assert_eq!(rtxn.rank(&probe).unwrap(), rank, "rank of {:?}", probe);
+    }
+    for n in (0..keys.len() + 3).step_by(89) {
+        let expected = keys.get(n).map(|k| (k.as_slice(), model[k].as_slice()));
+        assert_eq!(rtxn.nth(n as u64).unwrap(), expected, "nth {}", n);
+    }
+    let bounds = [
+        (Bound::Included(key(100)), Bound::Excluded(key(2_000))),
+        (Bound::Excluded(key(1_500)), Bound::Included(key(7_777))),
+        (Bound::Unbounded, Bound::Included(key(4_000))),
+        (Bound::Included(key(9_000)), Bound::Unbounded),
+        (Bound::Included(key(5_000)), Bound::Excluded(key(5_000))),
+        (Bound::Unbounded, Bound::Unbounded),
+    ];
+    for (start, end) in bounds {
+        let expected = model.range::<Vec<u8>, _>((start.as_ref(), end.as_ref())).count() as u64;
+        let range = (start.as_ref().map(Vec::as_slice), end.as_ref().map(Vec::as_slice));
+        assert_eq!(rtxn.count_range(range).unwrap(), expected, "range {:?}..{:?}", start, end);
+    }
+}
+
+#[test]
+fn test_counts_follow_writes() {
+    let db_path = &fresh("test_order_statistics_writes.rdb");
+    let mut model = BTreeMap::new();
+    {
+        let db = Db::open_with_options(db_path, counted()).unwrap();
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        for i in 0..10_000u32 {
+            let i = (i * 7919) % 10_000;
+            let value = format!("value_{}", i).into_bytes();
+            wtxn.insert(&key(i), &value).unwrap();
+            model.insert(key(i), value);
+        }
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+        let rtxn = db.begin_read_transaction().unwrap();
+        assert!(rtxn.has_order_statistics());
+        assert_matches(&rtxn, &model);
+        println!("   [OK] Counts matched after {} random inserts", model.len());
+    }
+
+    // deletes, range deletes and overwrites, then a reopen without asking for the mode
+    let db = Db::open(db_path).unwrap();
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    for i in (0..10_000u32).step_by(3) {
+        assert!(wtxn.delete(&key(i)).unwrap());
+        model.remove(&key(i));
+    }
+    let (start, end) = (key(2_500), key(6_500));
+    wtxn.delete_range(start.as_slice()..end.as_slice()).unwrap();
+    model.retain(|k, _| *k < start || *k >= end);
+    for i in 8_000..8_200u32 {
+        wtxn.insert(&key(i), b"longer value that moves the key").unwrap();
+        model.insert(key(i), b"longer value that moves the key".to_vec());
+    }
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert!(rtxn.has_order_statistics());
+    assert_matches(&rtxn, &model);
+    println!("   [OK] Counts matched after deletes, {} keys left", model.len());
+
+    drop(rtxn);
+    drop(db);
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_counts_after_bulk_load() {
+    let db = Db::open_in_memory_with_options(counted()).unwrap();
+    let model: BTreeMap<_, _> = (0..12_000u32).map(|i| (key(i), vec![b'v'; 30])).collect();
+    db.bulk_load(model.iter(), 0.8).unwrap();
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert!(rtxn.stats().unwrap().depth > 2);
+    assert_matches(&rtxn, &model);
+    assert_eq!(rtxn.nth(11_999).unwrap().unwrap().0, key(11_999).as_slice());
+    assert_eq!(rtxn.nth(12_000).unwrap(), None);
+    println!("   [OK] Counts matched after bulk loading {} keys", model.len());
+}
+
+#[test]
+fn test_no_op_writes_leave_branches_clean() {
+    let db = Db::open_in_memory_with_options(counted()).unwrap();
+    let mut model: BTreeMap<_, _> = (0..12_000u32).step_by(2).map(|i| (key(i), vec![b'v'; 30])).collect();
+    db.bulk_load(model.iter(), 0.8).unwrap();
+    let dirty_after = |write: &dyn Fn(&mut WriteTxn<'_>)| {
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        write(&mut wtxn);
+        wtxn.prepare_commit().0.len()
+    };
+    // what committing nothing writes, the free list
+    let empty = dirty_after(&|_| {});
+
+    let no_op = dirty_after(&|wtxn| {
+        assert!(wtxn.compare_and_swap(&key(10), Some(b"other"), Some(b"new")).unwrap().is_err());
+        assert!(!wtxn.insert_if_absent(&key(20), b"new").unwrap());
+        assert!(!wtxn.delete(&key(31)).unwrap());
+    });
+    assert_eq!(no_op, empty);
+    // a value of the same size is written over in its leaf, and no count changes
+    assert_eq!(dirty_after(&|wtxn| wtxn.insert(&key(40), &[b'w'; 30]).unwrap()), empty + 1);
+    // a new key changes the count of every branch on its path
+    assert!(dirty_after(&|wtxn| wtxn.insert(&key(41), b"new").unwrap()) > empty + 1);
+
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    wtxn.insert(&key(41), b"new").unwrap();
+    assert!(!wtxn.delete(&key(43)).unwrap());
+    wtxn.commit().unwrap();
+    model.insert(key(41), b"new".to_vec());
+    assert_matches(&db.begin_read_transaction().unwrap(), &model);
+    println!("   [OK] No-op writes dirtied {} pages, the same as an empty transaction", no_op);
+}
+
+#[test]
+fn test_without_counts_scans() {
+    let db = Db::open_in_memory().unwrap();
+    let mut model = BTreeMap::new();
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    for i in (0..12_000u32).step_by(2) {
+        wtxn.insert(&key(i), b"v").unwrap();
+        model.insert(key(i), b"v".to_vec());
+    }
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert!(!rtxn.has_order_statistics());
+    assert_matches(&rtxn, &model);
+    println!("   [OK] Tree without counts answered by scanning");
+}
@@ -1,12 +1,11 @@
 use rbolt::db::Db;
-use std::path::Path;
+
+mod common;
+use common::fresh;
 
 #[test]
 fn test_insert_ascending_order() {
-    let db_path = Path::new("test_ascending.rdb");
-    if db_path.exists() {
-        std::fs::remove_file(db_path).unwrap();
-    }
+    let db_path = &fresh("test_ascending.rdb");
 
     {
         let db = Db::open(db_path).unwrap();
@@ -40,10 +39,7 @@ fn test_insert_ascending_order() {
 
 #[test]
 fn test_insert_descending_order() {
-    let db_path = Path::new("test_descending.rdb");
-    if db_path.exists() {
-        std::fs::remove_file(db_path).unwrap();
-    }
+    let db_path = &fresh("test_descending.rdb");
 
     {
         let db = Db::open(db_path).unwrap();
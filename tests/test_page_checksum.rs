@@ -0,0 +1,128 @@
+use rbolt::btree::BTreeError;
+use rbolt::db::{Db, DbError, DEFAULT_PAGE_SIZE};
+use std::path::Path;
+
+mod common;
+use common::fresh;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+// Flips one bit in the last byte of `page_id`, where leaves keep their values
+fn flip_bit(db_path: &Path, page_id: usize) {
+    let mut bytes = std::fs::read(db_path).unwrap();
+    bytes[(page_id + 1) * DEFAULT_PAGE_SIZE - 1] ^= 0x01;
+    std::fs::write(db_path, &bytes).unwrap();
+}
+
+#[test]
+fn test_detects_corrupted_leaf() {
+    let db_path = &fresh("test_page_checksum_leaf.rdb");
+    let db = Db::open(db_path).unwrap();
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    for i in 0..2_000u32 {
+        wtxn.insert(&key(i), format!("value_{}", i).as_bytes()).unwrap();
+    }
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    drop(db);
+
+    // the first leaf after the root leaf created with the file
+    let bytes = std::fs::read(db_path).unwrap();
+    let leaf_id = (3..bytes.len() / DEFAULT_PAGE_SIZE)
+        .find(|&page_id| bytes[page_id * DEFAULT_PAGE_SIZE + 8] == 3)
+        .unwrap();
+    flip_bit(db_path, leaf_id);
+
+    let db = Db::open(db_path).unwrap();
+    let rtxn = db.begin_read_transaction().unwrap();
+    let mut corrupted = 0;
+    for i in 0..2_000u32 {
+        match rtxn.get(&key(i)) {
+            Ok(value) => assert_eq!(value, Some(format!("value_{}", i).into_bytes())),
+            Err(DbError::Corrupted { page_id, expected, found }) => {
+                assert_eq!(page_id, leaf_id as u64);
+                assert_ne!(expected, found);
+                corrupted += 1;
+            }
+            Err(other) => panic!("expected Corrupted, got {}", other),
+        }
+    }
+    assert!(corrupted > 0);
+    println!("   [OK] {} keys on page {} reported as corrupted, the rest read back", corrupted, leaf_id);
+
+    // a full scan crosses the leaf too
+    let scan: Result<Vec<_>, _> = rtxn.iter().unwrap().collect();
+    assert!(matches!(scan, Err(DbError::Corrupted { page_id, .. }) if page_id == leaf_id as u64));
+    drop(rtxn);
+
+    // and so does a write landing on it
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    let mut rejected = 0;
+    for i in 0..2_000u32 {
+        match wtxn.insert(&key(i), b"new") {
+            Ok(_) => {}
+            Err(BTreeError::Db(DbError::Corrupted { page_id, .. })) => {
+                assert_eq!(page_id, leaf_id as u64);
+                rejected += 1;
+            }
+            Err(other) => panic!("expected Corrupted, got {}", other),
+        }
+    }
+    // splits next to the leaf also touch it, to relink their siblings
+    assert!(rejected >= corrupted);
+    println!("   [OK] Writes to the corrupted page were refused");
+
+    drop(wtxn);
+    drop(db);
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_detects_corrupted_free_list() {
+    let db_path = &fresh("test_page_checksum_free_list.rdb");
+    drop(Db::open(db_path).unwrap());
+    flip_bit(db_path, 1);
+
+    let db = Db::open(db_path).unwrap();
+    match db.begin_write_transaction() {
+        Err(DbError::Corrupted { page_id: 1, .. }) => {}
+        Err(other) => panic!("expected Corrupted, got {}", other),
+        Ok(_) => panic!("expected Corrupted, the write transaction started"),
+    }
+    println!("   [OK] Corrupted free list page was reported");
+
+    drop(db);
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_checksums_survive_rewrites() {
+    let db_path = &fresh("test_page_checksum_rewrites.rdb");
+    let db = Db::open(db_path).unwrap();
+
+    // pages verified by one transaction are rewritten by the next commit
+    for round in 0..5u32 {
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        for i in 0..500u32 {
+            wtxn.insert(&key(i), format!("value_{}_{}", i, round).as_bytes()).unwrap();
+        }
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+
+        let rtxn = db.begin_read_transaction().unwrap();
+        assert_eq!(rtxn.iter().unwrap().count(), 500);
+        assert_eq!(rtxn.get(&key(250)).unwrap(), Some(format!("value_250_{}", round).into_bytes()));
+    }
+    drop(db);
+
+    let db = Db::open(db_path).unwrap();
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert_eq!(rtxn.iter().unwrap().filter(|entry| entry.is_ok()).count(), 500);
+    println!("   [OK] Every page verified after 5 rounds of rewrites and a reopen");
+
+    drop(rtxn);
+    drop(db);
+    std::fs::remove_file(db_path).unwrap();
+}
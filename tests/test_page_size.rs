@@ -0,0 +1,165 @@
+use rbolt::btree::BTreeError;
+use rbolt::db::{Db, DbError, DbOptions, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+use rbolt::page::{BRANCH_ELEMENT_SIZE, PAGE_HEADER_SIZE};
+
+mod common;
+use common::fresh;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+// `key(i)` padded out to `len` bytes, keeping the order of `i`
+fn long_key(i: u32, len: usize) -> Vec<u8> {
+    let mut key = key(i);
+    key.resize(len, b'k');
+    key
+}
+
+fn with_page_size(page_size: usize) -> DbOptions {
+    DbOptions { page_size, ..DbOptions::default() }
+}
+
+fn insert_all(db: &Db, entries: impl Iterator<Item = (Vec<u8>, Vec<u8>)>) {
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    for (k, v) in entries {
+        wtxn.insert(&k, &v).unwrap();
+    }
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+}
+
+#[test]
+fn test_page_size_is_kept_by_the_file() {
+    let db_path = &fresh("test_page_size_kept.rdb");
+    let db = Db::open_with_options(db_path, with_page_size(16 * 1024)).unwrap();
+    insert_all(&db, (0..5_000).map(|i| (key(i), format!("value_{}", i).into_bytes())));
+    drop(db);
+
+    // reopening with the default options still reads 16 KiB pages
+    let db = Db::open(db_path).unwrap();
+    assert_eq!(db.page_size(), 16 * 1024);
+    assert_eq!(std::fs::metadata(db_path).unwrap().len() % (16 * 1024), 0);
+    let rtxn = db.begin_read_transaction().unwrap();
+    for i in (0..5_000).step_by(37) {
+        assert_eq!(rtxn.get(&key(i)).unwrap(), Some(format!("value_{}", i).into_bytes()));
+    }
+    let large = rtxn.stats().unwrap();
+    assert_eq!(large.key_count, 5_000);
+    println!("   [OK] 16 KiB pages: {}", large);
+
+    // the same keys in 4 KiB pages need about four times the leaves
+    let small_db = Db::open_in_memory().unwrap();
+    insert_all(&small_db, (0..5_000).map(|i| (key(i), format!("value_{}", i).into_bytes())));
+    let small = small_db.begin_read_transaction().unwrap().stats().unwrap();
+    assert_eq!(small.page_size, DEFAULT_PAGE_SIZE);
+    assert!(small.leaf_pages > 3 * large.leaf_pages);
+    println!("   [OK] 4 KiB pages: {}", small);
+
+    drop(rtxn);
+    drop(db);
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_large_values_need_large_pages() {
+    let value = vec![b'v'; 12_000];
+
+    // doesn't fit a 4 KiB page, and is refused rather than split
+    let small_db = Db::open_in_memory().unwrap();
+    let mut wtxn = small_db.begin_write_transaction().unwrap();
+    assert!(matches!(wtxn.insert(b"big", &value), Err(BTreeError::ValueTooLarge { .. })));
+    drop(wtxn);
+
+    let db = Db::open_in_memory_with_options(with_page_size(32 * 1024)).unwrap();
+    insert_all(&db, (0..200).map(|i| (key(i), value.clone())));
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert_eq!(rtxn.iter().unwrap().count(), 200);
+    assert_eq!(rtxn.get(&key(123)).unwrap(), Some(value.clone()));
+    println!("   [OK] 200 values of 12000 bytes in 32 KiB pages: {}", rtxn.stats().unwrap());
+    drop(rtxn);
+
+    // the bulk loader packs them the same way
+    let loaded = Db::open_in_memory_with_options(with_page_size(32 * 1024)).unwrap();
+    loaded.bulk_load((0..200).map(|i| (key(i), value.clone())), 1.0).unwrap();
+    assert_eq!(loaded.begin_read_transaction().unwrap().get(&key(199)).unwrap(), Some(value));
+}
+
+#[test]
+fn test_largest_pages() {
+    let db_path = &fresh("test_page_size_largest.rdb");
+    let db = Db::open_with_options(db_path, with_page_size(MAX_PAGE_SIZE)).unwrap();
+    // element offsets near the end of a 64 KiB body still fit in their u16 fields
+    let value = vec![b'x'; 60_000];
+    insert_all(&db, (0..20).map(|i| (key(i), value.clone())));
+    insert_all(&db, (20..2_000).map(|i| (key(i), key(i))));
+    drop(db);
+
+    let db = Db::open(db_path).unwrap();
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert_eq!(rtxn.get(&key(7)).unwrap(), Some(value));
+    assert_eq!(rtxn.get(&key(1_999)).unwrap(), Some(key(1_999)));
+    assert_eq!(rtxn.iter().unwrap().count(), 2_000);
+    println!("   [OK] 64 KiB pages: {}", rtxn.stats().unwrap());
+
+    drop(rtxn);
+    drop(db);
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_key_size_is_limited_by_branch_pages() {
+    for page_size in [DEFAULT_PAGE_SIZE, 16 * 1024] {
+        let db = Db::open_in_memory_with_options(with_page_size(page_size)).unwrap();
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        let max_key_size = wtxn.max_key_size();
+        // separators are copied into branch pages, which have to hold two of them
+        assert_eq!(max_key_size, (page_size - PAGE_HEADER_SIZE - BRANCH_ELEMENT_SIZE) / 2 - BRANCH_ELEMENT_SIZE);
+        match wtxn.insert(&long_key(0, max_key_size + 1), b"v") {
+            Err(BTreeError::KeyTooLarge { key_size, max_size }) => assert_eq!((key_size, max_size), (max_key_size + 1, max_key_size)),
+            Err(other) => panic!("expected KeyTooLarge, got {}", other),
+            Ok(()) => panic!("a key of {} bytes was taken", max_key_size + 1),
+        }
+
+        // keys right at the limit, out of order, split leaves and branches alike
+        for i in (0..200).map(|i| (i * 7919) % 200) {
+            wtxn.insert(&long_key(i, max_key_size), b"v").unwrap();
+        }
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+        let rtxn = db.begin_read_transaction().unwrap();
+        let stats = rtxn.stats().unwrap();
+        assert_eq!(stats.key_count, 200);
+        assert!(stats.depth >= 3, "depth {}", stats.depth);
+        assert_eq!(rtxn.get(&long_key(123, max_key_size)).unwrap(), Some(b"v".to_vec()));
+        println!("   [OK] {} byte pages take {} byte keys: {}", page_size, max_key_size, stats);
+        drop(rtxn);
+
+        // the bulk loader holds keys to the same limit
+        let loaded = Db::open_in_memory_with_options(with_page_size(page_size)).unwrap();
+        let result = loaded.bulk_load([(long_key(0, max_key_size + 1), b"v".to_vec())], 1.0);
+        assert!(matches!(result, Err(BTreeError::KeyTooLarge { .. })));
+        loaded.bulk_load((0..200).map(|i| (long_key(i, max_key_size), b"v".to_vec())), 1.0).unwrap();
+        assert_eq!(loaded.begin_read_transaction().unwrap().iter().unwrap().count(), 200);
+    }
+
+    // keys that fit a leaf but not two to a branch page are refused up front
+    let db = Db::open_in_memory().unwrap();
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    for i in 0..3 {
+        assert!(matches!(wtxn.insert(&long_key(i, 4_000), b"v"), Err(BTreeError::KeyTooLarge { .. })));
+    }
+    println!("   [OK] 4000 byte keys were refused by 4 KiB pages");
+}
+
+#[test]
+fn test_rejects_unsupported_page_sizes() {
+    for page_size in [1024, 6000, 2 * MAX_PAGE_SIZE] {
+        match Db::open_in_memory_with_options(with_page_size(page_size)) {
+            Err(DbError::UnsupportedPageSize { found, .. }) => assert_eq!(found as usize, page_size),
+            Err(other) => panic!("expected UnsupportedPageSize, got {}", other),
+            Ok(_) => panic!("a database with {} byte pages was created", page_size),
+        }
+    }
+    println!("   [OK] Page sizes outside 4 KiB to 64 KiB, or not a power of two, were rejected");
+}
@@ -0,0 +1,105 @@
+use rbolt::db::Db;
+
+fn items(user: u32) -> u32 {
+    user * 7 % 13
+}
+
+fn item_key(user: u32, item: u32) -> Vec<u8> {
+    format!("user/{}/item/{:03}", user, item).into_bytes()
+}
+
+#[test]
+fn test_scan_prefix() {
+    let db = Db::open_in_memory().unwrap();
+    {
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        for user in 0..200 {
+            for item in 0..items(user) {
+                wtxn.insert(&item_key(user, item), format!("{}:{}", user, item).as_bytes()).unwrap();
+            }
+        }
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    for user in [1, 12, 123, 199] {
+        let prefix = format!("user/{}/", user);
+        let keys: Vec<_> = rtxn.scan_prefix(prefix.as_bytes()).unwrap()
+            .map(|entry| entry.unwrap().0.to_vec())
+            .collect();
+        // "user/12/" must not pick up user 120..129
+        assert_eq!(keys, (0..items(user)).map(|item| item_key(user, item)).collect::<Vec<_>>());
+        assert_eq!(rtxn.count_prefix(prefix.as_bytes()).unwrap(), items(user) as u64);
+
+        let first = rtxn.first_with_prefix(prefix.as_bytes()).unwrap().unwrap();
+        assert_eq!(first.0, item_key(user, 0).as_slice());
+        assert_eq!(first.1, format!("{}:0", user).as_bytes());
+        let last = rtxn.last_with_prefix(prefix.as_bytes()).unwrap().unwrap();
+        assert_eq!(last.0, item_key(user, items(user) - 1).as_slice());
+    }
+
+    // user 13 has no items, and nothing sorts after "v"
+    assert_eq!(rtxn.count_prefix(b"user/13/").unwrap(), 0);
+    assert_eq!(rtxn.first_with_prefix(b"user/13/").unwrap(), None);
+    assert_eq!(rtxn.last_with_prefix(b"user/13/").unwrap(), None);
+    assert_eq!(rtxn.last_with_prefix(b"v").unwrap(), None);
+
+    let total: u32 = (0..200).map(items).sum();
+    assert_eq!(rtxn.count_prefix(b"").unwrap(), total as u64);
+    assert_eq!(rtxn.last_with_prefix(b"").unwrap().unwrap().0, item_key(99, items(99) - 1).as_slice());
+    println!("   [OK] Prefix scans matched {} keys across 200 users", total);
+}
+
+#[test]
+fn test_prefix_with_trailing_ff() {
+    let db = Db::open_in_memory().unwrap();
+    {
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        for key in [&[0x01, 0xFE][..], &[0x01, 0xFF], &[0x01, 0xFF, 0x00], &[0x01, 0xFF, 0xFF], &[0x02], &[0xFF, 0xFF, 0x01]] {
+            wtxn.insert(key, b"v").unwrap();
+        }
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert_eq!(rtxn.count_prefix(&[0x01, 0xFF]).unwrap(), 3);
+    assert_eq!(rtxn.last_with_prefix(&[0x01, 0xFF]).unwrap().unwrap().0, &[0x01, 0xFF, 0xFF]);
+    assert_eq!(rtxn.last_with_prefix(&[0x01]).unwrap().unwrap().0, &[0x01, 0xFF, 0xFF]);
+    assert_eq!(rtxn.last_with_prefix(&[0xFF, 0xFF]).unwrap().unwrap().0, &[0xFF, 0xFF, 0x01]);
+    assert_eq!(rtxn.first_with_prefix(&[0xFF]).unwrap().unwrap().0, &[0xFF, 0xFF, 0x01]);
+    println!("   [OK] Prefixes ending in 0xFF found their last key");
+}
+
+#[test]
+fn test_cursor_walks_backwards() {
+    let db = Db::open_in_memory().unwrap();
+    {
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        for i in 0..2_000u32 {
+            wtxn.insert(format!("key_{:05}", i).as_bytes(), b"v").unwrap();
+        }
+        // leave some empty leaves behind
+        for i in 500..900u32 {
+            wtxn.delete(format!("key_{:05}", i).as_bytes()).unwrap();
+        }
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    let forward: Vec<_> = rtxn.iter().unwrap().map(|entry| entry.unwrap().0.to_vec()).collect();
+
+    let mut backward = Vec::new();
+    let mut cursor = rtxn.cursor();
+    let mut entry = cursor.last().unwrap();
+    while let Some((key, _)) = entry {
+        backward.push(key.to_vec());
+        entry = cursor.move_prev().unwrap();
+    }
+    backward.reverse();
+    assert_eq!(backward, forward);
+    assert_eq!(cursor.move_prev().unwrap(), None);
+    println!("   [OK] Walked {} keys backwards", backward.len());
+}
@@ -0,0 +1,87 @@
+use rbolt::db::Db;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+fn as_slices<'m>((k, v): (&'m Vec<u8>, &'m Vec<u8>)) -> (&'m [u8], &'m [u8]) {
+    (k.as_slice(), v.as_slice())
+}
+
+// Random inserts, then deletes that leave empty leaves behind
+fn build(db: &Db) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    let mut model = BTreeMap::new();
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    for i in 0..8_000u32 {
+        let i = (i * 7919) % 8_000;
+        let value = format!("value_{}", i).into_bytes();
+        wtxn.insert(&key(i), &value).unwrap();
+        model.insert(key(i), value);
+    }
+    for i in (2_000..3_000u32).chain((0..8_000).step_by(7)) {
+        wtxn.delete(&key(i)).unwrap();
+        model.remove(&key(i));
+    }
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    model
+}
+
+#[test]
+fn test_first_last_and_seek_for_prev() {
+    let db = Db::open_in_memory().unwrap();
+    {
+        let rtxn = db.begin_read_transaction().unwrap();
+        assert_eq!(rtxn.first().unwrap(), None);
+        assert_eq!(rtxn.last().unwrap(), None);
+        assert_eq!(rtxn.seek_for_prev(b"anything").unwrap(), None);
+    }
+    let model = build(&db);
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert_eq!(rtxn.first().unwrap(), model.iter().next().map(as_slices));
+    assert_eq!(rtxn.last().unwrap(), model.iter().next_back().map(as_slices));
+
+    let mut probes: Vec<Vec<u8>> = (0..8_100).step_by(13).map(key).collect();
+    probes.extend([b"a".to_vec(), b"key_".to_vec(), b"z".to_vec(), Vec::new()]);
+    for probe in &probes {
+        let expected = model.range::<Vec<u8>, _>(..=probe).next_back().map(as_slices);
+        assert_eq!(rtxn.seek_for_prev(probe).unwrap(), expected, "probe {:?}", probe);
+    }
+    println!("   [OK] seek_for_prev matched the model at {} probes", probes.len());
+}
+
+#[test]
+fn test_reverse_ranges_match_btreemap() {
+    let db = Db::open_in_memory().unwrap();
+    let model = build(&db);
+    let rtxn = db.begin_read_transaction().unwrap();
+
+    let bounds = [
+        (Bound::Unbounded, Bound::Unbounded),
+        (Bound::Included(key(100)), Bound::Excluded(key(2_500))),
+        (Bound::Excluded(key(1_001)), Bound::Included(key(7_000))),
+        (Bound::Included(key(2_100)), Bound::Included(key(2_900))),
+        (Bound::Unbounded, Bound::Excluded(key(7))),
+        (Bound::Included(key(7_990)), Bound::Unbounded),
+        (Bound::Included(key(7)), Bound::Included(key(7))),
+        (Bound::Excluded(key(500)), Bound::Excluded(key(501))),
+    ];
+    for (start, end) in &bounds {
+        let expected: Vec<_> = model.range::<Vec<u8>, _>((start.as_ref(), end.as_ref())).rev()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let range = (start.as_ref().map(Vec::as_slice), end.as_ref().map(Vec::as_slice));
+        let actual: Vec<_> = rtxn.range_rev(range).unwrap()
+            .map(|entry| entry.map(|(k, v)| (k.to_vec(), v.to_vec())).unwrap())
+            .collect();
+        assert_eq!(actual, expected, "range {:?}..{:?}", start, end);
+    }
+
+    // the latest ten entries
+    let latest: Vec<_> = rtxn.iter_rev().unwrap().take(10).map(|entry| entry.unwrap().0.to_vec()).collect();
+    assert_eq!(latest, model.keys().rev().take(10).cloned().collect::<Vec<_>>());
+    println!("   [OK] {} reverse ranges matched the model", bounds.len());
+}
@@ -0,0 +1,175 @@
+use rbolt::db::{Db, DbError, DbOptions, DEFAULT_PAGE_SIZE};
+use rbolt::salvage::salvage;
+use std::path::Path;
+use std::process::Command;
+
+mod common;
+use common::fresh;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+fn value(i: u32) -> Vec<u8> {
+    format!("value_{}", i).into_bytes()
+}
+
+// Writes keys 0..count and returns the root page id
+fn populate(db_path: &Path, count: u32) -> u64 {
+    let db = Db::open(db_path).unwrap();
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    for i in 0..count {
+        wtxn.insert(&key(i), &value(i)).unwrap();
+    }
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    root_page_id
+}
+
+fn flip_bit(db_path: &Path, page_id: u64) {
+    let mut bytes = std::fs::read(db_path).unwrap();
+    bytes[(page_id as usize + 1) * DEFAULT_PAGE_SIZE - 1] ^= 0x01;
+    std::fs::write(db_path, &bytes).unwrap();
+}
+
+fn contents(db_path: &Path) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let db = Db::open(db_path).unwrap();
+    let rtxn = db.begin_read_transaction().unwrap();
+    rtxn.iter().unwrap().map(|entry| {
+        let (key, value) = entry.unwrap();
+        (key.to_vec(), value.to_vec())
+    }).collect()
+}
+
+#[test]
+fn test_salvage_around_damaged_root() {
+    let src = &fresh("test_salvage_root_src.rdb");
+    let dst = &fresh("test_salvage_root_dst.rdb");
+    let root_page_id = populate(src, 3_000);
+    flip_bit(src, root_page_id);
+
+    let db = Db::open(src).unwrap();
+    assert!(matches!(db.begin_read_transaction().unwrap().get(&key(0)), Err(DbError::Corrupted { .. })));
+    drop(db);
+
+    // with the root gone every leaf is found by scanning
+    let report = salvage(src, dst, DbOptions::default()).unwrap();
+    println!("   [OK] {}", report);
+    assert_eq!(report.damaged_pages, vec![root_page_id]);
+    assert_eq!(report.reachable_leaves, 0);
+    assert_eq!(report.keys_recovered, 3_000);
+    assert_eq!(report.keys_from_unreachable, 3_000);
+    let expected: Vec<_> = (0..3_000).map(|i| (key(i), value(i))).collect();
+    assert_eq!(contents(dst), expected);
+    println!("   [OK] All 3000 keys recovered from under a damaged root");
+
+    std::fs::remove_file(src).unwrap();
+    std::fs::remove_file(dst).unwrap();
+}
+
+#[test]
+fn test_salvage_reports_lost_leaf() {
+    let src = &fresh("test_salvage_leaf_src.rdb");
+    let dst = &fresh("test_salvage_leaf_dst.rdb");
+    populate(src, 3_000);
+
+    // the first leaf after the root leaf created with the file
+    let bytes = std::fs::read(src).unwrap();
+    let leaf_id = (3..bytes.len() / DEFAULT_PAGE_SIZE)
+        .find(|&page_id| bytes[page_id * DEFAULT_PAGE_SIZE + 8] == 3)
+        .unwrap() as u64;
+    let on_leaf = u16::from_le_bytes(bytes[leaf_id as usize * DEFAULT_PAGE_SIZE + 10..][..2].try_into().unwrap()) as u64;
+    flip_bit(src, leaf_id);
+
+    let report = salvage(src, dst, DbOptions::default()).unwrap();
+    println!("   [OK] {}", report);
+    assert_eq!(report.damaged_pages, vec![leaf_id]);
+    assert_eq!(report.keys_recovered, 3_000 - on_leaf);
+    assert_eq!(report.keys_from_unreachable, 0);
+
+    // everything else survives intact
+    let recovered = contents(dst);
+    assert_eq!(recovered.len() as u64, 3_000 - on_leaf);
+    for (k, v) in &recovered {
+        let i: u32 = std::str::from_utf8(&k[4..]).unwrap().parse().unwrap();
+        assert_eq!(v, &value(i));
+    }
+    println!("   [OK] Lost the {} keys of page {}, kept the rest", on_leaf, leaf_id);
+
+    std::fs::remove_file(src).unwrap();
+    std::fs::remove_file(dst).unwrap();
+}
+
+#[test]
+fn test_salvage_skips_freed_pages() {
+    let src = &fresh("test_salvage_freed_src.rdb");
+    let dst = &fresh("test_salvage_freed_dst.rdb");
+    populate(src, 3_000);
+
+    let db = Db::open(src).unwrap();
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    let (start, end) = (key(500), key(2_500));
+    assert_eq!(wtxn.delete_range(start.as_slice()..end.as_slice()).unwrap(), 2_000);
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    drop(db);
+
+    // leaves emptied by the delete keep their old entries but sit on the free list
+    let report = salvage(src, dst, DbOptions::default()).unwrap();
+    println!("   [OK] {}", report);
+    assert!(report.damaged_pages.is_empty());
+    assert_eq!(report.unreachable_leaves, 0);
+    let expected: Vec<_> = (0..500).chain(2_500..3_000).map(|i| (key(i), value(i))).collect();
+    assert_eq!(contents(dst), expected);
+    println!("   [OK] Deleted keys stayed deleted");
+
+    // the destination is never overwritten
+    assert!(salvage(src, dst, DbOptions::default()).is_err());
+
+    std::fs::remove_file(src).unwrap();
+    std::fs::remove_file(dst).unwrap();
+}
+
+#[test]
+fn test_salvage_command() {
+    let src = &fresh("test_salvage_command_src.rdb");
+    let dst = &fresh("test_salvage_command_dst.rdb");
+    let root_page_id = populate(src, 1_000);
+    flip_bit(src, root_page_id);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rbolt"))
+        .args(["salvage", src.to_str().unwrap(), dst.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("1000 keys recovered"));
+    assert!(stdout.contains(&format!("damaged page {}", root_page_id)));
+    assert_eq!(contents(dst).len(), 1_000);
+    println!("   [OK] rbolt salvage recovered 1000 keys");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rbolt")).arg("salvage").output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+
+    // only the built in comparator can be named, and the usage says so
+    let again = &fresh("test_salvage_command_again.rdb");
+    let output = Command::new(env!("CARGO_BIN_EXE_rbolt"))
+        .args(["salvage", "--comparator", "rbolt.bytewise", src.to_str().unwrap(), again.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(contents(again).len(), 1_000);
+    let output = Command::new(env!("CARGO_BIN_EXE_rbolt"))
+        .args(["salvage", "--comparator", "reversed", src.to_str().unwrap(), dst.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("unknown comparator reversed"));
+    assert!(stderr.contains("through the library"));
+    println!("   [OK] rbolt salvage took rbolt.bytewise and refused other comparators");
+
+    std::fs::remove_file(src).unwrap();
+    std::fs::remove_file(dst).unwrap();
+    std::fs::remove_file(again).unwrap();
+}
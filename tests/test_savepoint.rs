@@ -0,0 +1,114 @@
+use rbolt::btree::BTreeError;
+use rbolt::db::Db;
+
+mod common;
+use common::fresh;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+#[test]
+fn test_rollback_to_savepoint() {
+    let db_path = &fresh("test_savepoint_rollback.rdb");
+    {
+        let db = Db::open(db_path).unwrap();
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        for i in 0..10 {
+            wtxn.insert(&key(i), b"before").unwrap();
+        }
+        let savepoint = wtxn.savepoint();
+
+        // enough inserts to split pages and grow the tree past the savepoint
+        for i in 10..2_000 {
+            wtxn.insert(&key(i), b"after").unwrap();
+        }
+        wtxn.insert(&key(3), b"overwritten").unwrap();
+        assert!(wtxn.delete(&key(5)).unwrap());
+
+        wtxn.rollback_to(&savepoint).unwrap();
+        assert_eq!(wtxn.get(&key(3)).unwrap(), Some(b"before".to_vec()));
+        assert_eq!(wtxn.get(&key(5)).unwrap(), Some(b"before".to_vec()));
+        assert_eq!(wtxn.get(&key(10)).unwrap(), None);
+
+        // the transaction carries on and commits normally
+        wtxn.insert(&key(100), b"kept").unwrap();
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    let db = Db::open(db_path).unwrap();
+    let rtxn = db.begin_read_transaction().unwrap();
+    let keys: Vec<_> = rtxn.iter().unwrap().map(|entry| entry.unwrap().0.to_vec()).collect();
+    let mut expected: Vec<_> = (0..10).map(key).collect();
+    expected.push(key(100));
+    assert_eq!(keys, expected);
+    assert_eq!(rtxn.stats().unwrap().depth, 1);
+    println!("   [OK] Rolled back 1990 inserts, an overwrite and a delete");
+
+    drop(rtxn);
+    drop(db);
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_stacked_savepoints() {
+    let db = Db::open_in_memory().unwrap();
+    let mut wtxn = db.begin_write_transaction().unwrap();
+
+    wtxn.insert(b"a", b"1").unwrap();
+    let first = wtxn.savepoint();
+    wtxn.insert(b"b", b"2").unwrap();
+    let second = wtxn.savepoint();
+    wtxn.insert(b"c", b"3").unwrap();
+
+    wtxn.rollback_to(&second).unwrap();
+    assert_eq!(wtxn.get(b"b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(wtxn.get(b"c").unwrap(), None);
+
+    // a savepoint can be rolled back to again
+    wtxn.insert(b"d", b"4").unwrap();
+    wtxn.rollback_to(&second).unwrap();
+    assert_eq!(wtxn.get(b"d").unwrap(), None);
+
+    wtxn.rollback_to(&first).unwrap();
+    assert_eq!(wtxn.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(wtxn.get(b"b").unwrap(), None);
+    println!("   [OK] Stacked savepoints rolled back innermost first");
+}
+
+#[test]
+fn test_nested_transaction() {
+    let db = Db::open_in_memory().unwrap();
+    let mut wtxn = db.begin_write_transaction().unwrap();
+
+    wtxn.insert(b"outer", b"1").unwrap();
+    let result: Result<(), BTreeError> = wtxn.nested(|txn| {
+        txn.insert(b"inner", b"2")?;
+        txn.insert(b"too_large", &vec![0u8; 70_000])
+    });
+    assert!(matches!(result, Err(BTreeError::ValueTooLarge { .. })));
+    assert_eq!(wtxn.get(b"inner").unwrap(), None);
+    assert_eq!(wtxn.get(b"outer").unwrap(), Some(b"1".to_vec()));
+
+    let count = wtxn.nested(|txn| -> Result<u32, BTreeError> {
+        txn.insert(b"inner", b"3")?;
+        Ok(1)
+    }).unwrap();
+    assert_eq!(count, 1);
+    assert_eq!(wtxn.get(b"inner").unwrap(), Some(b"3".to_vec()));
+    println!("   [OK] Failed nested transaction left the outer one intact");
+}
+
+#[test]
+fn test_savepoint_from_another_transaction() {
+    let db = Db::open_in_memory().unwrap();
+
+    let savepoint = {
+        let wtxn = db.begin_write_transaction().unwrap();
+        wtxn.savepoint()
+    };
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    assert!(matches!(wtxn.rollback_to(&savepoint), Err(BTreeError::ForeignSavepoint)));
+    println!("   [OK] Savepoint of another transaction was rejected");
+}
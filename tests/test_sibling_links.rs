@@ -0,0 +1,124 @@
+use rbolt::db::{Db, DbError, ReadTxn};
+
+mod common;
+use common::fresh;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+fn forward(rtxn: &ReadTxn<'_>, use_links: bool) -> Vec<Vec<u8>> {
+    let mut keys = Vec::new();
+    let mut cursor = rtxn.cursor();
+    cursor.use_sibling_links(use_links);
+    let mut entry = cursor.first().unwrap();
+    while let Some((key, _)) = entry {
+        keys.push(key.to_vec());
+        entry = cursor.move_next().unwrap();
+    }
+    keys
+}
+
+fn backward(rtxn: &ReadTxn<'_>, use_links: bool) -> Vec<Vec<u8>> {
+    let mut keys = Vec::new();
+    let mut cursor = rtxn.cursor();
+    cursor.use_sibling_links(use_links);
+    let mut entry = cursor.last().unwrap();
+    while let Some((key, _)) = entry {
+        keys.push(key.to_vec());
+        entry = cursor.move_prev().unwrap();
+    }
+    keys.reverse();
+    keys
+}
+
+// Walks the tree both ways, with and without the links, and checks all four agree with `expected`
+fn assert_scans(db: &Db, expected: &[Vec<u8>]) {
+    let rtxn = db.begin_read_transaction().unwrap();
+    assert_eq!(forward(&rtxn, true), expected);
+    assert_eq!(forward(&rtxn, false), expected);
+    assert_eq!(backward(&rtxn, true), expected);
+    assert_eq!(backward(&rtxn, false), expected);
+}
+
+#[test]
+fn test_links_follow_splits_and_deletes() {
+    let db = Db::open_in_memory().unwrap();
+
+    // random order splits leaves in the middle of the chain
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    for i in 0..5_000u32 {
+        let i = (i * 7919) % 5_000;
+        wtxn.insert(&key(i), format!("value_{}", i).as_bytes()).unwrap();
+    }
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    let mut expected: Vec<_> = (0..5_000).map(key).collect();
+    assert_scans(&db, &expected);
+    println!("   [OK] Links consistent after {} random inserts", expected.len());
+
+    // single deletes leave empty leaves in the chain, range deletes unlink freed ones
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    for i in 100..400u32 {
+        wtxn.delete(&key(i)).unwrap();
+    }
+    let (start, end) = (key(1_000), key(3_000));
+    wtxn.delete_range(start.as_slice()..end.as_slice()).unwrap();
+    wtxn.delete_range(key(4_990).as_slice()..).unwrap();
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    expected.retain(|k| !(key(100)..key(400)).contains(k) && !(&start..&end).contains(&k) && *k < key(4_990));
+    assert_scans(&db, &expected);
+    println!("   [OK] Links consistent after deletes, {} keys left", expected.len());
+
+    // freed pages are reused by the next splits
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    for i in 1_500..2_500u32 {
+        wtxn.insert(&key(i), b"again").unwrap();
+    }
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    expected.extend((1_500..2_500).map(key));
+    expected.sort();
+    assert_scans(&db, &expected);
+    println!("   [OK] Links consistent after reusing freed pages");
+}
+
+#[test]
+fn test_links_after_bulk_load() {
+    let db = Db::open_in_memory().unwrap();
+    db.bulk_load((0..20_000u32).map(|i| (key(i), vec![b'v'; 40])), 0.9).unwrap();
+    let expected: Vec<_> = (0..20_000).map(key).collect();
+    assert_scans(&db, &expected);
+
+    // a seek lands mid-chain and carries on through the links
+    let rtxn = db.begin_read_transaction().unwrap();
+    let mut cursor = rtxn.cursor();
+    assert_eq!(cursor.seek(&key(12_345)).unwrap().unwrap().0, key(12_345).as_slice());
+    for i in 12_346..12_600 {
+        assert_eq!(cursor.move_next().unwrap().unwrap().0, key(i).as_slice());
+    }
+    for i in (12_000..12_599).rev() {
+        assert_eq!(cursor.move_prev().unwrap().unwrap().0, key(i).as_slice());
+    }
+    println!("   [OK] Bulk loaded chain of {} keys scanned both ways", expected.len());
+}
+
+#[test]
+fn test_rejects_other_versions() {
+    let db_path = &fresh("test_sibling_links_version.rdb");
+    drop(Db::open(db_path).unwrap());
+
+    // files written before leaves carried links have version 1
+    let mut bytes = std::fs::read(db_path).unwrap();
+    bytes[4..8].copy_from_slice(&1u32.to_le_bytes());
+    std::fs::write(db_path, &bytes).unwrap();
+    match Db::open(db_path) {
+        Err(DbError::UnsupportedVersion { found: 1, .. }) => {}
+        Err(other) => panic!("expected UnsupportedVersion, got {}", other),
+        Ok(_) => panic!("expected UnsupportedVersion, the file opened"),
+    }
+    println!("   [OK] Version 1 file was rejected");
+
+    std::fs::remove_file(db_path).unwrap();
+}
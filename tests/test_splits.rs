@@ -1,13 +1,11 @@
 use rbolt::db::Db;
-use std::path::Path;
+
+mod common;
+use common::fresh;
 
 #[test]
 fn test_page_split() {
-    let db_path = Path::new("test_split.rdb");
-
-    if db_path.exists() {
-        std::fs::remove_file(db_path).unwrap();
-    }
+    let db_path = &fresh("test_split.rdb");
 
     {
         let db = Db::open(db_path).unwrap();
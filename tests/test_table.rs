@@ -0,0 +1,141 @@
+#![cfg(all(feature = "json", feature = "bincode", feature = "postcard"))]
+
+use rbolt::db::Db;
+use rbolt::table::{Bincode, Codec, Json, Postcard, Table, TableError};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct OrderKey {
+    customer: String,
+    order_id: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Order {
+    item: String,
+    quantity: u32,
+    price: f64,
+    note: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+enum Event {
+    Started,
+    Progress(u8),
+    Finished { code: i32 },
+}
+
+fn order(i: u64) -> Order {
+    Order {
+        item: format!("item_{}", i),
+        quantity: i as u32,
+        price: i as f64 * 1.5,
+        note: i.is_multiple_of(3).then(|| "fragile".to_string()),
+    }
+}
+
+fn roundtrip<C: Codec>() {
+    let orders: Table<OrderKey, Order, C> = Table::new("orders");
+    let db = Db::open_in_memory().unwrap();
+    {
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        for customer in ["carol", "alice", "bob"] {
+            for id in (0..40).rev() {
+                let key = OrderKey { customer: customer.to_string(), order_id: id };
+                orders.insert(&mut wtxn, &key, &order(id)).unwrap();
+            }
+        }
+        let key = OrderKey { customer: "bob".to_string(), order_id: 7 };
+        assert_eq!(orders.get(&wtxn, &key).unwrap(), Some(order(7)));
+        assert_eq!(orders.remove(&mut wtxn, &key).unwrap(), Some(order(7)));
+        assert_eq!(orders.remove(&mut wtxn, &key).unwrap(), None);
+
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    let key = OrderKey { customer: "alice".to_string(), order_id: 12 };
+    assert_eq!(orders.get(&rtxn, &key).unwrap(), Some(order(12)));
+
+    let all: Vec<_> = orders.iter(&rtxn).unwrap().map(|entry| entry.unwrap()).collect();
+    assert_eq!(all.len(), 119);
+    assert!(all.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+    let start = OrderKey { customer: "bob".to_string(), order_id: 0 };
+    let end = OrderKey { customer: "carol".to_string(), order_id: 0 };
+    let bobs: Vec<_> = orders.range(&rtxn, start..end).unwrap().map(|entry| entry.unwrap().0.order_id).collect();
+    assert_eq!(bobs, (0..40).filter(|&id| id != 7).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_json_table() {
+    roundtrip::<Json>();
+}
+
+#[test]
+fn test_bincode_table() {
+    roundtrip::<Bincode>();
+}
+
+#[test]
+fn test_postcard_table() {
+    roundtrip::<Postcard>();
+}
+
+#[test]
+fn test_tables_are_isolated_and_enum_keys_sort_by_variant() {
+    let events: Table<(i64, Event), String, Json> = Table::new("events");
+    let counters: Table<i64, u64, Json> = Table::new("counters");
+    let db = Db::open_in_memory().unwrap();
+    let keys = vec![
+        (-5, Event::Finished { code: -1 }),
+        (-5, Event::Started),
+        (3, Event::Progress(200)),
+        (3, Event::Progress(10)),
+        (3, Event::Started),
+        (3, Event::Finished { code: 0 }),
+    ];
+    {
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        for key in &keys {
+            events.insert(&mut wtxn, key, &format!("{:?}", key)).unwrap();
+        }
+        for i in -3..3 {
+            counters.insert(&mut wtxn, &i, &((i * i) as u64)).unwrap();
+        }
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    let mut expected = keys.clone();
+    expected.sort();
+    let stored: Vec<_> = events.iter(&rtxn).unwrap().map(|entry| entry.unwrap().0).collect();
+    assert_eq!(stored, expected);
+
+    let stored: Vec<_> = counters.iter(&rtxn).unwrap().map(|entry| entry.unwrap()).collect();
+    assert_eq!(stored, (-3..3).map(|i| (i, (i * i) as u64)).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_decode_failure_reports_key_and_codec() {
+    let as_text: Table<u64, String, Json> = Table::new("values");
+    let as_order: Table<u64, Order, Json> = Table::new("values");
+    let db = Db::open_in_memory().unwrap();
+    {
+        let mut wtxn = db.begin_write_transaction().unwrap();
+        as_text.insert(&mut wtxn, &1, &"not an order".to_string()).unwrap();
+        let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+        db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    }
+
+    let rtxn = db.begin_read_transaction().unwrap();
+    match as_order.get(&rtxn, &1) {
+        Err(err @ TableError::DecodeValue { codec: "json", .. }) => {
+            assert!(err.to_string().contains("json"));
+        }
+        other => panic!("expected a decode error, got {:?}", other),
+    }
+    assert!(matches!(as_order.iter(&rtxn).unwrap().next(), Some(Err(TableError::DecodeValue { .. }))));
+}
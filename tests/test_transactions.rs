@@ -1,14 +1,11 @@
 use rbolt::db::Db;
-use std::path::Path;
+
+mod common;
+use common::fresh;
 
 #[test]
 fn test_multiple_sequential_transactions() {
-    let db_path = Path::new("test_multi_txn.rdb");
-    if db_path.exists() {
-        std::fs::remove_file(db_path).unwrap();
-    }
-
-    let db = Db::open(db_path).unwrap();
+    let db = Db::open_in_memory().unwrap();
 
     {
         let mut wtxn = db.begin_write_transaction().unwrap();
@@ -51,16 +48,11 @@ fn test_multiple_sequential_transactions() {
             assert!(result.is_some(), "Key {} should exist", key);
         }
     }
-
-    std::fs::remove_file(db_path).unwrap();
 }
 
 #[test]
 fn test_persistence_across_reopens() {
-    let db_path = Path::new("test_persistence.rdb");
-    if db_path.exists() {
-        std::fs::remove_file(db_path).unwrap();
-    }
+    let db_path = &fresh("test_persistence.rdb");
 
     {
         let db = Db::open(db_path).unwrap();
@@ -121,12 +113,7 @@ fn test_persistence_across_reopens() {
 
 #[test]
 fn test_read_during_write_transaction_prep() {
-    let db_path = Path::new("test_concurrent.rdb");
-    if db_path.exists() {
-        std::fs::remove_file(db_path).unwrap();
-    }
-
-    let db = Db::open(db_path).unwrap();
+    let db = Db::open_in_memory().unwrap();
 
     {
         let mut wtxn = db.begin_write_transaction().unwrap();
@@ -144,6 +131,4 @@ fn test_read_during_write_transaction_prep() {
     let rtxn = db.begin_read_transaction().unwrap();
     assert_eq!(rtxn.get(b"key1").unwrap(), Some(b"value1".to_vec()));
     assert_eq!(rtxn.get(b"key2").unwrap(), Some(b"value2".to_vec()));
-
-    std::fs::remove_file(db_path).unwrap();
 }
@@ -0,0 +1,144 @@
+use rbolt::checksum::Checksum;
+use rbolt::db::{Db, DbError, DbOptions, HEADER_SIZE};
+use rbolt::btree::BTreeError;
+use rbolt::upgrade::{upgrade, upgrade_in_place};
+use std::path::Path;
+use std::process::Command;
+
+mod common;
+use common::fresh;
+
+// Files written by earlier versions of the crate: keys 0..1000, committed as 0..600 then 600..1000
+const FIXTURES: [(u32, &str); 1] = [
+    (1, "tests/golden/v1.rdb"),
+];
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+fn value(i: u32) -> Vec<u8> {
+    format!("value_{}", i).into_bytes()
+}
+
+fn assert_fixture_contents(db_path: &Path) {
+    let db = Db::open(db_path).unwrap();
+    let rtxn = db.begin_read_transaction().unwrap();
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = rtxn.iter().unwrap().map(|entry| {
+        let (key, value) = entry.unwrap();
+        (key.to_vec(), value.to_vec())
+    }).collect();
+    let expected: Vec<_> = (0..1_000).map(|i| (key(i), value(i))).collect();
+    assert_eq!(entries, expected);
+}
+
+#[test]
+fn test_upgrade_every_version_to_copy() {
+    for (version, fixture) in FIXTURES {
+        let dst = &fresh("test_upgrade_copy.rdb");
+
+        // the old file is refused until it's upgraded
+        match Db::open(Path::new(fixture)) {
+            Err(err @ DbError::UnsupportedVersion { .. }) => {
+                assert!(matches!(err, DbError::UnsupportedVersion { found, .. } if found == version));
+                assert!(err.to_string().contains("rbolt upgrade"));
+            }
+            Err(other) => panic!("expected UnsupportedVersion, got {}", other),
+            Ok(_) => panic!("version {} file opened without an upgrade", version),
+        }
+
+        let original = std::fs::read(fixture).unwrap();
+        let report = upgrade(Path::new(fixture), dst, DbOptions::default()).unwrap();
+        assert_eq!((report.from_version, report.to_version, report.keys), (version, 2, 1_000));
+        assert_eq!(std::fs::read(fixture).unwrap(), original);
+        assert_fixture_contents(dst);
+        println!("   [OK] {}", report);
+
+        std::fs::remove_file(dst).unwrap();
+    }
+}
+
+#[test]
+fn test_upgrade_in_place() {
+    let db_path = &fresh("test_upgrade_in_place.rdb");
+    std::fs::copy("tests/golden/v1.rdb", db_path).unwrap();
+
+    let report = upgrade_in_place(db_path, DbOptions::default()).unwrap();
+    assert_eq!((report.from_version, report.keys), (1, 1_000));
+    assert!(!db_path.with_extension("rdb.upgrade").exists());
+    assert_fixture_contents(db_path);
+
+    // the upgraded file takes new writes
+    let db = Db::open(db_path).unwrap();
+    let mut wtxn = db.begin_write_transaction().unwrap();
+    wtxn.insert(b"zebra", b"striped").unwrap();
+    let (dirty_pages, highest_page_id, root_page_id) = wtxn.prepare_commit();
+    db.commit(dirty_pages, highest_page_id, root_page_id).unwrap();
+    drop(db);
+
+    // a second upgrade has nothing to do
+    let before = std::fs::read(db_path).unwrap();
+    let report = upgrade_in_place(db_path, DbOptions::default()).unwrap();
+    assert_eq!((report.from_version, report.to_version, report.keys), (2, 2, 0));
+    assert_eq!(std::fs::read(db_path).unwrap(), before);
+    println!("   [OK] Version 1 file upgraded in place and left alone the second time");
+
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_rejects_other_page_sizes() {
+    let db_path = &fresh("test_upgrade_page_size.rdb");
+    drop(Db::open(db_path).unwrap());
+
+    // a page size that isn't a power of two, in a header that still checks out
+    let mut bytes = std::fs::read(db_path).unwrap();
+    bytes[8..12].copy_from_slice(&12288u32.to_le_bytes());
+    let mut checksum = Checksum::new();
+    checksum.update(&bytes[..HEADER_SIZE - 8]);
+    bytes[HEADER_SIZE - 8..HEADER_SIZE].copy_from_slice(&checksum.finish().to_le_bytes());
+    std::fs::write(db_path, &bytes).unwrap();
+    match Db::open(db_path) {
+        Err(DbError::UnsupportedPageSize { found: 12288, .. }) => {}
+        Err(other) => panic!("expected UnsupportedPageSize, got {}", other),
+        Ok(_) => panic!("expected UnsupportedPageSize, the file opened"),
+    }
+
+    // old files are checked the same way before they're upgraded
+    let mut bytes = std::fs::read("tests/golden/v1.rdb").unwrap();
+    bytes[8..12].copy_from_slice(&512u32.to_le_bytes());
+    std::fs::write(db_path, &bytes).unwrap();
+    assert!(matches!(
+        upgrade_in_place(db_path, DbOptions::default()),
+        Err(BTreeError::Db(DbError::UnsupportedPageSize { found: 512, .. }))
+    ));
+    println!("   [OK] Files with other page sizes were rejected");
+
+    std::fs::remove_file(db_path).unwrap();
+}
+
+#[test]
+fn test_upgrade_command() {
+    let db_path = &fresh("test_upgrade_command.rdb");
+    let dst = &fresh("test_upgrade_command_dst.rdb");
+    std::fs::copy("tests/golden/v1.rdb", db_path).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rbolt"))
+        .args(["upgrade", db_path.to_str().unwrap(), dst.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().contains("upgraded version 1 to version 2, 1000 keys copied"));
+    assert_fixture_contents(dst);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rbolt"))
+        .args(["upgrade", db_path.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_fixture_contents(db_path);
+    println!("   [OK] rbolt upgrade converted a version 1 file to a copy and in place");
+
+    std::fs::remove_file(db_path).unwrap();
+    std::fs::remove_file(dst).unwrap();
+}